@@ -5,6 +5,7 @@ use clap::{value_t, App, Arg, SubCommand};
 
 mod election_post;
 mod hash_fns;
+mod preprocessing;
 mod stacked;
 
 fn main() {
@@ -102,6 +103,55 @@ fn main() {
                     Arg::with_name("extract")
                         .long("extract")
                         .help("Extract data after proving and verifying.")
+                )
+                .arg(
+                    Arg::with_name("data-file")
+                        .long("data-file")
+                        .help("Use the contents of the given file as sector data, instead of zeroes. The data must already be preprocessed (bit-padded).")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("flamegraph")
+                        .long("flamegraph")
+                        .help("Write a flamegraph of replication and proving to the given path (requires the `flamegraph` feature).")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("parallel-verify")
+                        .long("parallel-verify")
+                        .help("Verify partitions concurrently.")
+                )
+                .arg(
+                    Arg::with_name("replication-samples")
+                        .long("replication-samples")
+                        .help("Replicate this many times, each with fresh data and replica id, to report the variance of the replication time.")
+                        .default_value("1")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("keep-replica")
+                        .long("keep-replica")
+                        .help("Keep the replica, its trees and the serialized aux in the given directory, instead of a temporary one.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("load-proofs")
+                        .long("load-proofs")
+                        .help("Skip replication and vanilla proving, and generate Groth proofs from the vanilla proofs written by --dump to the given file.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("assert-thresholds")
+                        .long("assert-thresholds")
+                        .help("Fail if any output exceeds its maximum value in the given JSON file.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("Report format. Available: \"json\", \"csv\" (default \"json\")")
+                        .default_value("json")
+                        .takes_value(true)
                 );
 
     let election_post_cmd = SubCommand::with_name("election-post")
@@ -114,6 +164,16 @@ fn main() {
                 .takes_value(true),
         );
 
+    let preprocessing_cmd = SubCommand::with_name("preprocessing")
+        .about("Benchmark fr32 preprocessing")
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .required(true)
+                .help("The data size in KiB")
+                .takes_value(true),
+        );
+
     let hash_cmd = SubCommand::with_name("hash-constraints")
         .about("Benchmark hash function inside of a circuit");
 
@@ -121,6 +181,7 @@ fn main() {
         .version("0.1")
         .subcommand(stacked_cmd)
         .subcommand(election_post_cmd)
+        .subcommand(preprocessing_cmd)
         .subcommand(hash_cmd)
         .get_matches();
 
@@ -134,6 +195,13 @@ fn main() {
                     let window_size_nodes = window_size_bytes / 32;
 
                     stacked::run(stacked::RunOpts {
+                        assert_thresholds: m.value_of("assert-thresholds").map(Into::into),
+                        data_file: m.value_of("data-file").map(Into::into),
+                        flamegraph: m.value_of("flamegraph").map(Into::into),
+                        parallel_verify: m.is_present("parallel-verify"),
+                        replication_samples: value_t!(m, "replication-samples", usize)?,
+                        keep_replica: m.value_of("keep-replica").map(Into::into),
+                        load_proofs: m.value_of("load-proofs").map(Into::into),
                         bench: m.is_present("bench"),
                         bench_only: m.is_present("bench-only"),
                         window_size_nodes,
@@ -147,6 +215,7 @@ fn main() {
                         layers,
                         no_bench: m.is_present("no-bench"),
                         no_tmp: m.is_present("no-tmp"),
+                        output: value_t!(m, "output", String)?,
                         partitions: value_t!(m, "partitions", usize)?,
                         size: value_t!(m, "size", usize)?,
                     })
@@ -159,6 +228,11 @@ fn main() {
             let sector_size = sector_size_kibs * 1024;
             election_post::run(sector_size).expect("election-post failed");
         }
+        ("preprocessing", Some(m)) => {
+            let size_kibs = value_t!(m, "size", usize)
+                .expect("could not convert `size` CLI argument to `usize`");
+            preprocessing::run(size_kibs).expect("preprocessing failed");
+        }
         ("hash-constraints", Some(_m)) => {
             hash_fns::run().expect("hash-constraints failed");
         }
@@ -0,0 +1,93 @@
+use std::io::{stdout, Cursor};
+
+use fil_proofs_tooling::{measure, Metadata};
+use filecoin_proofs::fr32::write_padded;
+use log::info;
+use rand::RngCore;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Inputs {
+    data_size: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Outputs {
+    padding_wall_time_ms: u64,
+    padding_cpu_time_ms: u64,
+    ns_per_byte: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Report {
+    inputs: Inputs,
+    outputs: Outputs,
+}
+
+impl Report {
+    /// Print all results to stdout
+    pub fn print(&self) {
+        let wrapped = Metadata::wrap(&self).expect("failed to retrieve metadata");
+        serde_json::to_writer(stdout(), &wrapped).expect("cannot write report JSON to stdout");
+    }
+}
+
+pub fn run(size_kib: usize) -> anyhow::Result<()> {
+    let data_size = size_kib * 1024;
+    info!("Benchy Preprocessing: data-size={}", data_size);
+
+    let mut data = vec![0u8; data_size];
+    rand::thread_rng().fill_bytes(&mut data);
+
+    let (report, _) = measure_preprocessing(&data)?;
+    report.print();
+
+    Ok(())
+}
+
+/// Time the fr32 padding of `data`, returning the report along with the padded data. The padded
+/// output is written to memory allocated up front, so that only the padding itself is measured.
+fn measure_preprocessing(data: &[u8]) -> anyhow::Result<(Report, Vec<u8>)> {
+    let data_size = data.len();
+    let mut padded = Cursor::new(Vec::with_capacity(data_size / 127 * 128 + 128));
+    let measurement = measure(|| Ok(write_padded(data, &mut padded)?))?;
+    let written = measurement.return_value;
+    anyhow::ensure!(
+        written == data_size,
+        "padded {} bytes, expected {}",
+        written,
+        data_size
+    );
+
+    let report = Report {
+        inputs: Inputs { data_size },
+        outputs: Outputs {
+            padding_wall_time_ms: measurement.wall_time.as_millis() as u64,
+            padding_cpu_time_ms: measurement.cpu_time.as_millis() as u64,
+            ns_per_byte: measurement.wall_time.as_nanos() as f64 / data_size as f64,
+        },
+    };
+
+    Ok((report, padded.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preprocessing_is_consistent() {
+        let mut data = vec![0u8; 8 * 1024 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+
+        let (a, padded_a) = measure_preprocessing(&data).unwrap();
+        let (b, padded_b) = measure_preprocessing(&data).unwrap();
+
+        assert_eq!(a.inputs.data_size, data.len());
+        assert_eq!(b.inputs.data_size, data.len());
+        assert!(padded_a == padded_b, "padding the same data differs");
+        assert!(a.outputs.ns_per_byte > 0.0 && b.outputs.ns_per_byte > 0.0);
+    }
+}
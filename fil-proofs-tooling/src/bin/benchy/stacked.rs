@@ -1,18 +1,23 @@
-use std::fs::{File, OpenOptions};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::time::Duration;
-use std::{io, u32};
+use std::{cmp, io, u32};
 
-use anyhow::bail;
+use anyhow::{bail, ensure, Context};
 use bellperson::Circuit;
 use chrono::Utc;
-use log::info;
+use log::{info, warn};
 use memmap::MmapMut;
 use memmap::MmapOptions;
 use merkletree::store::{StoreConfig, DEFAULT_CACHED_ABOVE_BASE_LAYER};
 use paired::bls12_381::Bls12;
 use rand::Rng;
+use rayon::prelude::*;
 
-use fil_proofs_tooling::{measure, FuncMeasurement, Metadata};
+use fil_proofs_tooling::{measure, peak_resident_bytes, FuncMeasurement, Metadata};
+use filecoin_proofs::types::{sector_nodes, SectorSize};
 use storage_proofs::circuit::metric::MetricCS;
 use storage_proofs::circuit::stacked::StackedCompound;
 use storage_proofs::compound_proof::{self, CompoundProof};
@@ -23,7 +28,10 @@ use storage_proofs::proof::ProofScheme;
 use storage_proofs::stacked::{
     self, CacheKey, ChallengeRequirements, StackedConfig, StackedDrg, TemporaryAuxCache, EXP_DEGREE,
 };
-use tempfile::TempDir;
+use storage_proofs::util::NODE_SIZE;
+
+/// Configs with a lower estimated soundness are benchmarked, but with a warning.
+const SOUNDNESS_WARNING_BITS: f64 = 80.0;
 
 fn file_backed_mmap_from_zeroes(n: usize, use_tmp: bool) -> anyhow::Result<MmapMut> {
     let file: File = if use_tmp {
@@ -44,20 +52,219 @@ fn file_backed_mmap_from_zeroes(n: usize, use_tmp: bool) -> anyhow::Result<MmapM
     Ok(map)
 }
 
+/// Copy the contents of the file at `path` into a file backed mmap of `n` nodes. Files smaller than
+/// the sector are padded with zeroes, larger ones are truncated.
+fn file_backed_mmap_from_file(n: usize, use_tmp: bool, path: &str) -> anyhow::Result<MmapMut> {
+    let mut map = file_backed_mmap_from_zeroes(n, use_tmp)?;
+
+    let mut source = File::open(path).with_context(|| format!("could not open {}", path))?;
+    let source_len = source.metadata()?.len() as usize;
+
+    if source_len < map.len() {
+        warn!(
+            "{} is smaller than the sector ({} < {} bytes), padding with zeroes",
+            path,
+            source_len,
+            map.len()
+        );
+    } else if source_len > map.len() {
+        warn!(
+            "{} is larger than the sector ({} > {} bytes), truncating",
+            path,
+            source_len,
+            map.len()
+        );
+    }
+
+    let len = cmp::min(source_len, map.len());
+    source.read_exact(&mut map[..len])?;
+
+    Ok(map)
+}
+
+/// Verify all partition proofs, concurrently if `parallel` is set. The result is only true if all
+/// partitions are valid.
+fn verify_partitions<H: 'static + Hasher>(
+    pp: &stacked::PublicParams<H>,
+    pub_inputs: &stacked::PublicInputs<H::Domain, <Sha256Hasher as Hasher>::Domain>,
+    all_partition_proofs: &[stacked::Proof<H, Sha256Hasher>],
+    parallel: bool,
+) -> anyhow::Result<bool> {
+    if !parallel {
+        return StackedDrg::<H, Sha256Hasher>::verify_all_partitions(
+            pp,
+            pub_inputs,
+            all_partition_proofs,
+        );
+    }
+
+    let verified = all_partition_proofs
+        .par_iter()
+        .enumerate()
+        .map(|(k, proof)| {
+            let partition_pub_inputs =
+                StackedDrg::<H, Sha256Hasher>::with_partition(pub_inputs.clone(), Some(k));
+            StackedDrg::<H, Sha256Hasher>::verify(pp, &partition_pub_inputs, proof)
+        })
+        .collect::<anyhow::Result<Vec<bool>>>()?;
+
+    Ok(verified.into_iter().all(|valid| valid))
+}
+
+/// Name of the replica file written to the cache directory with `--keep-replica`.
+const REPLICA_FILE: &str = "replica";
+
+/// Serialize `p_aux` and `t_aux` into `dir`, named like the aux files of `seal_pre_commit`.
+fn write_aux<H: Hasher>(
+    dir: &Path,
+    p_aux: &stacked::PersistentAux<H::Domain>,
+    t_aux: &stacked::TemporaryAux<H, Sha256Hasher>,
+) -> anyhow::Result<()> {
+    let p_aux_path = dir.join(CacheKey::PAux.to_string());
+    File::create(&p_aux_path)?.write_all(&bincode::serialize(p_aux)?)?;
+    info!("kept p_aux at {}", p_aux_path.display());
+
+    let t_aux_path = dir.join(CacheKey::TAux.to_string());
+    File::create(&t_aux_path)?.write_all(&bincode::serialize(t_aux)?)?;
+    info!("kept t_aux at {}", t_aux_path.display());
+
+    Ok(())
+}
+
+/// Everything needed to generate circuit proofs for dumped vanilla proofs without replicating,
+/// along with the parameters they were generated for.
+#[derive(Serialize, Deserialize)]
+struct DumpedProofs<H: Hasher> {
+    sector_size: usize,
+    layers: usize,
+    window_size_nodes: usize,
+    window_challenges: usize,
+    wrapper_challenges: usize,
+    hasher: String,
+    graph_seed: [u8; 28],
+    replica_id: H::Domain,
+    seed: [u8; 32],
+    comm_d: <Sha256Hasher as Hasher>::Domain,
+    comm_r: H::Domain,
+    #[serde(bound(
+        serialize = "stacked::Proof<H, Sha256Hasher>: serde::Serialize",
+        deserialize = "stacked::Proof<H, Sha256Hasher>: serde::Deserialize<'de>"
+    ))]
+    proofs: Vec<stacked::Proof<H, Sha256Hasher>>,
+}
+
+impl<H: Hasher> DumpedProofs<H> {
+    fn public_inputs(&self) -> stacked::PublicInputs<H::Domain, <Sha256Hasher as Hasher>::Domain> {
+        stacked::public_inputs_for::<H, Sha256Hasher>(
+            self.replica_id,
+            stacked::Tau {
+                comm_d: self.comm_d,
+                comm_r: self.comm_r,
+            },
+            self.seed,
+            0,
+        )
+    }
+}
+
 fn dump_proof_bytes<H: Hasher>(
+    params: &Params,
+    graph_seed: [u8; 28],
+    pub_inputs: &stacked::PublicInputs<H::Domain, <Sha256Hasher as Hasher>::Domain>,
     all_partition_proofs: &[stacked::Proof<H, Sha256Hasher>],
 ) -> anyhow::Result<()> {
+    let tau = pub_inputs.tau.as_ref().expect("missing tau");
+    let dumped = DumpedProofs::<H> {
+        sector_size: params.data_size,
+        layers: params.config.layers(),
+        window_size_nodes: params.window_size_nodes,
+        window_challenges: params.config.window_challenges.challenges_count_all(),
+        wrapper_challenges: params.config.wrapper_challenges.challenges_count_all(),
+        hasher: params.hasher.clone(),
+        graph_seed,
+        replica_id: pub_inputs.replica_id,
+        seed: pub_inputs.seed,
+        comm_d: tau.comm_d,
+        comm_r: tau.comm_r,
+        proofs: all_partition_proofs.to_vec(),
+    };
+
     let file = OpenOptions::new()
         .write(true)
         .create(true)
         .open(format!("./proofs-{:?}", Utc::now()))
         .unwrap();
 
-    serde_json::to_writer(file, all_partition_proofs)?;
+    serde_json::to_writer(file, &dumped)?;
 
     Ok(())
 }
 
+/// Read proofs written by `--dump`, failing if they were generated with different parameters
+/// than `params`.
+fn load_proof_bytes<H: Hasher>(path: &str, params: &Params) -> anyhow::Result<DumpedProofs<H>> {
+    let file = File::open(path).with_context(|| format!("could not open {}", path))?;
+    let dumped: DumpedProofs<H> = serde_json::from_reader(file)
+        .with_context(|| format!("could not parse proofs from {}", path))?;
+
+    ensure!(
+        dumped.hasher == params.hasher,
+        "{} contains proofs using the {} hasher, but --hasher is {}",
+        path,
+        dumped.hasher,
+        params.hasher
+    );
+    ensure!(
+        dumped.sector_size == params.data_size,
+        "{} contains proofs for a sector of {} KiB, but --size is {}",
+        path,
+        dumped.sector_size / 1024,
+        params.data_size / 1024
+    );
+    ensure!(
+        dumped.layers == params.config.layers(),
+        "{} contains proofs for {} layers, but --layers is {}",
+        path,
+        dumped.layers,
+        params.config.layers()
+    );
+    ensure!(
+        dumped.window_size_nodes == params.window_size_nodes,
+        "{} contains proofs for windows of {} bytes, but --window-size is {}",
+        path,
+        dumped.window_size_nodes * NODE_SIZE,
+        params.window_size_nodes * NODE_SIZE
+    );
+
+    let window_challenges = params.config.window_challenges.challenges_count_all();
+    ensure!(
+        dumped.window_challenges == window_challenges,
+        "{} contains proofs for {} window challenges, but --window-challenges is {}",
+        path,
+        dumped.window_challenges,
+        window_challenges
+    );
+
+    let wrapper_challenges = params.config.wrapper_challenges.challenges_count_all();
+    ensure!(
+        dumped.wrapper_challenges == wrapper_challenges,
+        "{} contains proofs for {} wrapper challenges, but --wrapper-challenges is {}",
+        path,
+        dumped.wrapper_challenges,
+        wrapper_challenges
+    );
+
+    ensure!(
+        dumped.proofs.len() == params.partitions,
+        "{} contains proofs for {} partitions, but --partitions is {}",
+        path,
+        dumped.proofs.len(),
+        params.partitions
+    );
+
+    Ok(dumped)
+}
+
 #[derive(Clone, Debug)]
 struct Params {
     samples: usize,
@@ -73,6 +280,12 @@ struct Params {
     dump_proofs: bool,
     bench_only: bool,
     hasher: String,
+    flamegraph: Option<String>,
+    data_file: Option<String>,
+    parallel_verify: bool,
+    replication_samples: usize,
+    keep_replica: bool,
+    load_proofs: Option<String>,
 }
 
 impl From<Params> for Inputs {
@@ -82,18 +295,25 @@ impl From<Params> for Inputs {
             partitions: p.partitions,
             hasher: p.hasher.clone(),
             samples: p.samples,
+            replication_samples: p.replication_samples,
             layers: p.config.layers(),
             partition_challenges: p.config.window_challenges.challenges_count_all(),
             total_challenges: p.config.window_challenges.challenges_count_all() * p.partitions,
             config: p.config,
+            data_source: p.data_file.unwrap_or_else(|| "zeroes".into()),
         }
     }
 }
 
-fn generate_report<H: 'static>(params: Params, cache_dir: &TempDir) -> anyhow::Result<Report>
+fn generate_report<H: 'static>(params: Params, cache_dir: &Path) -> anyhow::Result<Report>
 where
     H: Hasher,
 {
+    ensure!(
+        params.replication_samples > 0,
+        "replication samples must be at least 1"
+    );
+
     let FuncMeasurement {
         cpu_time: total_cpu_time,
         wall_time: total_wall_time,
@@ -117,13 +337,24 @@ where
             dump_proofs,
             bench_only,
             window_size_nodes,
+            flamegraph,
+            data_file,
+            parallel_verify,
+            replication_samples,
+            keep_replica,
+            load_proofs,
             ..
         } = &params;
 
+        let loaded = match load_proofs {
+            Some(path) => Some(load_proof_bytes::<H>(path, &params)?),
+            None => None,
+        };
+
         // MT for original data is always named tree-d, and it will be
         // referenced later in the process as such.
         let store_config = StoreConfig::new(
-            cache_dir.path(),
+            cache_dir,
             CacheKey::CommDTree.to_string(),
             DEFAULT_CACHED_ABOVE_BASE_LAYER,
         );
@@ -132,14 +363,17 @@ where
         let mut total_proving_cpu_time = Duration::new(0, 0);
 
         let rng = &mut rand::thread_rng();
-        let nodes = data_size / 32;
+        let nodes = sector_nodes(SectorSize(*data_size as u64));
 
         let replica_id = H::Domain::random(rng);
         let sp = stacked::SetupParams {
             nodes,
             degree: BASE_DEGREE,
             expansion_degree: EXP_DEGREE,
-            seed: new_seed(),
+            seed: loaded
+                .as_ref()
+                .map(|loaded| loaded.graph_seed)
+                .unwrap_or_else(new_seed),
             config: config.clone(),
             window_size_nodes: *window_size_nodes,
         };
@@ -148,137 +382,197 @@ where
 
         let (pub_in, priv_in, d) = if *bench_only {
             (None, None, None)
+        } else if let Some(loaded) = &loaded {
+            info!("skipping replication and vanilla proving, using loaded proofs");
+            (Some(loaded.public_inputs()), None, None)
         } else {
-            let mut data = file_backed_mmap_from_zeroes(nodes, *use_tmp)?;
-            let seed = rng.gen();
-
-            let FuncMeasurement {
-                cpu_time: replication_cpu_time,
-                wall_time: replication_wall_time,
-                return_value: (pub_inputs, priv_inputs),
-            } = measure(|| {
-                let (tau, (p_aux, t_aux)) = StackedDrg::<H, Sha256Hasher>::replicate(
-                    &pp,
-                    &replica_id,
-                    &mut data,
-                    None,
-                    Some(store_config.clone()),
-                )?;
-
-                let pb = stacked::PublicInputs::<H::Domain, <Sha256Hasher as Hasher>::Domain> {
-                    replica_id,
-                    seed,
-                    tau: Some(tau),
-                    k: Some(0),
-                };
-
-                // Convert TemporaryAux to TemporaryAuxCache, which instantiates all
-                // elements based on the configs stored in TemporaryAux.
-                let t_aux =
-                    TemporaryAuxCache::new(&t_aux).expect("failed to restore contents of t_aux");
-
-                let pv = stacked::PrivateInputs { p_aux, t_aux };
-
-                Ok((pb, pv))
-            })?;
-
-            let avg_duration = |duration: Duration, data_size: &usize| {
-                if *data_size > (u32::MAX as usize) {
-                    // Duration only supports division by u32, so if data_size (of type usize) is larger,
-                    // we have to jump through some hoops to get the value we want, which is duration / size.
-                    // Consider: x = size / max
-                    //           y = duration / x = duration * max / size
-                    //           y / max = duration * max / size * max = duration / size
-                    let x = *data_size as f64 / f64::from(u32::MAX);
-                    let y = duration / x as u32;
-                    y / u32::MAX
-                } else {
-                    duration / (*data_size as u32)
-                }
+            // Replication encodes the data in place, so every sample starts from a new copy.
+            let fresh_data = || match data_file {
+                Some(path) => file_backed_mmap_from_file(nodes, *use_tmp, path),
+                None => file_backed_mmap_from_zeroes(nodes, *use_tmp),
             };
 
-            report.outputs.replication_wall_time_ms =
-                Some(replication_wall_time.as_millis() as u64);
-            report.outputs.replication_cpu_time_ms = Some(replication_cpu_time.as_millis() as u64);
-
-            report.outputs.replication_wall_time_ns_per_byte =
-                Some(avg_duration(replication_wall_time, data_size).as_nanos() as u64);
-            report.outputs.replication_cpu_time_ns_per_byte =
-                Some(avg_duration(replication_cpu_time, data_size).as_nanos() as u64);
+            profile(flamegraph.as_ref().map(String::as_str), || {
+                let mut data = fresh_data()?;
+                let seed = rng.gen();
+
+                let FuncMeasurement {
+                    cpu_time: replication_cpu_time,
+                    wall_time: replication_wall_time,
+                    return_value: (pub_inputs, priv_inputs, t_aux, tree_build_timings),
+                } = measure(|| {
+                    let (tau, (p_aux, t_aux), tree_build_timings) =
+                        StackedDrg::<H, Sha256Hasher>::replicate_with_timings(
+                            &pp,
+                            &replica_id,
+                            &mut data,
+                            None,
+                            Some(store_config.clone()),
+                        )?;
+
+                    let pb =
+                        stacked::public_inputs_for::<H, Sha256Hasher>(replica_id, tau, seed, 0);
+
+                    // Convert TemporaryAux to TemporaryAuxCache, which instantiates all
+                    // elements based on the configs stored in TemporaryAux.
+                    let t_aux_cache = TemporaryAuxCache::new(&t_aux)
+                        .expect("failed to restore contents of t_aux");
+
+                    let pv = stacked::PrivateInputs {
+                        p_aux,
+                        t_aux: t_aux_cache,
+                    };
+
+                    Ok((pb, pv, t_aux, tree_build_timings))
+                })?;
 
-            let FuncMeasurement {
-                cpu_time: vanilla_proving_cpu_time,
-                wall_time: vanilla_proving_wall_time,
-                return_value: all_partition_proofs,
-            } = measure(|| {
-                StackedDrg::<H, Sha256Hasher>::prove_all_partitions(
-                    &pp,
-                    &pub_inputs,
-                    &priv_inputs,
-                    *partitions,
-                )
-            })?;
+                let avg_duration = |duration: Duration, data_size: &usize| {
+                    if *data_size > (u32::MAX as usize) {
+                        // Duration only supports division by u32, so if data_size (of type usize) is larger,
+                        // we have to jump through some hoops to get the value we want, which is duration / size.
+                        // Consider: x = size / max
+                        //           y = duration / x = duration * max / size
+                        //           y / max = duration * max / size * max = duration / size
+                        let x = *data_size as f64 / f64::from(u32::MAX);
+                        let y = duration / x as u32;
+                        y / u32::MAX
+                    } else {
+                        duration / (*data_size as u32)
+                    }
+                };
 
-            report.outputs.vanilla_proving_wall_time_us =
-                Some(vanilla_proving_wall_time.as_micros() as u64);
-            report.outputs.vanilla_proving_cpu_time_us =
-                Some(vanilla_proving_cpu_time.as_micros() as u64);
+                report.outputs.replication_wall_time_ms =
+                    Some(replication_wall_time.as_millis() as u64);
+                report.outputs.replication_cpu_time_ms =
+                    Some(replication_cpu_time.as_millis() as u64);
 
-            total_proving_wall_time += vanilla_proving_wall_time;
-            total_proving_cpu_time += vanilla_proving_cpu_time;
+                report.outputs.tree_d_build_ms = Some(tree_build_timings.tree_d.as_millis() as u64);
+                report.outputs.tree_c_build_ms = Some(tree_build_timings.tree_c.as_millis() as u64);
+                report.outputs.tree_r_last_build_ms =
+                    Some(tree_build_timings.tree_r_last.as_millis() as u64);
 
-            if *dump_proofs {
-                dump_proof_bytes(&all_partition_proofs)?;
-            }
+                if *keep_replica {
+                    let replica_path = cache_dir.join(REPLICA_FILE);
+                    File::create(&replica_path)?.write_all(&data)?;
+                    info!("kept replica at {}", replica_path.display());
 
-            let mut total_verification_time = FuncMeasurement {
-                cpu_time: Duration::new(0, 0),
-                wall_time: Duration::new(0, 0),
-                return_value: (),
-            };
+                    write_aux(cache_dir, &priv_inputs.p_aux, &t_aux)?;
+                }
 
-            for _ in 0..*samples {
-                let m = measure(|| {
-                    let verified = StackedDrg::<H, Sha256Hasher>::verify_all_partitions(
+                // Additional replications of fresh data under fresh replica ids, only timed.
+                let mut replication_wall_times = vec![replication_wall_time];
+                for _ in 1..*replication_samples {
+                    let sample_cache_dir = tempfile::tempdir()?;
+                    let sample_store_config = StoreConfig::new(
+                        sample_cache_dir.path(),
+                        CacheKey::CommDTree.to_string(),
+                        DEFAULT_CACHED_ABOVE_BASE_LAYER,
+                    );
+                    let mut sample_data = fresh_data()?;
+                    let sample_replica_id = H::Domain::random(rng);
+
+                    let m = measure(|| {
+                        StackedDrg::<H, Sha256Hasher>::replicate(
+                            &pp,
+                            &sample_replica_id,
+                            &mut sample_data,
+                            None,
+                            Some(sample_store_config),
+                        )
+                    })?;
+                    replication_wall_times.push(m.wall_time);
+                }
+                report
+                    .outputs
+                    .set_replication_wall_time_stats(&replication_wall_times);
+
+                report.outputs.replication_wall_time_ns_per_byte =
+                    Some(avg_duration(replication_wall_time, data_size).as_nanos() as u64);
+                report.outputs.replication_cpu_time_ns_per_byte =
+                    Some(avg_duration(replication_cpu_time, data_size).as_nanos() as u64);
+
+                let FuncMeasurement {
+                    cpu_time: vanilla_proving_cpu_time,
+                    wall_time: vanilla_proving_wall_time,
+                    return_value: all_partition_proofs,
+                } = measure(|| {
+                    StackedDrg::<H, Sha256Hasher>::prove_all_partitions(
                         &pp,
                         &pub_inputs,
-                        &all_partition_proofs,
-                    )?;
+                        &priv_inputs,
+                        *partitions,
+                    )
+                })?;
 
-                    if !verified {
-                        panic!("verification failed");
-                    }
+                report.outputs.vanilla_proving_wall_time_us =
+                    Some(vanilla_proving_wall_time.as_micros() as u64);
+                report.outputs.vanilla_proving_cpu_time_us =
+                    Some(vanilla_proving_cpu_time.as_micros() as u64);
 
-                    Ok(())
-                })?;
+                total_proving_wall_time += vanilla_proving_wall_time;
+                total_proving_cpu_time += vanilla_proving_cpu_time;
 
-                total_verification_time.cpu_time += m.cpu_time;
-                total_verification_time.wall_time += m.wall_time;
+                if *dump_proofs {
+                    dump_proof_bytes::<H>(&params, sp.seed, &pub_inputs, &all_partition_proofs)?;
+                }
 
-                report.outputs.vanilla_verification_wall_time_us =
-                    Some(m.wall_time.as_micros() as u64);
-                report.outputs.vanilla_verification_cpu_time_us =
-                    Some(m.cpu_time.as_micros() as u64);
-            }
+                let mut total_verification_time = FuncMeasurement {
+                    cpu_time: Duration::new(0, 0),
+                    wall_time: Duration::new(0, 0),
+                    return_value: (),
+                };
 
-            let avg_seconds = |duration: Duration, samples: &usize| {
-                let n = duration / *samples as u32;
-                f64::from(n.subsec_nanos()) / 1_000_000_000f64 + (n.as_secs() as f64)
-            };
+                for _ in 0..*samples {
+                    let m = measure(|| {
+                        let verified = verify_partitions(
+                            &pp,
+                            &pub_inputs,
+                            &all_partition_proofs,
+                            *parallel_verify,
+                        )?;
+
+                        if !verified {
+                            panic!("verification failed");
+                        }
+
+                        Ok(())
+                    })?;
+
+                    total_verification_time.cpu_time += m.cpu_time;
+                    total_verification_time.wall_time += m.wall_time;
+
+                    report.outputs.vanilla_verification_wall_time_us =
+                        Some(m.wall_time.as_micros() as u64);
+                    report.outputs.vanilla_verification_cpu_time_us =
+                        Some(m.cpu_time.as_micros() as u64);
+                }
 
-            report.outputs.verifying_wall_time_avg_ms =
-                Some((avg_seconds(total_verification_time.wall_time, samples) * 1000.0) as u64);
-            report.outputs.verifying_cpu_time_avg_ms =
-                Some((avg_seconds(total_verification_time.cpu_time, samples) * 1000.0) as u64);
+                let avg_seconds = |duration: Duration, samples: &usize| {
+                    let n = duration / *samples as u32;
+                    f64::from(n.subsec_nanos()) / 1_000_000_000f64 + (n.as_secs() as f64)
+                };
 
-            (Some(pub_inputs), Some(priv_inputs), Some(data))
+                report.outputs.verifying_wall_time_avg_ms =
+                    Some((avg_seconds(total_verification_time.wall_time, samples) * 1000.0) as u64);
+                report.outputs.verifying_cpu_time_avg_ms =
+                    Some((avg_seconds(total_verification_time.cpu_time, samples) * 1000.0) as u64);
+
+                Ok((Some(pub_inputs), Some(priv_inputs), Some(data)))
+            })?
         };
 
         if *circuit || *groth || *bench {
             let CircuitWorkMeasurement {
                 cpu_time,
                 wall_time,
-            } = do_circuit_work(&pp, pub_in, priv_in, &params, &mut report)?;
+            } = do_circuit_work(
+                &pp,
+                pub_in,
+                priv_in,
+                loaded.map(|loaded| loaded.proofs),
+                &params,
+                &mut report,
+            )?;
             total_proving_wall_time += wall_time;
             total_proving_cpu_time += cpu_time;
         }
@@ -311,10 +605,59 @@ where
 
     report.outputs.total_report_wall_time_ms = total_wall_time.as_millis() as u64;
     report.outputs.total_report_cpu_time_ms = total_cpu_time.as_millis() as u64;
+    // The high water mark covers everything this process did so far, including replication and
+    // proving.
+    report.outputs.peak_resident_bytes = peak_resident_bytes();
 
     Ok(report)
 }
 
+/// Run `f`, writing a flamegraph of it to `path` if one is given.
+#[cfg(feature = "flamegraph")]
+fn profile<T, F>(path: Option<&str>, f: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T>,
+{
+    let path = match path {
+        Some(path) => path,
+        None => return f(),
+    };
+
+    let guard = pprof::ProfilerGuard::new(100)
+        .map_err(|err| anyhow::anyhow!("failed to start profiler: {:?}", err))?;
+
+    let res = f()?;
+
+    let report = guard
+        .report()
+        .build()
+        .map_err(|err| anyhow::anyhow!("failed to build profile: {:?}", err))?;
+    let file = File::create(path).with_context(|| format!("could not create {}", path))?;
+    report
+        .flamegraph(file)
+        .map_err(|err| anyhow::anyhow!("failed to write flamegraph: {:?}", err))?;
+
+    Ok(res)
+}
+
+/// Run `f`, flamegraphs are only available with the `flamegraph` feature.
+#[cfg(not(feature = "flamegraph"))]
+fn profile<T, F>(_path: Option<&str>, f: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T>,
+{
+    f()
+}
+
+/// Fail early if a flamegraph is requested, but benchy was built without support for it.
+fn ensure_flamegraph_supported(path: &Option<String>) -> anyhow::Result<()> {
+    if path.is_some() && !cfg!(feature = "flamegraph") {
+        bail!("--flamegraph requires benchy to be built with `--features flamegraph`");
+    }
+
+    Ok(())
+}
+
 struct CircuitWorkMeasurement {
     cpu_time: Duration,
     wall_time: Duration,
@@ -324,6 +667,7 @@ fn do_circuit_work<H: 'static + Hasher>(
     pp: &<StackedDrg<H, Sha256Hasher> as ProofScheme>::PublicParams,
     pub_in: Option<<StackedDrg<H, Sha256Hasher> as ProofScheme>::PublicInputs>,
     priv_in: Option<<StackedDrg<H, Sha256Hasher> as ProofScheme>::PrivateInputs>,
+    vanilla_proofs: Option<Vec<<StackedDrg<H, Sha256Hasher> as ProofScheme>::Proof>>,
     params: &Params,
     report: &mut Report,
 ) -> anyhow::Result<CircuitWorkMeasurement> {
@@ -357,14 +701,10 @@ fn do_circuit_work<H: 'static + Hasher>(
     if *groth {
         info!("Generating Groth Proof");
         let pub_inputs = pub_in.expect("missing public inputs");
-        let priv_inputs = priv_in.expect("missing private inputs");
-
-        // TODO: The time measured for Groth proving also includes parameter loading (which can be long)
-        // and vanilla proving, which may also be.
-        // For now, analysis should note and subtract out these times.
-        // We should implement a method of CompoundProof, which will skip vanilla proving.
-        // We should also allow the serialized vanilla proofs to be passed (as a file) to the example
-        // and skip replication/vanilla-proving entirely.
+
+        // TODO: Unless vanilla proofs were loaded with `--load-proofs`, the time measured for
+        // Groth proving also includes parameter loading (which can be long) and vanilla proving,
+        // which may also be. For now, analysis should note and subtract out these times.
         let gparams =
             <StackedCompound as CompoundProof<_, StackedDrg<H, Sha256Hasher>, _>>::groth_params(
                 &compound_public_params.vanilla_params,
@@ -375,8 +715,22 @@ fn do_circuit_work<H: 'static + Hasher>(
                 wall_time,
                 cpu_time,
                 return_value,
-            } = measure(|| {
-                StackedCompound::prove(&compound_public_params, &pub_inputs, &priv_inputs, &gparams)
+            } = measure(|| match &vanilla_proofs {
+                Some(vanilla_proofs) => StackedCompound::prove_with_vanilla(
+                    &compound_public_params,
+                    &pub_inputs,
+                    vanilla_proofs,
+                    &gparams,
+                ),
+                None => {
+                    let priv_inputs = priv_in.as_ref().expect("missing private inputs");
+                    StackedCompound::prove(
+                        &compound_public_params,
+                        &pub_inputs,
+                        priv_inputs,
+                        &gparams,
+                    )
+                }
             })?;
             proving_wall_time += wall_time;
             proving_cpu_time += cpu_time;
@@ -419,6 +773,16 @@ fn do_circuit_work<H: 'static + Hasher>(
         assert!(verified);
     }
 
+    let groth_proving_time = if *groth {
+        Some(proving_wall_time)
+    } else {
+        None
+    };
+    let sector_size = report.inputs.sector_size;
+    report
+        .outputs
+        .set_normalized_circuit_metrics(sector_size, *partitions, groth_proving_time);
+
     Ok(CircuitWorkMeasurement {
         cpu_time: proving_cpu_time,
         wall_time: proving_wall_time,
@@ -432,10 +796,12 @@ struct Inputs {
     partitions: usize,
     hasher: String,
     samples: usize,
+    replication_samples: usize,
     layers: usize,
     partition_challenges: usize,
     total_challenges: usize,
     config: StackedConfig,
+    data_source: String,
 }
 
 #[derive(Serialize, Default)]
@@ -445,14 +811,24 @@ struct Outputs {
     avg_groth_verifying_wall_time_ms: Option<u64>,
     circuit_num_constraints: Option<u64>,
     circuit_num_inputs: Option<u64>,
+    constraints_per_byte: Option<f64>,
+    groth_us_per_constraint: Option<f64>,
     extracting_cpu_time_ms: Option<u64>,
     extracting_wall_time_ms: Option<u64>,
+    peak_resident_bytes: Option<u64>,
     replication_wall_time_ms: Option<u64>,
     replication_cpu_time_ms: Option<u64>,
+    replication_wall_time_min_ms: Option<u64>,
+    replication_wall_time_max_ms: Option<u64>,
+    replication_wall_time_mean_ms: Option<u64>,
+    replication_wall_time_stddev_ms: Option<u64>,
     replication_wall_time_ns_per_byte: Option<u64>,
     replication_cpu_time_ns_per_byte: Option<u64>,
     total_report_cpu_time_ms: u64,
     total_report_wall_time_ms: u64,
+    tree_d_build_ms: Option<u64>,
+    tree_c_build_ms: Option<u64>,
+    tree_r_last_build_ms: Option<u64>,
     total_proving_cpu_time_ms: Option<u64>,
     total_proving_wall_time_ms: Option<u64>,
     vanilla_proving_cpu_time_us: Option<u64>,
@@ -463,6 +839,154 @@ struct Outputs {
     verifying_cpu_time_avg_ms: Option<u64>,
 }
 
+/// Maximum allowed values for `Outputs` fields, keyed by their kebab-case name.
+type Thresholds = BTreeMap<String, u64>;
+
+impl Outputs {
+    /// All integral fields keyed by their field name, for consumers which do not know the schema.
+    /// The fractional normalized metrics are only part of the serialized report.
+    fn as_map(&self) -> BTreeMap<&'static str, Option<u64>> {
+        let mut map = BTreeMap::new();
+        map.insert(
+            "avg_groth_verifying_cpu_time_ms",
+            self.avg_groth_verifying_cpu_time_ms,
+        );
+        map.insert(
+            "avg_groth_verifying_wall_time_ms",
+            self.avg_groth_verifying_wall_time_ms,
+        );
+        map.insert("circuit_num_constraints", self.circuit_num_constraints);
+        map.insert("circuit_num_inputs", self.circuit_num_inputs);
+        map.insert("extracting_cpu_time_ms", self.extracting_cpu_time_ms);
+        map.insert("extracting_wall_time_ms", self.extracting_wall_time_ms);
+        map.insert("peak_resident_bytes", self.peak_resident_bytes);
+        map.insert("replication_wall_time_ms", self.replication_wall_time_ms);
+        map.insert("replication_cpu_time_ms", self.replication_cpu_time_ms);
+        map.insert(
+            "replication_wall_time_min_ms",
+            self.replication_wall_time_min_ms,
+        );
+        map.insert(
+            "replication_wall_time_max_ms",
+            self.replication_wall_time_max_ms,
+        );
+        map.insert(
+            "replication_wall_time_mean_ms",
+            self.replication_wall_time_mean_ms,
+        );
+        map.insert(
+            "replication_wall_time_stddev_ms",
+            self.replication_wall_time_stddev_ms,
+        );
+        map.insert(
+            "replication_wall_time_ns_per_byte",
+            self.replication_wall_time_ns_per_byte,
+        );
+        map.insert(
+            "replication_cpu_time_ns_per_byte",
+            self.replication_cpu_time_ns_per_byte,
+        );
+        map.insert(
+            "total_report_cpu_time_ms",
+            Some(self.total_report_cpu_time_ms),
+        );
+        map.insert(
+            "total_report_wall_time_ms",
+            Some(self.total_report_wall_time_ms),
+        );
+        map.insert("total_proving_cpu_time_ms", self.total_proving_cpu_time_ms);
+        map.insert(
+            "total_proving_wall_time_ms",
+            self.total_proving_wall_time_ms,
+        );
+        map.insert("tree_d_build_ms", self.tree_d_build_ms);
+        map.insert("tree_c_build_ms", self.tree_c_build_ms);
+        map.insert("tree_r_last_build_ms", self.tree_r_last_build_ms);
+        map.insert(
+            "vanilla_proving_cpu_time_us",
+            self.vanilla_proving_cpu_time_us,
+        );
+        map.insert(
+            "vanilla_proving_wall_time_us",
+            self.vanilla_proving_wall_time_us,
+        );
+        map.insert(
+            "vanilla_verification_wall_time_us",
+            self.vanilla_verification_wall_time_us,
+        );
+        map.insert(
+            "vanilla_verification_cpu_time_us",
+            self.vanilla_verification_cpu_time_us,
+        );
+        map.insert(
+            "verifying_wall_time_avg_ms",
+            self.verifying_wall_time_avg_ms,
+        );
+        map.insert("verifying_cpu_time_avg_ms", self.verifying_cpu_time_avg_ms);
+        map
+    }
+
+    /// Set the circuit constraints per byte of the sector and, if Groth proving ran, the proving
+    /// time per constraint of all `partitions`. Both need the number of constraints of the circuit.
+    fn set_normalized_circuit_metrics(
+        &mut self,
+        sector_size: usize,
+        partitions: usize,
+        groth_proving_time: Option<Duration>,
+    ) {
+        let constraints = match self.circuit_num_constraints {
+            Some(constraints) if constraints > 0 => constraints as f64,
+            _ => return,
+        };
+
+        self.constraints_per_byte = Some(constraints / sector_size as f64);
+        self.groth_us_per_constraint = groth_proving_time
+            .map(|time| time.as_secs_f64() * 1e6 / (constraints * partitions as f64));
+    }
+
+    /// Set the min, max, mean and (population) standard deviation of the replication wall times.
+    fn set_replication_wall_time_stats(&mut self, samples: &[Duration]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let millis = samples
+            .iter()
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+        let n = millis.len() as f64;
+        let mean = millis.iter().sum::<f64>() / n;
+        let variance = millis.iter().map(|m| (m - mean).powi(2)).sum::<f64>() / n;
+
+        self.replication_wall_time_min_ms = samples.iter().min().map(|d| d.as_millis() as u64);
+        self.replication_wall_time_max_ms = samples.iter().max().map(|d| d.as_millis() as u64);
+        self.replication_wall_time_mean_ms = Some(mean as u64);
+        self.replication_wall_time_stddev_ms = Some(variance.sqrt() as u64);
+    }
+
+    /// Compare all fields named in `thresholds` against their maximum allowed value, returning a
+    /// description of every violation. Fields without a value in this report are skipped.
+    fn threshold_violations(&self, thresholds: &Thresholds) -> anyhow::Result<Vec<String>> {
+        let values = self.as_map();
+
+        let mut violations = Vec::new();
+        for (name, max) in thresholds {
+            let value = match values.get(name.replace("-", "_").as_str()) {
+                Some(value) => value,
+                None => bail!("unknown output in thresholds: {}", name),
+            };
+
+            if let Some(actual) = *value {
+                if actual > *max {
+                    violations.push(format!("{}: {} > {}", name, actual, max));
+                }
+            }
+        }
+
+        Ok(violations)
+    }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "kebab-case")]
 struct Report {
@@ -476,9 +1000,79 @@ impl Report {
         let wrapped = Metadata::wrap(&self).expect("failed to retrieve metadata");
         serde_json::to_writer(io::stdout(), &wrapped).expect("cannot write report-JSON to stdout");
     }
+
+    /// Print all results to stdout as a CSV header row followed by a single data row.
+    pub fn print_csv(&self) {
+        let (header, row) = self.to_csv().expect("failed to flatten report");
+        println!("{}\n{}", header, row);
+    }
+
+    /// Flatten `inputs` and `outputs` into a header and a data row. Nested values are keyed by
+    /// their dotted path, inputs precede outputs and each is sorted by name, and missing outputs are
+    /// left empty.
+    fn to_csv(&self) -> anyhow::Result<(String, String)> {
+        let mut columns = Vec::new();
+        flatten_json("", &serde_json::to_value(&self.inputs)?, &mut columns);
+        flatten_json("", &serde_json::to_value(&self.outputs)?, &mut columns);
+
+        let (names, values): (Vec<_>, Vec<_>) = columns
+            .into_iter()
+            .map(|(name, value)| (csv_escape(&name), csv_escape(&value)))
+            .unzip();
+
+        Ok((names.join(","), values.join(",")))
+    }
+
+    /// Fail if any of the outputs exceeds the thresholds stored as JSON in the file at `path`.
+    pub fn assert_thresholds(&self, path: &str) -> anyhow::Result<()> {
+        let file = File::open(path).with_context(|| format!("could not open {}", path))?;
+        let thresholds: Thresholds = serde_json::from_reader(file)
+            .with_context(|| format!("could not parse thresholds from {}", path))?;
+
+        let violations = self.outputs.threshold_violations(&thresholds)?;
+        if !violations.is_empty() {
+            bail!("thresholds exceeded:\n{}", violations.join("\n"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Append every leaf of `value` to `columns`, naming nested fields by their dotted path.
+fn flatten_json(prefix: &str, value: &serde_json::Value, columns: &mut Vec<(String, String)>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields {
+                let name = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", prefix, name)
+                };
+                flatten_json(&name, field, columns);
+            }
+        }
+        serde_json::Value::Null => columns.push((prefix.to_string(), String::new())),
+        serde_json::Value::String(s) => columns.push((prefix.to_string(), s.clone())),
+        other => columns.push((prefix.to_string(), other.to_string())),
+    }
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_string()
+    }
 }
 
 pub struct RunOpts {
+    pub assert_thresholds: Option<String>,
+    pub data_file: Option<String>,
+    pub flamegraph: Option<String>,
+    pub parallel_verify: bool,
+    pub replication_samples: usize,
+    pub keep_replica: Option<String>,
+    pub load_proofs: Option<String>,
     pub bench: bool,
     pub bench_only: bool,
     pub window_size_nodes: usize,
@@ -492,13 +1086,28 @@ pub struct RunOpts {
     pub layers: usize,
     pub no_bench: bool,
     pub no_tmp: bool,
+    pub output: String,
     pub partitions: usize,
     pub size: usize,
 }
 
 pub fn run(opts: RunOpts) -> anyhow::Result<()> {
+    ensure_flamegraph_supported(&opts.flamegraph)?;
+    if opts.output != "json" && opts.output != "csv" {
+        bail!("invalid output format: {}", opts.output);
+    }
+
     let config = StackedConfig::new(opts.layers, opts.window_challenges, opts.wrapper_challenges);
 
+    let sector_size = (opts.size * 1024) as u64;
+    if !config.meets_soundness(sector_size, SOUNDNESS_WARNING_BITS) {
+        warn!(
+            "config only provides an estimated {:.1} bits of soundness per partition (< {}), results are not representative of a secure setup",
+            config.soundness_bits(sector_size),
+            SOUNDNESS_WARNING_BITS
+        );
+    }
+
     let params = Params {
         config,
         data_size: opts.size * 1024,
@@ -512,12 +1121,25 @@ pub fn run(opts: RunOpts) -> anyhow::Result<()> {
         extract: opts.extract,
         hasher: opts.hasher,
         window_size_nodes: opts.window_size_nodes,
+        flamegraph: opts.flamegraph,
+        data_file: opts.data_file,
+        parallel_verify: opts.parallel_verify,
+        replication_samples: opts.replication_samples,
+        keep_replica: opts.keep_replica.is_some(),
+        load_proofs: opts.load_proofs,
         samples: 5,
     };
 
     info!("Benchy Stacked: {:?}", &params);
 
-    let cache_dir = tempfile::tempdir().unwrap();
+    let temp_dir = tempfile::tempdir().unwrap();
+    let cache_dir = match opts.keep_replica {
+        Some(dir) => {
+            fs::create_dir_all(&dir).with_context(|| format!("could not create {}", dir))?;
+            PathBuf::from(dir)
+        }
+        None => temp_dir.path().to_path_buf(),
+    };
 
     let report = match params.hasher.as_ref() {
         "pedersen" => generate_report::<PedersenHasher>(params, &cache_dir)?,
@@ -526,7 +1148,447 @@ pub fn run(opts: RunOpts) -> anyhow::Result<()> {
         _ => bail!("invalid hasher: {}", params.hasher),
     };
 
-    report.print();
+    if opts.output == "csv" {
+        report.print_csv();
+    } else {
+        report.print();
+    }
+
+    if let Some(path) = opts.assert_thresholds {
+        report.assert_thresholds(&path)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeSet;
+
+    use storage_proofs::merkle::create_merkle_tree;
+
+    #[test]
+    fn test_threshold_violations() {
+        let outputs = Outputs {
+            replication_wall_time_ms: Some(2000),
+            total_report_wall_time_ms: 5000,
+            ..Default::default()
+        };
+
+        let mut thresholds = Thresholds::new();
+        thresholds.insert("replication-wall-time-ms".into(), 3000);
+        thresholds.insert("extracting-wall-time-ms".into(), 1);
+        assert!(outputs
+            .threshold_violations(&thresholds)
+            .unwrap()
+            .is_empty());
+
+        thresholds.insert("total-report-wall-time-ms".into(), 4000);
+        let violations = outputs.threshold_violations(&thresholds).unwrap();
+        assert_eq!(violations, vec!["total-report-wall-time-ms: 5000 > 4000"]);
+
+        thresholds.insert("not-an-output".into(), 1);
+        assert!(outputs.threshold_violations(&thresholds).is_err());
+    }
+
+    #[test]
+    fn test_report_csv() {
+        let report = Report {
+            inputs: Inputs {
+                sector_size: 1024,
+                partitions: 1,
+                hasher: "pedersen".into(),
+                samples: 5,
+                replication_samples: 1,
+                layers: 4,
+                partition_challenges: 2,
+                total_challenges: 2,
+                config: StackedConfig::new(4, 2, 1),
+                data_source: "a,b".into(),
+            },
+            outputs: Outputs {
+                circuit_num_inputs: Some(42),
+                total_report_wall_time_ms: 5000,
+                ..Default::default()
+            },
+        };
+
+        let (header, row) = report.to_csv().unwrap();
+        let names = header.split(',').collect::<Vec<_>>();
+        // Only the data source contains a comma, which is quoted.
+        let values = row.replace("\"a,b\"", "ab");
+        let values = values.split(',').collect::<Vec<_>>();
+        assert_eq!(names.len(), values.len());
+
+        let column = |name: &str| values[names.iter().position(|n| *n == name).unwrap()];
+        assert_eq!(column("sector-size"), "1024");
+        assert_eq!(column("replication-samples"), "1");
+        assert_eq!(column("config.window-challenges.layers"), "4");
+        assert_eq!(column("circuit-num-inputs"), "42");
+        assert_eq!(column("extracting-wall-time-ms"), "");
+        assert_eq!(column("peak-resident-bytes"), "");
+        assert_eq!(names.last(), Some(&"verifying-wall-time-avg-ms"));
+    }
+
+    #[test]
+    fn test_outputs_as_map() {
+        let outputs = Outputs {
+            circuit_num_inputs: Some(42),
+            total_report_wall_time_ms: 5000,
+            ..Default::default()
+        };
+
+        let map = outputs.as_map();
+        assert_eq!(map["circuit_num_inputs"], Some(42));
+        assert_eq!(map["total_report_wall_time_ms"], Some(5000));
+        assert_eq!(map["extracting_wall_time_ms"], None);
+
+        // The serialized field names are the kebab-case versions of the struct's field names.
+        let value = serde_json::to_value(&outputs).unwrap();
+        let fractional = ["constraints_per_byte", "groth_us_per_constraint"];
+        let field_names = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|name| name.replace("-", "_"))
+            .filter(|name| !fractional.contains(&name.as_str()))
+            .collect::<BTreeSet<_>>();
+        let keys = map
+            .keys()
+            .map(|key| key.to_string())
+            .collect::<BTreeSet<_>>();
+
+        assert_eq!(keys, field_names);
+    }
+
+    #[test]
+    fn test_data_file() {
+        let nodes = 64;
+
+        // Leave the last byte of every node empty, so all nodes are valid field elements.
+        let mut contents = vec![7u8; (nodes - 3) * 32];
+        for node in contents.chunks_mut(32) {
+            node[31] = 0;
+        }
+        let mut data_file = tempfile::NamedTempFile::new().unwrap();
+        io::Write::write_all(&mut data_file, &contents).unwrap();
+
+        let data =
+            file_backed_mmap_from_file(nodes, true, data_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(&data[..contents.len()], &contents[..]);
+        assert!(data[contents.len()..].iter().all(|b| *b == 0));
+
+        let zeroes = file_backed_mmap_from_zeroes(nodes, true).unwrap();
+
+        let comm_d = create_merkle_tree::<Sha256Hasher>(None, nodes, &data)
+            .unwrap()
+            .root();
+        let zero_comm_d = create_merkle_tree::<Sha256Hasher>(None, nodes, &zeroes)
+            .unwrap()
+            .root();
+        assert_ne!(comm_d, zero_comm_d);
+    }
+
+    #[test]
+    fn test_parallel_verify() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let rng = &mut rand::thread_rng();
+
+        let nodes = 64;
+        let partitions = 3;
+        let sp = stacked::SetupParams {
+            nodes,
+            degree: BASE_DEGREE,
+            expansion_degree: EXP_DEGREE,
+            seed: new_seed(),
+            config: StackedConfig::new(2, 2, 2),
+            window_size_nodes: nodes / 2,
+        };
+        let pp = StackedDrg::<PedersenHasher, Sha256Hasher>::setup(&sp).unwrap();
+
+        let store_config = StoreConfig::new(
+            cache_dir.path(),
+            CacheKey::CommDTree.to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        let mut data = file_backed_mmap_from_zeroes(nodes, true).unwrap();
+        let (tau, (p_aux, t_aux)) = StackedDrg::<PedersenHasher, Sha256Hasher>::replicate(
+            &pp,
+            &replica_id,
+            &mut data,
+            None,
+            Some(store_config),
+        )
+        .unwrap();
+
+        let pub_inputs = stacked::public_inputs_for::<PedersenHasher, Sha256Hasher>(
+            replica_id,
+            tau,
+            rng.gen(),
+            0,
+        );
+        let priv_inputs = stacked::PrivateInputs {
+            p_aux,
+            t_aux: TemporaryAuxCache::new(&t_aux).unwrap(),
+        };
+
+        let mut all_partition_proofs =
+            StackedDrg::<PedersenHasher, Sha256Hasher>::prove_all_partitions(
+                &pp,
+                &pub_inputs,
+                &priv_inputs,
+                partitions,
+            )
+            .unwrap();
+
+        let serial = verify_partitions(&pp, &pub_inputs, &all_partition_proofs, false).unwrap();
+        let parallel = verify_partitions(&pp, &pub_inputs, &all_partition_proofs, true).unwrap();
+        assert!(serial);
+        assert_eq!(serial, parallel);
+
+        // Proofs of the wrong partitions fail in both modes.
+        all_partition_proofs.swap(1, 2);
+        let serial = verify_partitions(&pp, &pub_inputs, &all_partition_proofs, false).unwrap();
+        let parallel = verify_partitions(&pp, &pub_inputs, &all_partition_proofs, true).unwrap();
+        assert!(!serial);
+        assert_eq!(serial, parallel);
+    }
+
+    /// Number of nodes of the sectors `test_params` replicates.
+    const TEST_NODES: usize = 64;
+
+    /// Params of a small, quick run, which replicates and proves once without a circuit.
+    fn test_params() -> Params {
+        Params {
+            samples: 1,
+            window_size_nodes: TEST_NODES / 2,
+            data_size: TEST_NODES * 32,
+            config: StackedConfig::new(2, 1, 1),
+            partitions: 1,
+            circuit: false,
+            groth: false,
+            bench: false,
+            extract: false,
+            use_tmp: true,
+            dump_proofs: false,
+            bench_only: false,
+            hasher: "pedersen".into(),
+            flamegraph: None,
+            data_file: None,
+            parallel_verify: false,
+            replication_samples: 1,
+            keep_replica: false,
+            load_proofs: None,
+        }
+    }
+
+    #[test]
+    fn test_tree_build_times() {
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let params = test_params();
+
+        let report = generate_report::<PedersenHasher>(params, cache_dir.path()).unwrap();
+
+        assert!(report.outputs.tree_d_build_ms.is_some());
+        assert!(report.outputs.tree_c_build_ms.is_some());
+        assert!(report.outputs.tree_r_last_build_ms.is_some());
+    }
+
+    #[test]
+    fn test_keep_replica() {
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let params = Params {
+            keep_replica: true,
+            ..test_params()
+        };
+
+        generate_report::<PedersenHasher>(params, cache_dir.path()).unwrap();
+
+        let replica = std::fs::metadata(cache_dir.path().join(REPLICA_FILE)).unwrap();
+        assert_eq!(replica.len(), (TEST_NODES * 32) as u64);
+
+        let t_aux_bytes = std::fs::read(cache_dir.path().join(CacheKey::TAux.to_string())).unwrap();
+        let _: stacked::TemporaryAux<PedersenHasher, Sha256Hasher> =
+            bincode::deserialize(&t_aux_bytes).unwrap();
+
+        assert!(cache_dir.path().join(CacheKey::PAux.to_string()).exists());
+    }
+
+    #[test]
+    fn test_replication_samples() {
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let params = Params {
+            replication_samples: 2,
+            ..test_params()
+        };
+
+        let outputs = generate_report::<PedersenHasher>(params, cache_dir.path())
+            .unwrap()
+            .outputs;
+
+        let min = outputs.replication_wall_time_min_ms.unwrap();
+        let max = outputs.replication_wall_time_max_ms.unwrap();
+        let mean = outputs.replication_wall_time_mean_ms.unwrap();
+        let stddev = outputs.replication_wall_time_stddev_ms.unwrap();
+        let first = outputs.replication_wall_time_ms.unwrap();
+
+        assert!(min <= mean && mean <= max);
+        assert!(min <= first && first <= max);
+        // With two samples, the standard deviation is half their distance.
+        assert!(stddev <= (max - min) / 2 + 1);
+
+        let params = Params {
+            replication_samples: 0,
+            ..test_params()
+        };
+        assert!(generate_report::<PedersenHasher>(params, cache_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_normalized_circuit_metrics() {
+        let mut outputs = Outputs::default();
+        outputs.set_normalized_circuit_metrics(1024, 2, Some(Duration::from_secs(1)));
+        assert_eq!(outputs.constraints_per_byte, None);
+        assert_eq!(outputs.groth_us_per_constraint, None);
+
+        outputs.circuit_num_constraints = Some(2048);
+        outputs.set_normalized_circuit_metrics(1024, 1, None);
+        assert_eq!(outputs.constraints_per_byte, Some(2.0));
+        assert_eq!(outputs.groth_us_per_constraint, None);
+
+        // Groth proving covers the constraints of all partitions.
+        outputs.set_normalized_circuit_metrics(1024, 2, Some(Duration::from_millis(4096)));
+        assert_eq!(outputs.constraints_per_byte, Some(2.0));
+        assert_eq!(outputs.groth_us_per_constraint, Some(1000.0));
+    }
+
+    #[test]
+    fn test_replication_wall_time_stats() {
+        let mut outputs = Outputs::default();
+        outputs.set_replication_wall_time_stats(&[
+            Duration::from_millis(10),
+            Duration::from_millis(30),
+            Duration::from_millis(20),
+        ]);
+
+        assert_eq!(outputs.replication_wall_time_min_ms, Some(10));
+        assert_eq!(outputs.replication_wall_time_max_ms, Some(30));
+        assert_eq!(outputs.replication_wall_time_mean_ms, Some(20));
+        // sqrt(200 / 3)
+        assert_eq!(outputs.replication_wall_time_stddev_ms, Some(8));
+    }
+
+    #[test]
+    fn test_load_proofs_params_mismatch() {
+        let params = test_params();
+        let dumped = DumpedProofs::<PedersenHasher> {
+            sector_size: params.data_size,
+            layers: params.config.layers(),
+            window_size_nodes: params.window_size_nodes,
+            window_challenges: params.config.window_challenges.challenges_count_all(),
+            wrapper_challenges: params.config.wrapper_challenges.challenges_count_all(),
+            hasher: params.hasher.clone(),
+            graph_seed: [1; 28],
+            replica_id: Default::default(),
+            seed: [2; 32],
+            comm_d: Default::default(),
+            comm_r: Default::default(),
+            proofs: Vec::new(),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("proofs");
+        serde_json::to_writer(File::create(&path).unwrap(), &dumped).unwrap();
+        let path = path.to_str().unwrap();
+
+        let load_err = |params: Params| {
+            load_proof_bytes::<PedersenHasher>(path, &params)
+                .err()
+                .expect("loaded proofs for different params")
+                .to_string()
+        };
+
+        let loaded = load_proof_bytes::<PedersenHasher>(
+            path,
+            &Params {
+                partitions: 0,
+                ..test_params()
+            },
+        )
+        .unwrap();
+        assert_eq!(loaded.graph_seed, [1; 28]);
+        assert_eq!(loaded.public_inputs().seed, [2; 32]);
+
+        assert!(load_err(Params {
+            partitions: 2,
+            ..test_params()
+        })
+        .contains("contains proofs for 0 partitions, but --partitions is 2"));
+
+        assert!(load_err(Params {
+            hasher: "blake2s".into(),
+            ..test_params()
+        })
+        .contains("contains proofs using the pedersen hasher, but --hasher is blake2s"));
+
+        assert!(load_err(Params {
+            data_size: params.data_size * 2,
+            ..test_params()
+        })
+        .contains("contains proofs for a sector of 2 KiB, but --size is 4"));
+
+        assert!(load_err(Params {
+            config: StackedConfig::new(3, 1, 1),
+            ..test_params()
+        })
+        .contains("contains proofs for 2 layers, but --layers is 3"));
+
+        assert!(load_err(Params {
+            window_size_nodes: TEST_NODES,
+            ..test_params()
+        })
+        .contains("contains proofs for windows of 1024 bytes, but --window-size is 2048"));
+
+        assert!(load_err(Params {
+            config: StackedConfig::new(2, 2, 1),
+            ..test_params()
+        })
+        .contains("contains proofs for 1 window challenges, but --window-challenges is 2"));
+
+        assert!(load_err(Params {
+            config: StackedConfig::new(2, 1, 2),
+            ..test_params()
+        })
+        .contains("contains proofs for 1 wrapper challenges, but --wrapper-challenges is 2"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "flamegraph"))]
+    fn test_flamegraph_unsupported() {
+        assert!(ensure_flamegraph_supported(&None).is_ok());
+        assert!(ensure_flamegraph_supported(&Some("replication.svg".into())).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "flamegraph")]
+    fn test_flamegraph() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let flamegraph = cache_dir.path().join("replication.svg");
+
+        let params = Params {
+            flamegraph: Some(flamegraph.to_string_lossy().into()),
+            ..test_params()
+        };
+
+        generate_report::<PedersenHasher>(params, cache_dir.path()).unwrap();
+
+        let metadata = std::fs::metadata(&flamegraph).unwrap();
+        assert!(metadata.len() > 0);
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use filecoin_proofs::types::SectorSize;
+use log::debug;
+
+/// Estimate how long replicating a sector of `sector_size` takes with the given `hasher`.
+///
+/// `ns_per_byte` is the calibrated cost of replicating a single byte with `hasher` on the target
+/// machine, as reported by `benchy stacked` in `replication-wall-time-ns-per-byte`. The estimate
+/// is only as accurate as this calibration.
+pub fn estimate_replication_time(
+    sector_size: SectorSize,
+    hasher: &str,
+    ns_per_byte: u64,
+) -> Duration {
+    let bytes = u64::from(sector_size);
+    debug!(
+        "estimating replication of {} bytes with {} at {}ns/byte",
+        bytes, hasher, ns_per_byte
+    );
+
+    Duration::from_nanos(bytes.saturating_mul(ns_per_byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_replication_time() {
+        let small = estimate_replication_time(SectorSize(1 << 20), "pedersen", 4000);
+        let large = estimate_replication_time(SectorSize(1 << 30), "pedersen", 4000);
+
+        assert_eq!(small, Duration::from_nanos((1 << 20) * 4000));
+        assert_eq!(large, small * 1024);
+    }
+}
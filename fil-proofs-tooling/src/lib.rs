@@ -1,5 +1,7 @@
+pub mod estimate;
 pub mod measure;
 pub mod metadata;
 
-pub use measure::{measure, FuncMeasurement};
+pub use estimate::estimate_replication_time;
+pub use measure::{measure, peak_resident_bytes, FuncMeasurement};
 pub use metadata::Metadata;
@@ -24,3 +24,48 @@ where
         return_value: x,
     })
 }
+
+/// The peak resident set size of this process so far, in bytes. Only available on Linux, where it
+/// is read from `VmHWM` in `/proc/self/status`.
+#[cfg(target_os = "linux")]
+pub fn peak_resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm(&status)
+}
+
+/// The peak resident set size of this process so far, in bytes. Only available on Linux.
+#[cfg(not(target_os = "linux"))]
+pub fn peak_resident_bytes() -> Option<u64> {
+    None
+}
+
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let mut fields = line["VmHWM:".len()..].split_whitespace();
+    let value: u64 = fields.next()?.parse().ok()?;
+
+    match fields.next() {
+        Some("kB") => Some(value * 1024),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status = "Name:\tbenchy\nVmPeak:\t  20000 kB\nVmHWM:\t    1234 kB\nVmRSS:\t  1000 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(1234 * 1024));
+        assert_eq!(parse_vm_hwm("VmRSS:\t  1000 kB\n"), None);
+        assert_eq!(parse_vm_hwm("VmHWM:\t  lots kB\n"), None);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_peak_resident_bytes() {
+        assert!(peak_resident_bytes().unwrap() > 0);
+    }
+}
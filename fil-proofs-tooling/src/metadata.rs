@@ -62,6 +62,7 @@ pub struct SystemMetadata {
     processor_features: String,
     processor_cores_logical: u64,
     processor_cores_physical: u64,
+    num_cpus: usize,
     memory_total_bytes: u64,
 }
 
@@ -107,6 +108,7 @@ impl SystemMetadata {
                 .unwrap_or_default(),
             processor_cores_logical: cpu_logical,
             processor_cores_physical: cpu_physical.unwrap_or_default(),
+            num_cpus: num_cpus::get(),
             memory_total_bytes: memory.total().get::<uom::si::information::byte>(),
         })
     }
@@ -123,4 +125,17 @@ mod tests {
 
         assert!(m.system.memory_total_bytes > 0);
     }
+
+    #[test]
+    fn test_metadata_num_cpus() {
+        let m = Metadata::wrap(()).unwrap();
+        assert!(m.system.num_cpus > 0);
+        assert_eq!(m.system.num_cpus, num_cpus::get());
+
+        let json = serde_json::to_value(&m).unwrap();
+        assert_eq!(
+            json["system"]["num-cpus"].as_u64(),
+            Some(num_cpus::get() as u64)
+        );
+    }
 }
@@ -2,11 +2,14 @@
 extern crate criterion;
 
 use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::time::Duration;
 
 use criterion::{Criterion, ParameterizedBenchmark, Throughput};
-use filecoin_proofs::fr32::{write_padded, write_unpadded};
+use filecoin_proofs::fr32::{
+    padded_bytes, write_padded, write_padded_at, write_padded_into, write_padded_scalar,
+    write_unpadded,
+};
 use rand::{thread_rng, Rng};
 
 fn random_data(size: usize) -> Vec<u8> {
@@ -34,6 +37,32 @@ fn preprocessing_benchmark(c: &mut Criterion) {
             },
             vec![128, 256, 512, 256_000, 512_000, 1024_000, 2048_000],
         )
+        .with_function("write_padded_scalar", |b, size| {
+            let data = random_data(*size);
+
+            b.iter(|| {
+                let mut tmpfile: File = tempfile::tempfile().unwrap();
+
+                write_padded_scalar(&mut &data[..], &mut tmpfile).unwrap();
+            })
+        })
+        .with_function("write_padded_at (in memory)", |b, size| {
+            let data = random_data(*size);
+            let mut target = vec![0u8; padded_bytes(*size)];
+
+            b.iter(|| {
+                // The target is preallocated, so its length does not tell where to start.
+                write_padded_at(&mut &data[..], Cursor::new(&mut target[..]), 0).unwrap();
+            })
+        })
+        .with_function("write_padded_into (in memory)", |b, size| {
+            let data = random_data(*size);
+            let mut target = vec![0u8; padded_bytes(*size)];
+
+            b.iter(|| {
+                write_padded_into(&data, &mut target).unwrap();
+            })
+        })
         .with_function("write_padded + unpadded", |b, size| {
             let data = random_data(*size);
 
@@ -1,13 +1,14 @@
 use std::fs::File;
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use merkletree::store::{StoreConfig, DEFAULT_CACHED_ABOVE_BASE_LAYER};
 use storage_proofs::drgraph::DefaultTreeHasher;
 use storage_proofs::hasher::Hasher;
 use storage_proofs::sector::SectorId;
 use storage_proofs::stacked::{generate_replica_id, CacheKey, StackedDrg};
+use storage_proofs::util::NODE_SIZE;
 use tempfile::tempfile;
 
 use crate::api::util::as_safe_commitment;
@@ -15,12 +16,16 @@ use crate::constants::{
     DefaultPieceHasher,
     MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
 };
+use crate::error::Error;
 use crate::fr32::{write_padded, write_unpadded};
 use crate::parameters::public_params;
-use crate::pieces::get_aligned_source;
+use crate::pieces::{
+    compute_comm_d, for_each_padded_chunk, get_aligned_source, streaming_comm_d, verify_pieces,
+    CommDWriter, SparseTreeBuilder,
+};
 use crate::types::{
-    Commitment, PaddedBytesAmount, PieceInfo, PoRepConfig, PoRepProofPartitions, ProverId, Ticket,
-    UnpaddedByteIndex, UnpaddedBytesAmount,
+    Commitment, PaddedBytesAmount, PieceInfo, PoRepConfig, PoRepProofPartitions, ProverId,
+    SectorSize, Ticket, UnpaddedByteIndex, UnpaddedBytesAmount,
 };
 
 mod post;
@@ -133,6 +138,36 @@ pub fn generate_piece_commitment<T: std::io::Read>(
     })
 }
 
+/// Generates the same piece commitment as `generate_piece_commitment`, but faster for pieces
+/// which are mostly zero: runs of zero nodes are not hashed, they are added as aligned zero
+/// subtrees whose roots come from the zero root table. The padded piece is never staged on disk.
+pub fn generate_piece_commitment_sparse<R: Read>(
+    source: R,
+    piece_size: UnpaddedBytesAmount,
+) -> Result<PieceInfo> {
+    ensure_piece_size(piece_size)?;
+
+    let mut builder = SparseTreeBuilder::default();
+    let read = for_each_padded_chunk(source, |_, padded| {
+        for node in padded.chunks(NODE_SIZE) {
+            builder.push(node);
+        }
+        Ok(())
+    })?;
+
+    ensure!(
+        UnpaddedBytesAmount(read) == piece_size,
+        "read ({:?}) but expected to read ({:?}) when preprocessing",
+        UnpaddedBytesAmount(read),
+        piece_size
+    );
+
+    Ok(PieceInfo {
+        commitment: builder.root()?,
+        size: piece_size,
+    })
+}
+
 /// Computes a NUL-byte prefix and/or suffix for `source` using the provided
 /// `piece_lengths` and `piece_size` (such that the `source`, after
 /// preprocessing, will occupy a subtree of a merkle tree built using the bytes
@@ -159,6 +194,7 @@ where
 
     let (aligned_source_size, alignment, aligned_source) =
         get_aligned_source(source, &piece_lengths, piece_size);
+    let alignment_bytes = alignment.left_bytes + alignment.right_bytes;
 
     // allows us to tee the source byte stream
     let (mut pipe_r, pipe_w) =
@@ -197,6 +233,18 @@ where
         .join()
         .map_err(|err| format_err!("join piece commitment-generating thread failed: {:?}", err));
 
+    // the alignment bytes are always written in full, so any shortfall is due to the source
+    if let Ok(n) = &write_rslt {
+        let actual = UnpaddedBytesAmount(*n as u64) - alignment_bytes;
+        if actual < piece_size {
+            return Err(Error::PieceShorterThanDeclared {
+                expected: piece_size,
+                actual,
+            }
+            .into());
+        }
+    }
+
     match (write_rslt, join_rslt) {
         (Ok(n), Ok(Ok(r))) => {
             ensure!(n != 0, "add_piece: read 0 bytes before EOF from source");
@@ -235,6 +283,314 @@ where
     }
 }
 
+/// Computes the `comm_d` of a sector holding the contents of the files at `paths`, each as a
+/// separate piece in the given order, and returns it with the infos of those pieces.
+///
+/// Every file is padded with zeros to the smallest valid piece size which fits it. The files are
+/// streamed, so neither the pieces nor the sector are staged in memory or on disk.
+pub fn comm_d_from_files(
+    paths: &[&Path],
+    sector_size: SectorSize,
+) -> Result<(Commitment, Vec<PieceInfo>)> {
+    let piece_infos = paths
+        .iter()
+        .map(|path| {
+            let file =
+                File::open(path).with_context(|| format!("could not open {}", path.display()))?;
+            let len = file.metadata()?.len();
+            let piece_size = smallest_piece_size(UnpaddedBytesAmount(len));
+
+            let source = file
+                .take(len)
+                .chain(io::repeat(0))
+                .take(u64::from(piece_size));
+            let commitment = streaming_piece_commitment(source, piece_size, |_, _| {})
+                .with_context(|| format!("could not commit to {}", path.display()))?;
+
+            Ok(PieceInfo::new(commitment, piece_size))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let comm_d = compute_comm_d(sector_size, &piece_infos)?;
+
+    Ok((comm_d, piece_infos))
+}
+
+/// The smallest valid piece size which holds `len` bytes.
+fn smallest_piece_size(len: UnpaddedBytesAmount) -> UnpaddedBytesAmount {
+    let padded_len = u64::from(PaddedBytesAmount::from(len));
+
+    UnpaddedBytesAmount::from(PaddedBytesAmount(std::cmp::max(
+        padded_len.next_power_of_two(),
+        u64::from(PaddedBytesAmount::from(UnpaddedBytesAmount(
+            MINIMUM_PIECE_SIZE,
+        ))),
+    )))
+}
+
+/// Checks that the `comm_d` of a sector holding the first `len` bytes of `unpadded`, followed by
+/// zeros, is the same whether it is computed by padding the data and building the data tree over
+/// the whole sector, or by committing to the data as a piece and combining that with zero
+/// padding pieces. A mismatch points to a bug at fr32 boundaries in one of the two paths.
+///
+/// The padded sector is held in memory, so this is meant for tests and debugging.
+pub fn verify_comm_d_consistency<R: Read>(
+    unpadded: R,
+    len: UnpaddedBytesAmount,
+    sector_size: SectorSize,
+) -> Result<bool> {
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    ensure!(
+        len <= unpadded_sector,
+        "data ({:?}) does not fit into the sector ({:?})",
+        len,
+        unpadded_sector
+    );
+
+    let mut data = Vec::with_capacity(u64::from(len) as usize);
+    unpadded.take(u64::from(len)).read_to_end(&mut data)?;
+    ensure!(
+        data.len() as u64 == u64::from(len),
+        "read ({}) but expected to read ({:?})",
+        data.len(),
+        len
+    );
+
+    // Pad the data, then build the data tree over the zero extended sector.
+    let mut padded = Vec::new();
+    write_padded(&data[..], Cursor::new(&mut padded))?;
+    padded.resize(u64::from(sector_size) as usize, 0);
+    let tree_comm_d = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+        &mut Cursor::new(&padded),
+    )?;
+
+    // Commit to the data as a zero extended piece, then fill the sector with padding pieces.
+    let piece_size = smallest_piece_size(len);
+    let source = Cursor::new(&data)
+        .chain(io::repeat(0))
+        .take(u64::from(piece_size));
+    let piece = generate_piece_commitment(source, piece_size)?;
+    let piece_comm_d = compute_comm_d(sector_size, &[piece])?;
+
+    Ok(tree_comm_d == piece_comm_d)
+}
+
+/// Checks that piece commitment, piece alignment, `comm_d` computation and the data tree agree
+/// with each other, by staging a small deterministic sector of `sector_size`. Meant as a startup
+/// self test, the whole sector is held in memory.
+pub fn self_test(sector_size: SectorSize) -> Result<()> {
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    ensure!(
+        u64::from(unpadded_sector) >= 2 * MINIMUM_PIECE_SIZE,
+        "sector size ({:?}) is too small to hold two pieces",
+        sector_size
+    );
+
+    // A minimum sized piece followed by one filling half of the sector, so the second piece
+    // needs alignment for all but the smallest sector.
+    let piece_sizes = [
+        UnpaddedBytesAmount(MINIMUM_PIECE_SIZE),
+        UnpaddedBytesAmount(u64::from(unpadded_sector) / 2),
+    ];
+
+    let mut staged_sector = Vec::with_capacity(u64::from(sector_size) as usize);
+    let mut staged_sector_io = Cursor::new(&mut staged_sector);
+    let mut piece_infos = Vec::with_capacity(piece_sizes.len());
+
+    for (i, piece_size) in piece_sizes.iter().enumerate() {
+        let piece_bytes: Vec<u8> = (0..u64::from(*piece_size))
+            .map(|j| (j as u8).wrapping_mul(31).wrapping_add(i as u8))
+            .collect();
+
+        let piece_info = generate_piece_commitment(Cursor::new(&piece_bytes), *piece_size)?;
+        let (_, added_commitment) = add_piece(
+            Cursor::new(&piece_bytes),
+            &mut staged_sector_io,
+            *piece_size,
+            &piece_sizes[..i],
+        )?;
+        ensure!(
+            added_commitment == piece_info.commitment,
+            "commitment of piece {} differs between generate_piece_commitment ({:?}) and \
+             add_piece ({:?})",
+            i,
+            piece_info.commitment,
+            added_commitment
+        );
+
+        piece_infos.push(piece_info);
+    }
+
+    ensure!(
+        staged_sector.len() as u64 == u64::from(sector_size),
+        "staged sector is {} bytes, but the sector size is {:?}",
+        staged_sector.len(),
+        sector_size
+    );
+
+    let comm_d = compute_comm_d(sector_size, &piece_infos)?;
+    ensure!(
+        verify_pieces(&comm_d, &piece_infos, sector_size)?,
+        "pieces do not verify against their own comm_d ({:?})",
+        comm_d
+    );
+
+    let tree_comm_d = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+        &mut Cursor::new(&staged_sector),
+    )?;
+    ensure!(
+        tree_comm_d == comm_d,
+        "comm_d of the pieces ({:?}) differs from the data tree root ({:?})",
+        comm_d,
+        tree_comm_d
+    );
+
+    Ok(())
+}
+
+/// Computes the `comm_d` a partially written sector would have if the space after the first
+/// `written_len` bytes were filled with zeros. Only the written bytes are read from `written`,
+/// the zero tail is not materialized.
+pub fn partial_comm_d<R: Read>(
+    written: R,
+    written_len: UnpaddedBytesAmount,
+    sector_size: SectorSize,
+) -> Result<Commitment> {
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    ensure!(
+        written_len <= unpadded_sector,
+        "written ({:?}) exceeds the sector ({:?})",
+        written_len,
+        unpadded_sector
+    );
+
+    let (comm_d, read) = streaming_comm_d(written.take(u64::from(written_len)), sector_size)?;
+    ensure!(
+        read == u64::from(written_len),
+        "written bytes ended {} bytes early",
+        u64::from(written_len) - read
+    );
+
+    Ok(comm_d)
+}
+
+/// Computes the `comm_d` of a sector into which the `sources` are interleaved, chunk by chunk.
+///
+/// Every source provides the given number of unpadded bytes, which must be a multiple of 127, so
+/// that it pads to whole nodes. Each source is split into consecutive chunks of `chunk_nodes`
+/// padded nodes, i.e. `chunk_nodes / 4 * 127` unpadded bytes, the last chunk of a source holding
+/// whatever is left. `chunk_nodes` must be a multiple of 4. The sector then consists of the first
+/// chunk of every source, in the order of `sources`, followed by the second chunk of every source,
+/// and so on. Sources which have run out of chunks are skipped, and the remainder of the sector is
+/// filled with zeros.
+pub fn comm_d_interleaved(
+    sources: Vec<(Box<dyn Read>, UnpaddedBytesAmount)>,
+    sector_size: SectorSize,
+    chunk_nodes: usize,
+) -> Result<Commitment> {
+    ensure!(
+        chunk_nodes > 0 && chunk_nodes % 4 == 0,
+        "chunk_nodes must be a non zero multiple of 4, got {}",
+        chunk_nodes
+    );
+
+    let total = sources
+        .iter()
+        .map(|(_, size)| u64::from(PaddedBytesAmount::from(*size)))
+        .sum::<u64>();
+    ensure!(
+        total <= u64::from(sector_size),
+        "sources ({} padded bytes) do not fit into the sector ({} bytes)",
+        total,
+        u64::from(sector_size)
+    );
+
+    let chunk_size = (chunk_nodes / 4 * 127) as u64;
+    let mut sources = sources
+        .into_iter()
+        .map(|(source, size)| {
+            ensure!(
+                u64::from(size) % 127 == 0,
+                "source size {:?} is not a multiple of 127 bytes",
+                size
+            );
+            Ok((source, u64::from(size)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut writer = CommDWriter::new(io::sink(), sector_size);
+    let mut chunk = Vec::with_capacity(chunk_size as usize);
+    let mut padded = Vec::new();
+
+    while sources.iter().any(|(_, remaining)| *remaining > 0) {
+        for (i, (source, remaining)) in sources.iter_mut().enumerate() {
+            if *remaining == 0 {
+                continue;
+            }
+
+            let len = std::cmp::min(chunk_size, *remaining);
+            chunk.clear();
+            source.by_ref().take(len).read_to_end(&mut chunk)?;
+            ensure!(
+                chunk.len() as u64 == len,
+                "source {} ended {} bytes early",
+                i,
+                *remaining - chunk.len() as u64
+            );
+            *remaining -= len;
+
+            padded.clear();
+            write_padded(&chunk[..], Cursor::new(&mut padded))?;
+            writer.write_all(&padded)?;
+        }
+    }
+
+    writer.finalize()
+}
+
+/// Generates the same piece commitment as `generate_piece_commitment`, without staging the
+/// padded piece in a temporary file. `progress` is called after every chunk of the piece with
+/// the number of bytes processed so far and the total number of bytes of the piece.
+pub fn generate_piece_commitment_streaming<R, F>(
+    source: R,
+    piece_size: UnpaddedBytesAmount,
+    progress: F,
+) -> Result<PieceInfo>
+where
+    R: Read,
+    F: FnMut(u64, u64),
+{
+    let commitment = streaming_piece_commitment(source, piece_size, progress)?;
+
+    Ok(PieceInfo::new(commitment, piece_size))
+}
+
+/// Generates the commitment of a piece, padding `source` on the fly instead of writing the padded
+/// bytes to a temporary file first.
+fn streaming_piece_commitment<R, F>(
+    source: R,
+    piece_size: UnpaddedBytesAmount,
+    mut progress: F,
+) -> Result<Commitment>
+where
+    R: Read,
+    F: FnMut(u64, u64),
+{
+    ensure_piece_size(piece_size)?;
+
+    let padded_size = PaddedBytesAmount::from(piece_size);
+    let mut writer = CommDWriter::new(io::sink(), SectorSize(u64::from(padded_size)));
+
+    let total = u64::from(piece_size);
+    for_each_padded_chunk(source, |processed, padded| {
+        writer.write_all(padded)?;
+        progress(processed, total);
+        Ok(())
+    })?;
+
+    writer.finalize()
+}
+
 fn ensure_piece_size(piece_size: UnpaddedBytesAmount) -> Result<()> {
     ensure!(
         piece_size >= UnpaddedBytesAmount(MINIMUM_PIECE_SIZE),
@@ -278,18 +634,19 @@ mod tests {
 
     use std::collections::BTreeMap;
     use std::io::{Seek, SeekFrom, Write};
+    use std::sync::atomic::Ordering;
     use std::sync::Once;
 
     use ff::Field;
     use paired::bls12_381::{Bls12, Fr};
-    use rand::{Rng, SeedableRng};
+    use rand::{Rng, RngCore, SeedableRng};
     use rand_xorshift::XorShiftRng;
     use storage_proofs::election_post::Candidate;
     use storage_proofs::fr32::bytes_into_fr;
     use tempfile::NamedTempFile;
 
     use crate::constants::{SECTOR_SIZE_ONE_KIB, SINGLE_PARTITION_PROOF_LEN};
-    use crate::types::{PoStConfig, SectorSize};
+    use crate::types::PoStConfig;
 
     static INIT_LOGGER: Once = Once::new();
     fn init_logger() {
@@ -298,6 +655,14 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_self_test() {
+        self_test(SectorSize(4 * 128)).expect("self test failed");
+        self_test(SectorSize(SECTOR_SIZE_ONE_KIB)).expect("self test failed");
+
+        assert!(self_test(SectorSize(128)).is_err());
+    }
+
     #[test]
     fn test_verify_seal_fr32_validation() {
         let convertible_to_fr_bytes = [0; 32];
@@ -365,6 +730,374 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_comm_d_from_files() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 64);
+
+        let files = [100, 300]
+            .iter()
+            .map(|len| {
+                let mut file = NamedTempFile::new().unwrap();
+                let contents = (0..*len).map(|_| rng.gen()).collect::<Vec<u8>>();
+                file.write_all(&contents).unwrap();
+                file
+            })
+            .collect::<Vec<_>>();
+        let paths = files.iter().map(|file| file.path()).collect::<Vec<_>>();
+
+        let (comm_d, piece_infos) = comm_d_from_files(&paths, sector_size).unwrap();
+        assert_eq!(
+            piece_infos.iter().map(|info| info.size).collect::<Vec<_>>(),
+            vec![UnpaddedBytesAmount(127), UnpaddedBytesAmount(508)]
+        );
+
+        // Stage the zero padded files into a buffer through `add_piece`.
+        let mut staged = Cursor::new(Vec::new());
+        let mut piece_lengths = Vec::new();
+        for (path, piece_info) in paths.iter().zip(piece_infos.iter()) {
+            let source = File::open(path)
+                .unwrap()
+                .chain(io::repeat(0))
+                .take(u64::from(piece_info.size));
+            let (_, commitment) =
+                add_piece(source, &mut staged, piece_info.size, &piece_lengths).unwrap();
+
+            assert_eq!(commitment, piece_info.commitment);
+            piece_lengths.push(piece_info.size);
+        }
+
+        let mut sector = staged.into_inner();
+        sector.resize(u64::from(sector_size) as usize, 0);
+        let staged_comm_d = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+            &mut Cursor::new(&sector),
+        )
+        .unwrap();
+
+        assert_eq!(comm_d, staged_comm_d);
+    }
+
+    #[test]
+    fn test_generate_piece_commitment_streaming() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let piece_size = UnpaddedBytesAmount(127 * 4096);
+
+        let mut data = vec![0u8; u64::from(piece_size) as usize];
+        rng.fill_bytes(&mut data);
+
+        let mut reports = Vec::new();
+        let streamed =
+            generate_piece_commitment_streaming(Cursor::new(&data), piece_size, |done, total| {
+                reports.push((done, total))
+            })
+            .unwrap();
+
+        let expected = generate_piece_commitment(Cursor::new(&data), piece_size).unwrap();
+        assert_eq!(streamed, expected);
+
+        // The piece spans several chunks, each of which is reported.
+        assert!(reports.len() > 1);
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(reports
+            .iter()
+            .all(|(_, total)| *total == u64::from(piece_size)));
+        assert_eq!(
+            reports.last(),
+            Some(&(u64::from(piece_size), u64::from(piece_size)))
+        );
+    }
+
+    #[test]
+    fn test_verify_comm_d_consistency() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 128);
+
+        // Lengths around the 127 byte units which pad to whole 128 byte units of fr32 elements,
+        // and around single fr32 elements within them.
+        for len in &[1, 31, 32, 126, 127, 128, 253, 254, 255, 1000, 127 * 32] {
+            let mut data = vec![0u8; *len];
+            rng.fill_bytes(&mut data);
+
+            assert!(
+                verify_comm_d_consistency(
+                    Cursor::new(&data),
+                    UnpaddedBytesAmount(*len as u64),
+                    sector_size
+                )
+                .unwrap(),
+                "len {}",
+                len
+            );
+        }
+
+        assert!(verify_comm_d_consistency(
+            Cursor::new(vec![1u8; 10]),
+            UnpaddedBytesAmount(11),
+            sector_size
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_partial_comm_d() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(1024 * 128);
+        let unpadded_sector = u64::from(UnpaddedBytesAmount::from(sector_size)) as usize;
+
+        for written_len in &[0, 1, 127, 127 * 3 + 50, 127 * 1000, unpadded_sector] {
+            let mut sector = vec![0u8; unpadded_sector];
+            rng.fill_bytes(&mut sector[..*written_len]);
+
+            let expected = generate_piece_commitment(
+                Cursor::new(&sector),
+                UnpaddedBytesAmount::from(sector_size),
+            )
+            .unwrap()
+            .commitment;
+
+            let partial = partial_comm_d(
+                Cursor::new(&sector[..*written_len]),
+                UnpaddedBytesAmount(*written_len as u64),
+                sector_size,
+            )
+            .unwrap();
+
+            assert_eq!(partial, expected, "written_len {}", written_len);
+        }
+
+        assert!(partial_comm_d(
+            Cursor::new(vec![0u8; 10]),
+            UnpaddedBytesAmount(20),
+            sector_size
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_generate_piece_commitment_sparse() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for piece_size in &[127, 127 * 64, 127 * 2048] {
+            let piece_size = UnpaddedBytesAmount(*piece_size);
+            let len = u64::from(piece_size) as usize;
+
+            // Random data at both ends of the piece, with a large zero run in between.
+            let mut data = vec![0u8; len];
+            let edge = std::cmp::min(len / 2, 300);
+            rng.fill_bytes(&mut data[..edge]);
+            rng.fill_bytes(&mut data[len - edge / 2..]);
+
+            let dense = generate_piece_commitment(Cursor::new(&data), piece_size).unwrap();
+            let sparse = generate_piece_commitment_sparse(Cursor::new(&data), piece_size).unwrap();
+            assert_eq!(sparse, dense);
+
+            let zeros = vec![0u8; len];
+            let dense = generate_piece_commitment(Cursor::new(&zeros), piece_size).unwrap();
+            let sparse = generate_piece_commitment_sparse(Cursor::new(&zeros), piece_size).unwrap();
+            assert_eq!(sparse, dense);
+        }
+
+        assert!(generate_piece_commitment_sparse(
+            Cursor::new(vec![0u8; 127]),
+            UnpaddedBytesAmount(254)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_comm_d_interleaved() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 64);
+        let chunk_nodes = 8;
+
+        let data = [127 * 6, 127 * 3]
+            .iter()
+            .map(|len| (0..*len).map(|_| rng.gen()).collect::<Vec<u8>>())
+            .collect::<Vec<_>>();
+
+        let sources = data
+            .iter()
+            .map(|data| {
+                (
+                    Box::new(Cursor::new(data.clone())) as Box<dyn Read>,
+                    UnpaddedBytesAmount(data.len() as u64),
+                )
+            })
+            .collect();
+        let comm_d = comm_d_interleaved(sources, sector_size, chunk_nodes).unwrap();
+
+        // Pad the sources as a whole, and interleave their padded chunks explicitly:
+        // a0 b0 a1 b1 a2, where b1 is half a chunk.
+        let padded = data
+            .iter()
+            .map(|data| {
+                let mut padded = Vec::new();
+                write_padded(&data[..], Cursor::new(&mut padded)).unwrap();
+                padded
+            })
+            .collect::<Vec<_>>();
+        let chunk = chunk_nodes * 32;
+
+        let mut sector = Vec::new();
+        sector.extend_from_slice(&padded[0][..chunk]);
+        sector.extend_from_slice(&padded[1][..chunk]);
+        sector.extend_from_slice(&padded[0][chunk..2 * chunk]);
+        sector.extend_from_slice(&padded[1][chunk..]);
+        sector.extend_from_slice(&padded[0][2 * chunk..]);
+        sector.resize(u64::from(sector_size) as usize, 0);
+
+        let expected = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+            &mut Cursor::new(&sector),
+        )
+        .unwrap();
+        assert_eq!(comm_d, expected);
+
+        // A source which ends early is an error.
+        let short = vec![(
+            Box::new(Cursor::new(vec![0u8; 127])) as Box<dyn Read>,
+            UnpaddedBytesAmount(254),
+        )];
+        assert!(comm_d_interleaved(short, sector_size, chunk_nodes).is_err());
+    }
+
+    #[test]
+    fn test_sealed_sector_footprint() {
+        use crate::constants::WINDOW_SIZE_NODES_ONE_KIB;
+        use crate::parameters::setup_params;
+
+        let porep_config = PoRepConfig {
+            sector_size: SectorSize(SECTOR_SIZE_ONE_KIB),
+            partitions: PoRepProofPartitions(2),
+        };
+        let sector_bytes = SECTOR_SIZE_ONE_KIB;
+        let layers = setup_params(PaddedBytesAmount::from(porep_config), 2)
+            .config
+            .layers() as u64;
+        let window_bytes = WINDOW_SIZE_NODES_ONE_KIB as u64 * 32;
+        let footprint = |level| sealed_sector_footprint(porep_config, level).unwrap();
+
+        // The replica and layer labels, plus the base layers of tree_d, tree_q, tree_r_last and
+        // tree_c.
+        assert_eq!(
+            footprint(0),
+            (1 + layers) * sector_bytes + 3 * sector_bytes + window_bytes
+        );
+
+        let mut previous = 0;
+        for level in 0..=5 {
+            assert!(footprint(level) > previous);
+            previous = footprint(level);
+        }
+
+        // A 1KiB sector has 32 leafs, so there is nothing to cache above the root.
+        assert_eq!(footprint(5), footprint(10));
+        // Full trees hold just under twice their base layer.
+        assert_eq!(
+            footprint(5),
+            (1 + layers) * sector_bytes + 3 * (2 * sector_bytes - 32) + (2 * window_bytes - 32)
+        );
+
+        // Sector sizes without a window size are rejected.
+        let unknown = PoRepConfig {
+            sector_size: SectorSize(2048),
+            ..porep_config
+        };
+        assert!(sealed_sector_footprint(unknown, 0).is_err());
+    }
+
+    #[test]
+    fn test_cc_comm_r() {
+        use storage_proofs::drgraph::{new_seed, BASE_DEGREE};
+        use storage_proofs::hasher::{Domain, PedersenHasher};
+        use storage_proofs::parameter_cache::ParameterSetMetadata;
+        use storage_proofs::porep::PoRep;
+        use storage_proofs::proof::ProofScheme;
+        use storage_proofs::stacked::{SetupParams, StackedConfig, EXP_DEGREE};
+
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let nodes = 64;
+        let sector_size = SectorSize(nodes as u64 * 32);
+
+        let sp = SetupParams {
+            nodes,
+            degree: BASE_DEGREE,
+            expansion_degree: EXP_DEGREE,
+            seed: new_seed(),
+            config: StackedConfig::new(4, 1, 1),
+            window_size_nodes: nodes / 2,
+        };
+        let pp = StackedDrg::<PedersenHasher, DefaultPieceHasher>::setup(&sp).unwrap();
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::new(
+            cache_dir.path(),
+            CacheKey::CommDTree.to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+        let mut data = vec![0u8; nodes * 32];
+        let (tau, _) = StackedDrg::<PedersenHasher, DefaultPieceHasher>::replicate(
+            &pp,
+            &replica_id,
+            &mut data,
+            None,
+            Some(config),
+        )
+        .unwrap();
+
+        let comm_r = cc_comm_r(sector_size, &replica_id, &pp, false).unwrap();
+        assert_eq!(comm_r, tau.comm_r);
+
+        // The cached value is returned on the second call.
+        assert_eq!(
+            cc_comm_r(sector_size, &replica_id, &pp, true).unwrap(),
+            comm_r
+        );
+        let hits_before = seal::CC_COMM_R_CACHE_HITS.load(Ordering::SeqCst);
+        assert_eq!(
+            cc_comm_r(sector_size, &replica_id, &pp, true).unwrap(),
+            comm_r
+        );
+        assert!(seal::CC_COMM_R_CACHE_HITS.load(Ordering::SeqCst) > hits_before);
+
+        // Params differing only in their graph seed share the identifier, but not the cache entry.
+        let other_pp = StackedDrg::<PedersenHasher, DefaultPieceHasher>::setup(&SetupParams {
+            seed: new_seed(),
+            ..sp
+        })
+        .unwrap();
+        assert_eq!(other_pp.identifier(), pp.identifier());
+        assert_ne!(
+            cc_comm_r(sector_size, &replica_id, &other_pp, true).unwrap(),
+            comm_r
+        );
+
+        let other_replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        assert_ne!(
+            cc_comm_r(sector_size, &other_replica_id, &pp, true).unwrap(),
+            comm_r
+        );
+
+        assert!(cc_comm_r(SectorSize(nodes as u64 * 64), &replica_id, &pp, false).is_err());
+    }
+
+    #[test]
+    fn test_add_piece_short_source() {
+        let piece_size = UnpaddedBytesAmount(127);
+        let source = std::io::Cursor::new(vec![1u8; 100]);
+        let target = tempfile().unwrap();
+
+        let err = add_piece(source, target, piece_size, &[]).unwrap_err();
+
+        match err.downcast_ref::<Error>() {
+            Some(Error::PieceShorterThanDeclared { expected, actual }) => {
+                assert_eq!(*expected, piece_size);
+                assert_eq!(*actual, UnpaddedBytesAmount(100));
+            }
+            _ => panic!("unexpected error: {:?}", err),
+        }
+    }
+
     #[test]
     #[ignore]
     fn test_verify_post_fr32_validation() {
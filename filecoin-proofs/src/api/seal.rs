@@ -1,38 +1,155 @@
+use std::collections::HashMap;
 use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::path::Path;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use anyhow::Result;
 use bincode::{deserialize, serialize};
 use memmap::MmapOptions;
 use merkletree::store::{StoreConfig, DEFAULT_CACHED_ABOVE_BASE_LAYER};
-use paired::bls12_381::{Bls12, Fr};
+use paired::bls12_381::Fr;
 use storage_proofs::circuit::multi_proof::MultiProof;
 use storage_proofs::circuit::stacked::StackedCompound;
 use storage_proofs::compound_proof::{self, CompoundProof};
 use storage_proofs::drgraph::{DefaultTreeHasher, Graph};
 use storage_proofs::hasher::{Domain, Hasher};
 use storage_proofs::merkle::create_merkle_tree;
+use storage_proofs::parameter_cache::ParameterSetMetadata;
 use storage_proofs::porep::PoRep;
 use storage_proofs::sector::SectorId;
 use storage_proofs::stacked::{
     self, generate_replica_id, CacheKey, ChallengeRequirements, StackedDrg, Tau, TemporaryAux,
     TemporaryAuxCache,
 };
+use storage_proofs::util::NODE_SIZE;
 
-use crate::api::util::{as_safe_commitment, commitment_from_fr};
+use crate::api::util::{as_safe_commitment, commitment_from_bls_fr};
 use crate::caches::{get_stacked_params, get_stacked_verifying_key};
 use crate::constants::{
     DefaultPieceHasher, POREP_WINDOW_MINIMUM_CHALLENGES, SINGLE_PARTITION_PROOF_LEN,
 };
-use crate::parameters::setup_params;
+use crate::parameters::{setup_params, window_size_nodes_for_sector_bytes};
 pub use crate::pieces;
-pub use crate::pieces::verify_pieces;
+pub use crate::pieces::{
+    clear_verify_cache, set_verify_cache_capacity, shared_pieces, verify_pieces,
+    verify_pieces_detailed, verify_pieces_raw, PieceVerification,
+};
 use crate::types::{
     Commitment, PaddedBytesAmount, PieceInfo, PoRepConfig, PoRepProofPartitions, ProverId,
-    SealCommitOutput, SealPreCommitOutput, Ticket,
+    SealCommitOutput, SealPreCommitOutput, SectorSize, Ticket,
 };
 
+/// Key of a CC sector `comm_r`: hasher name, public params identifier, window and wrapper graph
+/// seeds, sector size and replica id. The seeds are not part of the identifier, but change the
+/// graphs and so the replica.
+type CcCommRKey = (String, String, [u8; 28], [u8; 28], u64, Vec<u8>);
+type CcCommRCache = HashMap<CcCommRKey, Vec<u8>>;
+
+lazy_static! {
+    static ref CC_COMM_R_CACHE: Mutex<CcCommRCache> = Default::default();
+}
+
+#[cfg(test)]
+pub(crate) static CC_COMM_R_CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Computes the `comm_r` of a committed capacity sector, i.e. a sector sealed from all zero data,
+/// by replicating it. With `use_cache`, results are remembered per public params, sector size and
+/// replica id and graph seeds.
+pub fn cc_comm_r<H: 'static + Hasher>(
+    sector_size: SectorSize,
+    replica_id: &H::Domain,
+    pp: &stacked::PublicParams<H>,
+    use_cache: bool,
+) -> Result<H::Domain> {
+    let sector_bytes = u64::from(sector_size);
+    ensure!(
+        sector_bytes == pp.layer_size() as u64,
+        "sector size {} does not match the public params ({})",
+        sector_bytes,
+        pp.layer_size()
+    );
+
+    let key = (
+        H::name(),
+        pp.identifier(),
+        pp.window_graph.seed(),
+        pp.wrapper_graph.seed(),
+        sector_bytes,
+        replica_id.into_bytes(),
+    );
+
+    if use_cache {
+        if let Some(comm_r) = CC_COMM_R_CACHE.lock().unwrap().get(&key) {
+            #[cfg(test)]
+            CC_COMM_R_CACHE_HITS.fetch_add(1, Ordering::SeqCst);
+
+            return H::Domain::try_from_bytes(comm_r);
+        }
+    }
+
+    let cache_dir = tempfile::tempdir()?;
+    let config = StoreConfig::new(
+        cache_dir.path(),
+        CacheKey::CommDTree.to_string(),
+        DEFAULT_CACHED_ABOVE_BASE_LAYER,
+    );
+
+    let mut data = vec![0u8; sector_bytes as usize];
+    let (tau, _) = StackedDrg::<H, DefaultPieceHasher>::replicate(
+        pp,
+        replica_id,
+        &mut data,
+        None,
+        Some(config),
+    )?;
+
+    if use_cache {
+        CC_COMM_R_CACHE
+            .lock()
+            .unwrap()
+            .insert(key, tau.comm_r.into_bytes());
+    }
+
+    Ok(tau.comm_r)
+}
+
+/// Number of bytes a sealed sector occupies on disk: the replica, plus every store `replicate`
+/// persists to the cache directory, i.e. the labels of each layer, `tree_d`, `tree_q`,
+/// `tree_r_last` and `tree_c`.
+///
+/// Each tree is assumed to store its base layer and the `cached_above_base_layer` layers above it,
+/// the layers above those being recomputed when needed. `tree_c` has one leaf per column, i.e. per
+/// node of a window, all other trees one leaf per node of the sector.
+pub fn sealed_sector_footprint(
+    porep_config: PoRepConfig,
+    cached_above_base_layer: usize,
+) -> Result<u64> {
+    let sector_bytes = PaddedBytesAmount::from(porep_config);
+    let window_size_nodes = window_size_nodes_for_sector_bytes(sector_bytes)?;
+    let layers = setup_params(sector_bytes, usize::from(porep_config.partitions))
+        .config
+        .layers() as u64;
+
+    let sector_bytes = u64::from(sector_bytes);
+    let tree_bytes = |leafs: u64| -> u64 {
+        let height = leafs.trailing_zeros() as usize;
+        (0..=std::cmp::min(cached_above_base_layer, height))
+            .map(|layer| (leafs >> layer) * NODE_SIZE as u64)
+            .sum()
+    };
+    let sector_tree_bytes = tree_bytes(sector_bytes / NODE_SIZE as u64);
+
+    // replica and layer labels
+    let data_bytes = sector_bytes + layers * sector_bytes;
+    // tree_d, tree_q and tree_r_last, plus tree_c
+    let trees_bytes = 3 * sector_tree_bytes + tree_bytes(window_size_nodes as u64);
+
+    Ok(data_bytes + trees_bytes)
+}
+
 /// Seals the staged sector at `in_path` in place, saving the resulting replica to `out_path`.
 #[allow(clippy::too_many_arguments)]
 pub fn seal_pre_commit<R: AsRef<Path>, T: AsRef<Path>, S: AsRef<Path>>(
@@ -103,7 +220,7 @@ pub fn seal_pre_commit<R: AsRef<Path>, T: AsRef<Path>, S: AsRef<Path>>(
     )?;
 
     let comm_d_root: Fr = data_tree.root().into();
-    let comm_d = commitment_from_fr::<Bls12>(comm_d_root);
+    let comm_d = commitment_from_bls_fr(comm_d_root);
 
     ensure!(
         verify_pieces(&comm_d, piece_infos, porep_config.into())?,
@@ -125,7 +242,7 @@ pub fn seal_pre_commit<R: AsRef<Path>, T: AsRef<Path>, S: AsRef<Path>>(
         Some(config),
     )?;
 
-    let comm_r = commitment_from_fr::<Bls12>(tau.comm_r.into());
+    let comm_r = commitment_from_bls_fr(tau.comm_r.into());
 
     info!("seal_pre_commit: end");
 
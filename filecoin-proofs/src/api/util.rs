@@ -1,5 +1,5 @@
 use anyhow::Result;
-use paired::bls12_381::Bls12;
+use paired::bls12_381::{Bls12, Fr};
 use paired::Engine;
 use storage_proofs::fr32::{bytes_into_fr, fr_into_bytes};
 use storage_proofs::hasher::Domain;
@@ -10,7 +10,7 @@ pub(crate) fn as_safe_commitment<H: Domain, T: AsRef<str>>(
     comm: &Commitment,
     commitment_name: T,
 ) -> Result<H> {
-    bytes_into_fr::<Bls12>(comm).map(Into::into).map_err(|err| {
+    bls_fr_from_commitment(comm).map(Into::into).map_err(|err| {
         format_err!(
             "Invalid commitment ({}): {:?}",
             commitment_name.as_ref(),
@@ -26,3 +26,36 @@ pub(crate) fn commitment_from_fr<E: Engine>(fr: E::Fr) -> Commitment {
     }
     commitment
 }
+
+/// Convert a BLS12-381 field element into its commitment bytes.
+pub(crate) fn commitment_from_bls_fr(fr: Fr) -> Commitment {
+    commitment_from_fr::<Bls12>(fr)
+}
+
+/// Convert commitment bytes into a BLS12-381 field element, failing if they are not a valid one.
+pub(crate) fn bls_fr_from_commitment(comm: &Commitment) -> Result<Fr> {
+    Ok(bytes_into_fr::<Bls12>(comm)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    #[test]
+    fn test_bls_fr_commitment_roundtrip() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for _ in 0..10 {
+            let fr: Fr = rng.gen();
+            let commitment = commitment_from_bls_fr(fr);
+
+            assert_eq!(commitment, commitment_from_fr::<Bls12>(fr));
+            assert_eq!(bls_fr_from_commitment(&commitment).unwrap(), fr);
+        }
+
+        assert!(bls_fr_from_commitment(&[0xff; 32]).is_err());
+    }
+}
@@ -1,31 +1,205 @@
 #[macro_use]
 extern crate log;
 
-use clap::{values_t, App, Arg};
+use anyhow::{Context, Result};
+use bellperson::Circuit;
+use clap::{value_t, values_t, App, Arg};
+use fil_sapling_crypto::jubjub::JubjubEngine;
 use paired::bls12_381::Bls12;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use filecoin_proofs::constants::*;
-use filecoin_proofs::parameters::{post_public_params, public_params};
+use filecoin_proofs::parameters::{
+    estimate_param_gen_time, measure_param_gen_nanos_per_constraint, post_public_params,
+    public_params,
+};
 use filecoin_proofs::types::*;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use storage_proofs::circuit::election_post::{ElectionPoStCircuit, ElectionPoStCompound};
 use storage_proofs::circuit::stacked::StackedCompound;
 use storage_proofs::compound_proof::CompoundProof;
 use storage_proofs::election_post::ElectionPoSt;
 use storage_proofs::hasher::pedersen::PedersenHasher;
-use storage_proofs::parameter_cache::CacheableParameters;
+use storage_proofs::parameter_cache::{
+    parameter_cache_dir, parameter_cache_metadata_path, parameter_cache_params_path,
+    parameter_cache_verifying_key_path, CacheableParameters, ParameterSetMetadata,
+    GROTH_PARAMETER_EXT, VERIFYING_KEY_EXT,
+};
 use storage_proofs::stacked::StackedDrg;
 
-const POREP_PROOF_PARTITION_CHOICES: [PoRepProofPartitions; 1] = [PoRepProofPartitions(2)];
+/// Name of the manifest in the content addressed directory.
+const MANIFEST_FILE: &str = "manifest.json";
 
-const PUBLISHED_SECTOR_SIZES: [u64; 4] = [
-    SECTOR_SIZE_ONE_KIB,
-    SECTOR_SIZE_16_MIB,
-    SECTOR_SIZE_256_MIB,
-    SECTOR_SIZE_1_GIB,
-];
+/// Content hashes of the parameters for one sector size.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ManifestEntry {
+    porep: String,
+    post: String,
+}
+
+/// Maps sector sizes to the content hashes of their parameters.
+type Manifest = BTreeMap<u64, ManifestEntry>;
+
+/// Returns the identifier under which `CP` caches the parameters for `circuit`.
+fn cache_identifier<E, C, P, CP>(_circuit: &C, pub_params: &P) -> String
+where
+    E: JubjubEngine,
+    C: Circuit<E>,
+    P: ParameterSetMetadata,
+    CP: CacheableParameters<E, C, P>,
+{
+    CP::cache_identifier(pub_params)
+}
+
+/// The cached parameter, verifying key and metadata files for `cache_id`.
+fn cache_files(cache_id: &str) -> Vec<PathBuf> {
+    vec![
+        parameter_cache_params_path(cache_id),
+        parameter_cache_verifying_key_path(cache_id),
+        parameter_cache_metadata_path(cache_id),
+    ]
+}
+
+/// Whether the parameter, verifying key and metadata files, as returned by `cache_files`, all
+/// exist and look complete. The parameters start with the verifying key, so a parameter file which
+/// is not larger than the verifying key must have been truncated. The parameters are ignored if
+/// only verifying keys are generated.
+fn cache_files_complete(files: &[PathBuf], verifying_keys_only: bool) -> bool {
+    let len = |file: &PathBuf| fs::metadata(file).map(|meta| meta.len()).ok();
+
+    match files {
+        [params, vk, meta] => match (len(vk), len(meta)) {
+            (Some(vk), Some(meta)) if vk > 0 && meta > 0 => {
+                verifying_keys_only || len(params).map_or(false, |params| params > vk)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// A parameter or verifying key file produced by a run, as written to `--manifest`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileRecord {
+    sector_size: u64,
+    /// Either `params` or `vk`.
+    kind: String,
+    identifier: String,
+    path: PathBuf,
+    sha256: String,
+    byte_len: u64,
+}
+
+impl FileRecord {
+    /// Describe the file at `path`, hashing it in chunks so multi-GiB parameters are not read into
+    /// memory at once.
+    fn new(sector_size: u64, kind: &str, identifier: &str, path: PathBuf) -> Result<Self> {
+        let mut file =
+            File::open(&path).with_context(|| format!("could not open {}", path.display()))?;
+
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 1 << 20];
+        let mut byte_len = 0;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.input(&buf[..n]);
+            byte_len += n as u64;
+        }
+
+        Ok(FileRecord {
+            sector_size,
+            kind: kind.to_string(),
+            identifier: identifier.to_string(),
+            path,
+            sha256: hex::encode(hasher.result()),
+            byte_len,
+        })
+    }
+}
+
+/// Describe the parameter and verifying key files of every generated parameter set. Parameter
+/// files which were not generated, because only verifying keys were requested, are left out.
+fn file_records(ids: &BTreeMap<ParamSet, String>) -> Result<Vec<FileRecord>> {
+    let mut records = Vec::new();
+    for (param_set, id) in ids {
+        for (kind, path) in &[
+            (GROTH_PARAMETER_EXT, parameter_cache_params_path(id)),
+            (VERIFYING_KEY_EXT, parameter_cache_verifying_key_path(id)),
+        ] {
+            if path.exists() {
+                records.push(FileRecord::new(
+                    param_set.sector_size(),
+                    kind,
+                    id,
+                    path.clone(),
+                )?);
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+/// How parameter sets are generated.
+#[derive(Debug, Clone, Copy)]
+struct CacheOptions {
+    /// Skip parameter sets whose files are already cached.
+    skip_existing: bool,
+    /// Only cache the verifying keys and metadata, not the Groth parameters.
+    verifying_keys_only: bool,
+}
+
+/// Copy `files` into `out_dir`, named by the parameter set hash of `cache_id` and their original
+/// extension, and return that hash.
+fn content_address(out_dir: &Path, cache_id: &str, files: &[PathBuf]) -> Result<String> {
+    // Cache identifiers end in the hash of the parameter set identifier.
+    let hash = cache_id
+        .rsplit('-')
+        .next()
+        .filter(|hash| !hash.is_empty())
+        .ok_or_else(|| anyhow::anyhow!("invalid cache identifier: {}", cache_id))?
+        .to_string();
+
+    fs::create_dir_all(out_dir)?;
+    for file in files {
+        let ext = file
+            .extension()
+            .ok_or_else(|| anyhow::anyhow!("{} has no extension", file.display()))?;
+        let target = out_dir.join(&hash).with_extension(ext);
+        fs::copy(file, &target).with_context(|| {
+            format!("could not copy {} to {}", file.display(), target.display())
+        })?;
+    }
+
+    Ok(hash)
+}
+
+/// Merge `entries` into the manifest in `out_dir`, replacing the entries of the same sector sizes.
+fn update_manifest(out_dir: &Path, entries: Manifest) -> Result<()> {
+    let path = out_dir.join(MANIFEST_FILE);
+
+    let mut manifest: Manifest = if path.exists() {
+        serde_json::from_reader(File::open(&path)?)
+            .with_context(|| format!("could not parse {}", path.display()))?
+    } else {
+        Manifest::new()
+    };
+    manifest.extend(entries);
+
+    serde_json::to_writer_pretty(File::create(&path)?, &manifest)?;
+
+    Ok(())
+}
 
-fn cache_porep_params(porep_config: PoRepConfig) {
+fn cache_porep_params(porep_config: PoRepConfig, opts: CacheOptions) -> String {
     let n = u64::from(PaddedBytesAmount::from(porep_config));
     info!(
         "begin PoRep parameter-cache check/populate routine for {}-byte sectors",
@@ -37,6 +211,20 @@ fn cache_porep_params(porep_config: PoRepConfig) {
         usize::from(PoRepProofPartitions::from(porep_config)),
     );
 
+    let cache_id = {
+        let circuit = <StackedCompound as CompoundProof<
+            _,
+            StackedDrg<DefaultTreeHasher, DefaultPieceHasher>,
+            _,
+        >>::blank_circuit(&public_params);
+        cache_identifier::<_, _, _, StackedCompound>(&circuit, &public_params)
+    };
+    if opts.skip_existing && cache_files_complete(&cache_files(&cache_id), opts.verifying_keys_only)
+    {
+        info!("skipping PoRep parameters {}, already cached", cache_id);
+        return cache_id;
+    }
+
     {
         let circuit = <StackedCompound as CompoundProof<
             _,
@@ -45,7 +233,7 @@ fn cache_porep_params(porep_config: PoRepConfig) {
         >>::blank_circuit(&public_params);
         let _ = StackedCompound::get_param_metadata(circuit, &public_params);
     }
-    {
+    if !opts.verifying_keys_only {
         let circuit = <StackedCompound as CompoundProof<
             _,
             StackedDrg<DefaultTreeHasher, DefaultPieceHasher>,
@@ -59,11 +247,17 @@ fn cache_porep_params(porep_config: PoRepConfig) {
             StackedDrg<DefaultTreeHasher, DefaultPieceHasher>,
             _,
         >>::blank_circuit(&public_params);
-        let _ = StackedCompound::get_verifying_key(circuit, &public_params);
+        let _ = if opts.verifying_keys_only {
+            StackedCompound::get_verifying_key_only(circuit, &public_params)
+        } else {
+            StackedCompound::get_verifying_key(circuit, &public_params)
+        };
     }
+
+    cache_id
 }
 
-fn cache_post_params(post_config: PoStConfig) {
+fn cache_post_params(post_config: PoStConfig, opts: CacheOptions) -> String {
     let n = u64::from(PaddedBytesAmount::from(post_config));
     info!(
         "begin PoSt parameter-cache check/populate routine for {}-byte sectors",
@@ -72,6 +266,24 @@ fn cache_post_params(post_config: PoStConfig) {
 
     let post_public_params = post_public_params(post_config);
 
+    let cache_id = {
+        let post_circuit: ElectionPoStCircuit<Bls12, PedersenHasher> =
+            <ElectionPoStCompound<PedersenHasher> as CompoundProof<
+                Bls12,
+                ElectionPoSt<PedersenHasher>,
+                ElectionPoStCircuit<Bls12, PedersenHasher>,
+            >>::blank_circuit(&post_public_params);
+        cache_identifier::<_, _, _, ElectionPoStCompound<PedersenHasher>>(
+            &post_circuit,
+            &post_public_params,
+        )
+    };
+    if opts.skip_existing && cache_files_complete(&cache_files(&cache_id), opts.verifying_keys_only)
+    {
+        info!("skipping PoSt parameters {}, already cached", cache_id);
+        return cache_id;
+    }
+
     {
         let post_circuit: ElectionPoStCircuit<Bls12, PedersenHasher> =
             <ElectionPoStCompound<PedersenHasher> as CompoundProof<
@@ -85,7 +297,7 @@ fn cache_post_params(post_config: PoStConfig) {
         )
         .expect("failed to get metadata");
     }
-    {
+    if !opts.verifying_keys_only {
         let post_circuit: ElectionPoStCircuit<Bls12, PedersenHasher> =
             <ElectionPoStCompound<PedersenHasher> as CompoundProof<
                 Bls12,
@@ -106,12 +318,84 @@ fn cache_post_params(post_config: PoStConfig) {
                 ElectionPoStCircuit<Bls12, PedersenHasher>,
             >>::blank_circuit(&post_public_params);
 
-        let _ = <ElectionPoStCompound<PedersenHasher>>::get_verifying_key(
-            post_circuit,
-            &post_public_params,
-        )
+        let _ = if opts.verifying_keys_only {
+            <ElectionPoStCompound<PedersenHasher>>::get_verifying_key_only(
+                post_circuit,
+                &post_public_params,
+            )
+        } else {
+            <ElectionPoStCompound<PedersenHasher>>::get_verifying_key(
+                post_circuit,
+                &post_public_params,
+            )
+        }
         .expect("failed to get verifying key");
     }
+
+    cache_id
+}
+
+/// An independently generated set of parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum ParamSet {
+    /// Sector size and number of partitions.
+    PoRep(u64, u8),
+    PoSt(u64),
+}
+
+impl ParamSet {
+    fn sector_size(self) -> u64 {
+        match self {
+            ParamSet::PoRep(sector_size, _) | ParamSet::PoSt(sector_size) => sector_size,
+        }
+    }
+
+    /// Generate the parameters unless already cached, and return their cache identifier.
+    fn cache(self, opts: CacheOptions) -> String {
+        match self {
+            ParamSet::PoRep(sector_size, partitions) => {
+                let porep_config =
+                    PoRepConfig::new(SectorSize(sector_size), PoRepProofPartitions(partitions))
+                        .expect("invalid PoRep configuration");
+                cache_porep_params(porep_config, opts)
+            }
+            ParamSet::PoSt(sector_size) => {
+                let post_config =
+                    PoStConfig::new(SectorSize(sector_size)).expect("invalid PoSt configuration");
+                cache_post_params(post_config, opts)
+            }
+        }
+    }
+}
+
+/// The default number of PoRep partitions for `sector_size`.
+fn default_partitions(sector_size: u64) -> u8 {
+    default_partitions_for(sector_size)
+        .unwrap_or(DEFAULT_POREP_PROOF_PARTITIONS)
+        .0
+}
+
+/// Validate a `--partitions` value, which must fit `PoRepProofPartitions` and be non-zero.
+fn validate_partitions(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u8>() {
+        Ok(partitions) if partitions > 0 => Ok(()),
+        _ => Err(format!(
+            "partitions must be between 1 and {}, got {}",
+            u8::max_value(),
+            value
+        )),
+    }
+}
+
+/// Validate a `--params-for-sector-sizes` value, which must be one of `PUBLISHED_SECTOR_SIZES`.
+fn validate_sector_size(value: String) -> std::result::Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(sector_size) if PUBLISHED_SECTOR_SIZES.contains(&sector_size) => Ok(()),
+        _ => Err(format!(
+            "sector size must be one of {:?}, got {}",
+            PUBLISHED_SECTOR_SIZES, value
+        )),
+    }
 }
 
 // Run this from the command-line to pre-generate the groth parameters used by the API.
@@ -129,8 +413,53 @@ pub fn main() {
                 .require_delimiter(true)
                 .value_delimiter(",")
                 .multiple(true)
+                .validator(validate_sector_size)
                 .help("A comma-separated list of sector sizes, in bytes, for which Groth parameters will be generated")
         )
+        .arg(
+            Arg::with_name("partitions")
+                .long("partitions")
+                .conflicts_with_all(&["content-addressed", "estimate"])
+                .require_delimiter(true)
+                .value_delimiter(",")
+                .multiple(true)
+                .validator(validate_partitions)
+                .help("A comma-separated list of PoRep partition counts for which parameters will be generated, instead of the default for each sector size")
+        )
+        .arg(
+            Arg::with_name("estimate")
+                .long("estimate")
+                .help("Print an estimate of how long generating the parameters takes on this machine, measured on a small circuit, instead of generating them")
+        )
+        .arg(
+            Arg::with_name("content-addressed")
+                .long("content-addressed")
+                .help("Also copy the generated files into the content-addressed directory of the parameter cache, named by the hash of their parameter set, and record them in its manifest.json")
+        )
+        .arg(
+            Arg::with_name("skip-existing")
+                .long("skip-existing")
+                .help("Skip generating the parameters of a circuit if its parameter, verifying key and metadata files are already cached and not truncated")
+        )
+        .arg(
+            Arg::with_name("verifying-keys-only")
+                .long("verifying-keys-only")
+                .conflicts_with("content-addressed")
+                .help("Only generate and persist the verifying keys and metadata, e.g. for nodes which verify but never prove")
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .long("manifest")
+                .takes_value(true)
+                .help("Write a JSON list of the generated parameter and verifying key files, with their sha256 digests and lengths, to the given path")
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .default_value("1")
+                .help("How many parameter sets to generate concurrently")
+        )
         .get_matches();
 
     let sizes: HashSet<u64> = if matches.is_present("params-for-sector-sizes") {
@@ -142,16 +471,244 @@ pub fn main() {
         PUBLISHED_SECTOR_SIZES.iter().cloned().collect()
     };
 
-    for sector_size in sizes {
-        cache_post_params(PoStConfig {
-            sector_size: SectorSize(sector_size),
-        });
+    if matches.is_present("estimate") {
+        let mut sizes: Vec<u64> = sizes.into_iter().collect();
+        sizes.sort();
+        let partitions: Vec<_> = sizes
+            .iter()
+            .map(|size| PoRepProofPartitions(default_partitions(*size)))
+            .collect();
+
+        let nanos_per_constraint = measure_param_gen_nanos_per_constraint()
+            .expect("failed to measure parameter generation time");
+        let estimate = estimate_param_gen_time(&sizes, &partitions, nanos_per_constraint)
+            .expect("failed to estimate parameter generation time");
+        println!(
+            "generating parameters for sector sizes {:?} takes about {} minutes on this machine",
+            sizes,
+            estimate.as_secs() / 60
+        );
+        return;
+    }
+
+    let content_addressed_dir = if matches.is_present("content-addressed") {
+        Some(parameter_cache_dir().join("content-addressed"))
+    } else {
+        None
+    };
+    let opts = CacheOptions {
+        skip_existing: matches.is_present("skip-existing"),
+        verifying_keys_only: matches.is_present("verifying-keys-only"),
+    };
+
+    let jobs = value_t!(matches, "jobs", usize).expect("failed to parse --jobs");
+    assert!(jobs > 0, "--jobs must be at least 1");
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .expect("failed to build thread pool");
+
+    let partitions: Option<Vec<u8>> = if matches.is_present("partitions") {
+        Some(values_t!(matches.values_of("partitions"), u8).unwrap())
+    } else {
+        None
+    };
+
+    let mut param_sets: Vec<ParamSet> = sizes
+        .iter()
+        .flat_map(|size| {
+            let porep_partitions = partitions
+                .clone()
+                .unwrap_or_else(|| vec![default_partitions(*size)]);
+            std::iter::once(ParamSet::PoSt(*size)).chain(
+                porep_partitions
+                    .into_iter()
+                    .map(move |count| ParamSet::PoRep(*size, count)),
+            )
+        })
+        .collect();
+    param_sets.sort();
+
+    let ids: BTreeMap<ParamSet, String> = pool.install(|| {
+        param_sets
+            .par_iter()
+            .map(|param_set| {
+                info!("start generating {:?}", param_set);
+                let id = param_set.cache(opts);
+                info!("finished generating {:?}: {}", param_set, id);
+                (*param_set, id)
+            })
+            .collect()
+    });
+
+    if let Some(dir) = &content_addressed_dir {
+        let mut manifest = Manifest::new();
+        for sector_size in sizes {
+            let porep_id = &ids[&ParamSet::PoRep(sector_size, default_partitions(sector_size))];
+            let post_id = &ids[&ParamSet::PoSt(sector_size)];
+            let entry = ManifestEntry {
+                porep: content_address(dir, porep_id, &cache_files(porep_id))
+                    .expect("failed to content address PoRep parameters"),
+                post: content_address(dir, post_id, &cache_files(post_id))
+                    .expect("failed to content address PoSt parameters"),
+            };
+            manifest.insert(sector_size, entry);
+        }
+
+        update_manifest(dir, manifest).expect("failed to write manifest");
+        info!("wrote content addressed parameters to {}", dir.display());
+    }
+
+    if let Some(path) = matches.value_of("manifest") {
+        let records = file_records(&ids).expect("failed to hash generated files");
+        let file = File::create(path).expect("failed to create manifest");
+        serde_json::to_writer_pretty(file, &records).expect("failed to write manifest");
+        info!("wrote manifest of {} files to {}", records.len(), path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fake_cache_files(dir: &Path, cache_id: &str) -> Vec<PathBuf> {
+        ["params", "vk", "meta"]
+            .iter()
+            .map(|ext| {
+                let path = dir.join(format!("v19-{}.{}", cache_id, ext));
+                fs::write(&path, format!("{} {}", cache_id, ext)).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn list_files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_cache_files_complete() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let files = ["params", "vk", "meta"]
+            .iter()
+            .map(|ext| cache_dir.path().join(format!("v19-id.{}", ext)))
+            .collect::<Vec<_>>();
+        assert!(!cache_files_complete(&files, false));
+
+        fs::write(&files[1], "vk").unwrap();
+        fs::write(&files[2], "meta").unwrap();
+        assert!(!cache_files_complete(&files, false));
+        assert!(cache_files_complete(&files, true));
 
-        for p in &POREP_PROOF_PARTITION_CHOICES {
-            cache_porep_params(PoRepConfig {
-                sector_size: SectorSize(sector_size),
-                partitions: *p,
-            });
+        fs::write(&files[0], "params and vk").unwrap();
+        assert!(cache_files_complete(&files, false));
+
+        // Truncated parameters.
+        fs::write(&files[0], "p").unwrap();
+        assert!(!cache_files_complete(&files, false));
+        assert!(cache_files_complete(&files, true));
+    }
+
+    #[test]
+    fn test_validate_partitions() {
+        assert!(validate_partitions("1".into()).is_ok());
+        assert!(validate_partitions("255".into()).is_ok());
+        assert!(validate_partitions("0".into()).is_err());
+        assert!(validate_partitions("256".into()).is_err());
+        assert!(validate_partitions("two".into()).is_err());
+    }
+
+    #[test]
+    fn test_validate_sector_size() {
+        for sector_size in PUBLISHED_SECTOR_SIZES.iter() {
+            assert!(validate_sector_size(sector_size.to_string()).is_ok());
         }
+        assert!(validate_sector_size("2048".into()).is_err());
+        assert!(validate_sector_size("0".into()).is_err());
+        assert!(validate_sector_size("1KiB".into()).is_err());
+    }
+
+    #[test]
+    fn test_file_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("v19-id.vk");
+        fs::write(&path, "abc").unwrap();
+
+        let record = FileRecord::new(1024, "vk", "id", path.clone()).unwrap();
+        assert_eq!(
+            record,
+            FileRecord {
+                sector_size: 1024,
+                kind: "vk".into(),
+                identifier: "id".into(),
+                path,
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into(),
+                byte_len: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_content_addressed_layout() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        let porep_id = "stacked-proof-of-replication-abcd";
+        let post_id = "proof-of-spacetime-election-1234";
+        let porep_files = fake_cache_files(cache_dir.path(), porep_id);
+        let post_files = fake_cache_files(cache_dir.path(), post_id);
+
+        let run = |out_dir: &Path| {
+            let mut manifest = Manifest::new();
+            for sector_size in &[1024, 2048] {
+                let entry = ManifestEntry {
+                    porep: content_address(out_dir, porep_id, &porep_files).unwrap(),
+                    post: content_address(out_dir, post_id, &post_files).unwrap(),
+                };
+                manifest.insert(*sector_size, entry);
+            }
+            update_manifest(out_dir, manifest).unwrap();
+        };
+
+        let out_a = tempfile::tempdir().unwrap();
+        let out_b = tempfile::tempdir().unwrap();
+        run(out_a.path());
+        run(out_b.path());
+        // Running again is idempotent.
+        run(out_b.path());
+
+        let files = list_files(out_a.path());
+        assert_eq!(
+            files,
+            vec![
+                "1234.meta",
+                "1234.params",
+                "1234.vk",
+                "abcd.meta",
+                "abcd.params",
+                "abcd.vk",
+                MANIFEST_FILE,
+            ]
+        );
+        assert_eq!(files, list_files(out_b.path()));
+
+        let manifest: Manifest =
+            serde_json::from_reader(File::open(out_b.path().join(MANIFEST_FILE)).unwrap()).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(
+            manifest[&1024],
+            ManifestEntry {
+                porep: "abcd".into(),
+                post: "1234".into(),
+            }
+        );
+
+        assert_eq!(
+            fs::read(out_a.path().join("abcd.vk")).unwrap(),
+            fs::read(&porep_files[1]).unwrap()
+        );
     }
 }
@@ -1,6 +1,6 @@
 use storage_proofs::util::NODE_SIZE;
 
-use crate::types::UnpaddedBytesAmount;
+use crate::types::{PoRepProofPartitions, SectorSize, UnpaddedBytesAmount};
 
 pub const POREP_WINDOW_MINIMUM_CHALLENGES: usize = 1; // FIXME:
 pub const POREP_WRAPPER_MINIMUM_CHALLENGES: usize = 1; // FIXME:
@@ -12,6 +12,37 @@ pub const SECTOR_SIZE_16_MIB: u64 = 1 << 24;
 pub const SECTOR_SIZE_256_MIB: u64 = 1 << 28;
 pub const SECTOR_SIZE_1_GIB: u64 = 1 << 30;
 
+/// Sector sizes for which parameters are published.
+pub const PUBLISHED_SECTOR_SIZES: [u64; 4] = [
+    SECTOR_SIZE_ONE_KIB,
+    SECTOR_SIZE_16_MIB,
+    SECTOR_SIZE_256_MIB,
+    SECTOR_SIZE_1_GIB,
+];
+
+/// The number of PoRep partitions used for the published sector sizes.
+pub const DEFAULT_POREP_PROOF_PARTITIONS: PoRepProofPartitions = PoRepProofPartitions(2);
+
+/// Returns the number of PoRep partitions used in production for the given sector size, or `None`
+/// if parameters for this sector size are not published.
+pub fn default_partitions_for(sector_size: u64) -> Option<PoRepProofPartitions> {
+    match sector_size {
+        SECTOR_SIZE_ONE_KIB | SECTOR_SIZE_16_MIB | SECTOR_SIZE_256_MIB | SECTOR_SIZE_1_GIB => {
+            Some(DEFAULT_POREP_PROOF_PARTITIONS)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the smallest published sector size which fits `bytes` of unpadded user data, or `None`
+/// if `bytes` exceeds the capacity of the largest published sector size.
+pub fn next_published_sector_size(bytes: UnpaddedBytesAmount) -> Option<SectorSize> {
+    PUBLISHED_SECTOR_SIZES
+        .iter()
+        .map(|sector_size| SectorSize(*sector_size))
+        .find(|sector_size| UnpaddedBytesAmount::from(*sector_size) >= bytes)
+}
+
 // Window sizes, picked to match expected perf characteristics. Not finalized.
 
 pub const WINDOW_SIZE_NODES_ONE_KIB: usize = 512 / NODE_SIZE;
@@ -32,3 +63,45 @@ pub const MIN_PIECE_SIZE: UnpaddedBytesAmount = UnpaddedBytesAmount(127);
 pub type DefaultPieceHasher = storage_proofs::hasher::Sha256Hasher;
 
 pub use storage_proofs::drgraph::DefaultTreeHasher;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_partitions_for_published_sizes() {
+        for sector_size in PUBLISHED_SECTOR_SIZES.iter() {
+            let partitions = default_partitions_for(*sector_size)
+                .unwrap_or_else(|| panic!("no partitions for sector size {}", sector_size));
+            assert!(partitions.0 > 0);
+        }
+
+        assert!(default_partitions_for(SECTOR_SIZE_ONE_KIB + 1).is_none());
+    }
+
+    #[test]
+    fn test_next_published_sector_size() {
+        assert_eq!(
+            next_published_sector_size(UnpaddedBytesAmount(0)),
+            Some(SectorSize(SECTOR_SIZE_ONE_KIB))
+        );
+
+        for (i, sector_size) in PUBLISHED_SECTOR_SIZES.iter().enumerate() {
+            let capacity = u64::from(UnpaddedBytesAmount::from(SectorSize(*sector_size)));
+
+            assert_eq!(
+                next_published_sector_size(UnpaddedBytesAmount(capacity - 1)),
+                Some(SectorSize(*sector_size))
+            );
+            assert_eq!(
+                next_published_sector_size(UnpaddedBytesAmount(capacity)),
+                Some(SectorSize(*sector_size))
+            );
+            // The padded sector size itself does not fit into the sector.
+            assert_eq!(
+                next_published_sector_size(UnpaddedBytesAmount(*sector_size)),
+                PUBLISHED_SECTOR_SIZES.get(i + 1).cloned().map(SectorSize)
+            );
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use crate::types::UnpaddedBytesAmount;
+
+pub use anyhow::Result;
+
+/// Custom error types
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Piece source yielded {:?} but {:?} were declared", actual, expected)]
+    PieceShorterThanDeclared {
+        expected: UnpaddedBytesAmount,
+        actual: UnpaddedBytesAmount,
+    },
+}
@@ -647,11 +647,67 @@ pub fn clear_right_bits(byte: &mut u8, offset: usize) {
 const N: usize = 1000;
 const CHUNK_SIZE: usize = 127 * N;
 
-pub fn write_padded<R, W>(mut source: R, mut target: W) -> io::Result<usize>
+pub fn write_padded<R, W>(source: R, mut target: W) -> io::Result<usize>
 where
     R: Read,
     W: Read + Write + Seek,
 {
+    let (_, _, padded_bits) = FR32_PADDING_MAP.target_offsets(&mut target)?;
+
+    write_padded_at(source, target, padded_bits.total_bits() as u64)
+}
+
+/// Like `write_padded`, but never uses the vectorized padding of `ElementPadder`. The output is
+/// identical, this only exists to compare both implementations.
+pub fn write_padded_scalar<R, W>(source: R, mut target: W) -> io::Result<usize>
+where
+    R: Read,
+    W: Read + Write + Seek,
+{
+    let (_, _, padded_bits) = FR32_PADDING_MAP.target_offsets(&mut target)?;
+
+    write_padded_with(
+        source,
+        target,
+        padded_bits.total_bits() as u64,
+        ElementPadder::Scalar,
+    )
+}
+
+/// Like `write_padded`, but continues the padded bit stream at `bit_offset` (counted in padded
+/// bits from the start of `target`) instead of at the end of `target`. This allows concatenating
+/// pieces into a padded file whose length does not reflect how much of it was written yet. Any
+/// bits of `target` beyond `bit_offset` are overwritten.
+pub fn write_padded_at<R, W>(source: R, target: W, bit_offset: u64) -> io::Result<usize>
+where
+    R: Read,
+    W: Read + Write + Seek,
+{
+    write_padded_with(source, target, bit_offset, ElementPadder::detect())
+}
+
+fn write_padded_with<R, W>(
+    mut source: R,
+    mut target: W,
+    bit_offset: u64,
+    padder: ElementPadder,
+) -> io::Result<usize>
+where
+    R: Read,
+    W: Read + Write + Seek,
+{
+    let padding_map = &FR32_PADDING_MAP;
+    let bit_offset = bit_offset as usize;
+
+    // Offsets within the padding bits of an element can not be reached by writing data.
+    if bit_offset % padding_map.element_bits >= padding_map.data_bits {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("bit offset {} points into padding", bit_offset),
+        ));
+    }
+
+    let mut raw_bits = padding_map.transform_bit_offset(bit_offset, false);
     let mut buffer = [0; CHUNK_SIZE];
     let mut written = 0;
 
@@ -661,7 +717,15 @@ where
                 if bytes_read == 0 {
                     break;
                 }
-                written += write_padded_aux(&FR32_PADDING_MAP, &buffer[..bytes_read], &mut target)?;
+                let position = BitByte::from_bits(padding_map.transform_bit_offset(raw_bits, true));
+                written += write_padded_aux(
+                    padding_map,
+                    &buffer[..bytes_read],
+                    &mut target,
+                    &position,
+                    padder,
+                )?;
+                raw_bits += bytes_read * 8;
             }
             Err(err) => {
                 if err.kind() == io::ErrorKind::Interrupted {
@@ -675,12 +739,167 @@ where
     Ok(written)
 }
 
+/// Pad all of `source` into the start of `target`, like `write_padded` into an empty target, and
+/// return the number of padded bytes written. Specialized for targets which are already in memory,
+/// e.g. a mapped sector, it fails if `target` is too small to hold the padded data.
+pub fn write_padded_into(source: &[u8], target: &mut [u8]) -> io::Result<usize> {
+    let padding_map = &FR32_PADDING_MAP;
+    let padded_len = padding_map.transform_byte_offset(source.len(), true);
+    if target.len() < padded_len {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "target of {} bytes is too small for {} padded bytes",
+                target.len(),
+                padded_len
+            ),
+        ));
+    }
+
+    let padder = ElementPadder::detect();
+    let source_bits = source.len() * 8;
+    let mut read_pos = 0;
+    let mut written = 0;
+
+    while read_pos < source_bits {
+        let data_bits = min(padding_map.data_bits, source_bits - read_pos);
+
+        if padder.pads(padding_map)
+            && data_bits == padding_map.data_bits
+            && read_pos / 8 + 33 <= source.len()
+        {
+            let element = padder.pad_element(source, read_pos);
+            target[written..written + element.len()].copy_from_slice(&element);
+            written += element.len();
+        } else {
+            let element = extract_bits_and_shift(source, read_pos, data_bits, 0);
+            target[written..written + element.len()].copy_from_slice(&element);
+            written += element.len();
+        }
+
+        read_pos += data_bits;
+    }
+
+    debug_assert_eq!(written, padded_len);
+
+    Ok(written)
+}
+
+/// How full elements are padded by `write_padded_aux`. All variants produce identical output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ElementPadder {
+    /// Bit-level extraction through `extract_bits_and_shift`.
+    Scalar,
+    /// 16 bytes at a time with SSE2.
+    #[cfg(target_arch = "x86_64")]
+    Sse2,
+    /// 32 bytes, a whole element, at a time with AVX2.
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl ElementPadder {
+    /// The fastest implementation supported by this CPU.
+    fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return ElementPadder::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return ElementPadder::Sse2;
+            }
+        }
+
+        ElementPadder::Scalar
+    }
+
+    /// Whether `pad_element` can be used for `padding_map`. The vectorized implementations only
+    /// support the Fr32 layout of 254 data bits in 32 byte elements.
+    fn pads(self, padding_map: &PaddingMap) -> bool {
+        self != ElementPadder::Scalar
+            && padding_map.data_bits == 254
+            && padding_map.element_bits == 256
+    }
+
+    /// The 32 byte element holding the 254 data bits of `source` starting at bit `read_pos`.
+    /// Reads up to the 33rd byte after the start of the data.
+    fn pad_element(self, source: &[u8], read_pos: usize) -> [u8; 32] {
+        assert!(read_pos / 8 + 33 <= source.len());
+
+        let mut element = [0u8; 32];
+        match self {
+            ElementPadder::Scalar => unreachable!("the scalar padder extracts bits instead"),
+            #[cfg(target_arch = "x86_64")]
+            ElementPadder::Sse2 => unsafe { pad_element_sse2(source, read_pos, &mut element) },
+            #[cfg(target_arch = "x86_64")]
+            ElementPadder::Avx2 => unsafe { pad_element_avx2(source, read_pos, &mut element) },
+        }
+        // The last two bits of the element are padding.
+        element[31] &= 0b0011_1111;
+
+        element
+    }
+}
+
+// Both kernels compute every element byte `i` as
+// `(source[start + i] >> shift) | (source[start + i + 1] << (8 - shift))`. There are no
+// per-byte shifts, so the bytes are shifted as 16 bit lanes and the bits which crossed
+// into the neighbouring byte of the lane are masked off.
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn pad_element_sse2(source: &[u8], read_pos: usize, element: &mut [u8; 32]) {
+    use std::arch::x86_64::*;
+
+    let start = source.as_ptr().add(read_pos / 8);
+    let shift = (read_pos % 8) as i32;
+
+    let right = _mm_cvtsi32_si128(shift);
+    let left = _mm_cvtsi32_si128(8 - shift);
+    let low_mask = _mm_set1_epi8((0xffu8 >> shift) as i8);
+    let high_mask = _mm_set1_epi8(!(0xffu8 >> shift) as i8);
+
+    for half in 0..2 {
+        let low = _mm_loadu_si128(start.add(16 * half) as *const __m128i);
+        let high = _mm_loadu_si128(start.add(16 * half + 1) as *const __m128i);
+        let bytes = _mm_or_si128(
+            _mm_and_si128(_mm_srl_epi16(low, right), low_mask),
+            _mm_and_si128(_mm_sll_epi16(high, left), high_mask),
+        );
+        _mm_storeu_si128(element.as_mut_ptr().add(16 * half) as *mut __m128i, bytes);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn pad_element_avx2(source: &[u8], read_pos: usize, element: &mut [u8; 32]) {
+    use std::arch::x86_64::*;
+
+    let start = source.as_ptr().add(read_pos / 8);
+    let shift = (read_pos % 8) as i32;
+
+    let right = _mm_cvtsi32_si128(shift);
+    let left = _mm_cvtsi32_si128(8 - shift);
+    let low_mask = _mm256_set1_epi8((0xffu8 >> shift) as i8);
+    let high_mask = _mm256_set1_epi8(!(0xffu8 >> shift) as i8);
+
+    let low = _mm256_loadu_si256(start as *const __m256i);
+    let high = _mm256_loadu_si256(start.add(1) as *const __m256i);
+    let bytes = _mm256_or_si256(
+        _mm256_and_si256(_mm256_srl_epi16(low, right), low_mask),
+        _mm256_and_si256(_mm256_sll_epi16(high, left), high_mask),
+    );
+    _mm256_storeu_si256(element.as_mut_ptr() as *mut __m256i, bytes);
+}
+
 /** Padding process.
 
 Read a `source` of raw byte-aligned data, pad it in a bit stream and
-write a byte-aligned version of it in the `target`. The `target` needs
-to implement (besides `Write`) the `Read` and `Seek` traits since the
-last byte written may be incomplete and will need to be rewritten.
+write a byte-aligned version of it in the `target`, starting at the
+padded bit `position`. The `target` needs to implement (besides `Write`)
+the `Read` and `Seek` traits since the byte at `position` may be
+incomplete and will need to be rewritten.
 
 The reader will always be byte-aligned, the writer will operate with
 bit precision since we may have (when calling this function multiple
@@ -697,7 +916,13 @@ need to handle the potential bit-level misalignments:
 **/
 // TODO: Change name, this is the real write padded function, the previous one
 // just partition data in chunks.
-fn write_padded_aux<W>(padding_map: &PaddingMap, source: &[u8], mut target: W) -> io::Result<usize>
+fn write_padded_aux<W>(
+    padding_map: &PaddingMap,
+    source: &[u8],
+    mut target: W,
+    padded_bits: &BitByte,
+    padder: ElementPadder,
+) -> io::Result<usize>
 where
     W: Read + Write + Seek,
 {
@@ -705,7 +930,7 @@ where
     // the alignment calculations that will be worthless (because we wont' have any
     // data with which to align).
 
-    let (padded_bytes, _, padded_bits) = padding_map.target_offsets(&mut target)?;
+    target.seek(SeekFrom::Start(padded_bits.bytes as u64))?;
 
     // (1): Overwrite the extra bits (if any): we actually don't write in-place, we
     // remove the last byte and extract its valid bits to `last_bits` to be later
@@ -715,11 +940,8 @@ where
         // Read the last incomplete byte and left the `target` positioned to overwrite
         // it in the next `write_all`.
         let last_byte = &mut [0u8; 1];
-        target.seek(SeekFrom::Start(padded_bytes - 1))?;
         target.read_exact(last_byte)?;
-        target.seek(SeekFrom::Start(padded_bytes - 1))?;
-        // TODO: Can we use a relative `SeekFrom::End` seek to avoid
-        // setting our absolute `padded_bytes` position?
+        target.seek(SeekFrom::Start(padded_bits.bytes as u64))?;
 
         // Extract the valid bit from the last byte (the `bits` fraction
         // of the `padded_bits` bit stream that doesn't complete a byte).
@@ -730,7 +952,7 @@ where
 
     // (2): Fill the current data unit adding `missing_data_bits` from the
     // `source` (if available, or as many bits as we have).
-    let (_, missing_data_bits) = padding_map.next_boundary(&padded_bits);
+    let (_, missing_data_bits) = padding_map.next_boundary(padded_bits);
 
     // Check if we have enough `source_bits` to complete the data unit (and hence
     // add the padding and complete the element) or if we'll use all the `source_bits`
@@ -782,6 +1004,17 @@ where
             // avoid checks unrolling the incomplete data unit (last iteration) in
             // a separate block. (Related to the `padded_output` optimization note.)
 
+            // Full data units can be padded by the vectorized implementations, as
+            // long as they are followed by at least one more byte of `source`.
+            if padder.pads(padding_map)
+                && source_bits - read_pos >= padding_map.data_bits
+                && read_pos / 8 + 33 <= source.len()
+            {
+                padded_output.extend_from_slice(&padder.pad_element(source, read_pos));
+                read_pos += padding_map.data_bits;
+                continue;
+            }
+
             // Extract `data_bits` (or whatever we have left) from the `source` where
             // the `read_pos` left off and reposition them at the byte boundary (setting
             // `new_offset` to 0) since elements are byte-aligned.
@@ -812,6 +1045,65 @@ where
     Ok(source.len())
 }
 
+/// A `Read` adapter producing the padded byte stream of an unpadded `source` on demand, exactly
+/// as `write_padded` would write it.
+///
+/// The source is padded in chunks of whole multiples of 127 bytes, which pad to whole elements,
+/// so every chunk can be padded on its own.
+pub struct PaddingReader<R> {
+    source: R,
+    raw: Vec<u8>,
+    padded: io::Cursor<Vec<u8>>,
+    done: bool,
+}
+
+impl<R: Read> PaddingReader<R> {
+    pub fn new(source: R) -> Self {
+        PaddingReader {
+            source,
+            raw: vec![0; CHUNK_SIZE],
+            padded: io::Cursor::new(Vec::new()),
+            done: false,
+        }
+    }
+
+    /// Read the next chunk from `source` and pad it into `padded`. Only the last chunk may be
+    /// shorter than `CHUNK_SIZE`.
+    fn fill(&mut self) -> io::Result<()> {
+        let mut filled = 0;
+        while filled < self.raw.len() {
+            match self.source.read(&mut self.raw[filled..]) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(n) => filled += n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        self.padded.get_mut().clear();
+        self.padded.set_position(0);
+        write_padded(&self.raw[..filled], &mut self.padded)?;
+        self.padded.set_position(0);
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for PaddingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.padded.read(buf)?;
+            if n > 0 || buf.is_empty() || self.done {
+                return Ok(n);
+            }
+            self.fill()?;
+        }
+    }
+}
+
 // offset and num_bytes are based on the unpadded data, so
 // if [0, 1, ..., 255] was the original unpadded data, offset 3 and len 4 would return
 // [3, 4, 5, 6].
@@ -863,6 +1155,99 @@ where
     Ok(written)
 }
 
+/// A `Read` adapter recovering `len` raw bytes, starting at raw byte `offset`, from a padded
+/// `source` on demand. It yields exactly what `write_unpadded` would write for the same window,
+/// without holding the whole padded data in memory.
+///
+/// The source is unpadded in windows of whole multiples of 128 padded bytes, which unpad to whole
+/// multiples of 127 raw bytes, so every window can be unpadded on its own.
+pub struct UnpaddingReader<R> {
+    source: R,
+    padded: Vec<u8>,
+    raw: io::Cursor<Vec<u8>>,
+    /// Padded bytes to skip before the first window.
+    discard: u64,
+    /// Raw bytes to skip at the start of the next window.
+    skip: usize,
+    /// Raw bytes left to recover.
+    remaining: usize,
+}
+
+impl<R: Read> UnpaddingReader<R> {
+    pub fn new(source: R, offset: usize, len: usize) -> Self {
+        UnpaddingReader {
+            source,
+            padded: vec![0; 128 * N],
+            raw: io::Cursor::new(Vec::new()),
+            discard: (offset / 127 * 128) as u64,
+            skip: offset % 127,
+            remaining: len,
+        }
+    }
+
+    /// Read the next window from `source` and unpad it into `raw`.
+    fn fill(&mut self) -> io::Result<()> {
+        if self.discard > 0 {
+            let discarded = io::copy(&mut (&mut self.source).take(self.discard), &mut io::sink())?;
+            if discarded < self.discard {
+                return Err(self.unexpected_eof());
+            }
+            self.discard = 0;
+        }
+
+        let mut filled = 0;
+        while filled < self.padded.len() {
+            match self.source.read(&mut self.padded[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let read_pos = FR32_PADDING_MAP.transform_bit_offset(self.skip * 8, true);
+        if filled * 8 <= read_pos {
+            return Err(self.unexpected_eof());
+        }
+
+        self.raw.get_mut().clear();
+        self.raw.set_position(0);
+        let written = write_unpadded_aux(
+            &FR32_PADDING_MAP,
+            &self.padded[..filled],
+            self.raw.get_mut(),
+            self.skip,
+            self.remaining,
+        )?;
+        self.skip = 0;
+        self.remaining -= written;
+
+        Ok(())
+    }
+
+    fn unexpected_eof(&self) -> Error {
+        Error::new(
+            ErrorKind::UnexpectedEof,
+            format!(
+                "source ended with {} raw data bytes left to extract",
+                self.remaining
+            ),
+        )
+    }
+}
+
+impl<R: Read> Read for UnpaddingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.raw.read(buf)?;
+            if n > 0 || buf.is_empty() || self.remaining == 0 {
+                return Ok(n);
+            }
+            self.fill()?;
+        }
+    }
+}
+
 /**  Unpadding process.
 
 Read a `source` of padded data and recover from it the byte-aligned
@@ -1226,6 +1611,191 @@ mod tests {
         assert_eq!(output_x, output_y, "should have written same bytes")
     }
 
+    #[test]
+    fn test_write_padded_at() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let data: Vec<u8> = (0..127 * 8).map(|_| rng.gen()).collect();
+
+        let mut expected = Cursor::new(Vec::new());
+        write_padded(&mut data[..].as_ref(), &mut expected).unwrap();
+        let expected = expected.into_inner();
+
+        for i in &[1, 31, 32, 126, 127, 128, 500, 127 * 8 - 1] {
+            // Pre-allocate the whole target, so its length does not tell where the first piece ended.
+            let mut cursor = Cursor::new(vec![0u8; expected.len()]);
+            let mut written = write_padded_at(&mut data[..*i].as_ref(), &mut cursor, 0).unwrap();
+            let bit_offset = FR32_PADDING_MAP.transform_bit_offset(i * 8, true) as u64;
+            written += write_padded_at(&mut data[*i..].as_ref(), &mut cursor, bit_offset).unwrap();
+
+            assert_eq!(written, data.len());
+            assert_eq!(cursor.into_inner(), expected, "split at {}", i);
+        }
+
+        // The last two bits of every element are padding.
+        let mut cursor = Cursor::new(Vec::new());
+        assert!(write_padded_at(&mut data[..].as_ref(), &mut cursor, 254).is_err());
+        assert!(write_padded_at(&mut data[..].as_ref(), &mut cursor, 256 + 255).is_err());
+    }
+
+    #[test]
+    fn test_padding_reader() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for size in &[
+            0,
+            1,
+            127,
+            128,
+            256,
+            512,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            256_000,
+            512_000,
+        ] {
+            let data: Vec<u8> = (0..*size).map(|_| rng.gen()).collect();
+
+            let mut expected = Cursor::new(Vec::new());
+            write_padded(&mut data[..].as_ref(), &mut expected).unwrap();
+
+            let mut padded = Vec::new();
+            PaddingReader::new(&data[..])
+                .read_to_end(&mut padded)
+                .unwrap();
+            assert_eq!(padded, expected.into_inner(), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn test_unpadding_reader() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 1000).map(|_| rng.gen()).collect();
+
+        let mut padded = Cursor::new(Vec::new());
+        write_padded(&mut data[..].as_ref(), &mut padded).unwrap();
+        let padded = padded.into_inner();
+
+        for (offset, len) in &[
+            (0, data.len()),
+            (0, 1),
+            (1, 126),
+            (127, 128),
+            (1000, CHUNK_SIZE),
+            (CHUNK_SIZE - 3, 2 * CHUNK_SIZE + 7),
+            (data.len() - 10, 10),
+        ] {
+            let mut expected = Vec::new();
+            write_unpadded(&padded, &mut expected, *offset, *len).unwrap();
+
+            // Drain the reader in small, unaligned chunks.
+            let mut reader = UnpaddingReader::new(&padded[..], *offset, *len);
+            let mut unpadded = Vec::new();
+            let mut buf = [0u8; 1001];
+            loop {
+                let n = reader.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                unpadded.extend_from_slice(&buf[..n]);
+            }
+
+            assert_eq!(unpadded, expected, "offset {}, len {}", offset, len);
+            assert_eq!(&unpadded[..], &data[*offset..*offset + *len]);
+        }
+
+        let mut reader = UnpaddingReader::new(&padded[..], 10, data.len());
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_element_padders() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        let mut padders = vec![ElementPadder::Scalar];
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sse2") {
+                padders.push(ElementPadder::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                padders.push(ElementPadder::Avx2);
+            }
+        }
+
+        // Most of these are not a multiple of 254 bits, to cover the tail handling.
+        for len in &[
+            1,
+            31,
+            32,
+            33,
+            34,
+            64,
+            127,
+            128,
+            300,
+            1000,
+            4097,
+            CHUNK_SIZE + 5,
+        ] {
+            let data: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+
+            for bit_offset in &[0, 3, 8, 253, 256 + 100] {
+                let outputs = padders
+                    .iter()
+                    .map(|padder| {
+                        // Existing bits before a non byte aligned offset have to be preserved.
+                        let mut cursor = Cursor::new(vec![0xa5; 64]);
+                        write_padded_with(&data[..], &mut cursor, *bit_offset, *padder).unwrap();
+                        cursor.into_inner()
+                    })
+                    .collect::<Vec<_>>();
+
+                for (padder, output) in padders.iter().zip(&outputs) {
+                    assert_eq!(
+                        output, &outputs[0],
+                        "{:?} differs for {} bytes at bit {}",
+                        padder, len, bit_offset
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_padded_into() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for len in &[0, 1, 32, 127, 128, 1000, CHUNK_SIZE + 5] {
+            let data: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+
+            let mut expected = Cursor::new(Vec::new());
+            write_padded(&mut data[..].as_ref(), &mut expected).unwrap();
+            let expected = expected.into_inner();
+
+            // Bytes beyond the padded data are left alone.
+            let mut target = vec![0xa5; expected.len() + 3];
+            let written = write_padded_into(&data, &mut target).unwrap();
+            assert_eq!(written, expected.len());
+            assert_eq!(&target[..written], &expected[..], "{} bytes", len);
+            assert_eq!(&target[written..], &[0xa5; 3]);
+        }
+    }
+
+    #[test]
+    fn test_write_padded_into_undersized() {
+        let data = vec![255u8; 127];
+
+        let mut target = vec![0u8; 127];
+        let err = write_padded_into(&data, &mut target).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        // Nothing is written if the target is too small.
+        assert!(target.iter().all(|b| *b == 0));
+
+        let mut target = vec![0u8; 128];
+        assert_eq!(write_padded_into(&data, &mut target).unwrap(), 128);
+    }
+
     // `write_padded` for 127 bytes of 1s, splitting it in two calls of varying
     // sizes, from 0 to the full size, generating many unaligned calls, check padding.
     #[test]
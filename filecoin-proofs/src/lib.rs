@@ -13,6 +13,7 @@ mod api;
 mod caches;
 
 pub mod constants;
+pub mod error;
 pub mod fr32;
 pub mod param;
 pub mod parameters;
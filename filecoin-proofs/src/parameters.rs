@@ -1,4 +1,14 @@
+use std::time::{Duration, Instant};
+
 use anyhow::Result;
+use bellperson::groth16;
+use bellperson::{Circuit, ConstraintSystem, SynthesisError};
+use paired::bls12_381::Bls12;
+use rand::rngs::OsRng;
+use storage_proofs::circuit::counting::CountingCS;
+use storage_proofs::circuit::election_post::{ElectionPoStCircuit, ElectionPoStCompound};
+use storage_proofs::circuit::stacked::{StackedCircuit, StackedCompound};
+use storage_proofs::compound_proof::CompoundProof;
 use storage_proofs::drgraph::{DefaultTreeHasher, BASE_DEGREE};
 use storage_proofs::election_post::{self, ElectionPoSt};
 use storage_proofs::proof::ProofScheme;
@@ -7,7 +17,12 @@ use storage_proofs::stacked::{self, LayerChallenges, StackedConfig, StackedDrg,
 use crate::constants::{
     DefaultPieceHasher, POREP_WINDOW_MINIMUM_CHALLENGES, POREP_WRAPPER_MINIMUM_CHALLENGES,
 };
-use crate::types::{PaddedBytesAmount, PoStConfig};
+use crate::types::{PaddedBytesAmount, PoRepConfig, PoRepProofPartitions, PoStConfig, SectorSize};
+
+/// Size of an uncompressed BLS12-381 G1 element, as written to parameter files.
+const G1_UNCOMPRESSED_BYTES: u64 = 96;
+/// Size of an uncompressed BLS12-381 G2 element, as written to parameter files.
+const G2_UNCOMPRESSED_BYTES: u64 = 192;
 
 const LAYERS: usize = 4; // TODO: correct params;
 
@@ -90,6 +105,167 @@ pub fn setup_params(sector_bytes: PaddedBytesAmount, partitions: usize) -> stack
     }
 }
 
+/// Number of constraints of the circuit `measure_param_gen_nanos_per_constraint` generates
+/// parameters for, large enough for the fixed costs of parameter generation not to dominate.
+const CALIBRATION_CONSTRAINTS: usize = 1 << 14;
+
+/// Measures how long Groth parameter generation takes per constraint on this machine, by
+/// generating parameters for a circuit of `CALIBRATION_CONSTRAINTS` squarings.
+///
+/// Parameter generation is dominated by the FFTs over the evaluation domain of the constraints and
+/// by a fixed-base exponentiation in G1 or G2 for every query element, both of which grow about
+/// linearly with the number of constraints, so the rate carries over to the proof circuits. It
+/// leaves out the synthesis of their blank circuits, and the `n log n` growth of the FFTs, so
+/// estimates based on it are on the low side.
+pub fn measure_param_gen_nanos_per_constraint() -> Result<u64> {
+    let start = Instant::now();
+    groth16::generate_random_parameters::<Bls12, _, _>(
+        Squarings {
+            n: CALIBRATION_CONSTRAINTS,
+        },
+        &mut OsRng,
+    )?;
+    let nanos = start.elapsed().as_nanos() as u64;
+
+    Ok(std::cmp::max(1, nanos / CALIBRATION_CONSTRAINTS as u64))
+}
+
+/// Proves knowledge of `x` with `x^(2^n) = y`, with one constraint per squaring.
+struct Squarings {
+    n: usize,
+}
+
+impl Circuit<Bls12> for Squarings {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let mut x = cs.alloc(|| "x", || Err(SynthesisError::AssignmentMissing))?;
+        for i in 0..self.n {
+            let square = if i + 1 == self.n {
+                cs.alloc_input(|| "y", || Err(SynthesisError::AssignmentMissing))?
+            } else {
+                cs.alloc(
+                    || format!("square {}", i),
+                    || Err(SynthesisError::AssignmentMissing),
+                )?
+            };
+            cs.enforce(
+                || format!("squaring {}", i),
+                |lc| lc + x,
+                |lc| lc + x,
+                |lc| lc + square,
+            );
+            x = square;
+        }
+
+        Ok(())
+    }
+}
+
+/// Estimates the size in bytes of the Groth parameter file for the PoRep circuit of
+/// `porep_config`, from the number of inputs, auxiliary variables and constraints of its circuit.
+pub fn expected_param_file_size(porep_config: PoRepConfig) -> Result<u64> {
+    let (num_inputs, num_aux, num_constraints) = circuit_shape(porep_blank_circuit(porep_config))?;
+
+    Ok(param_file_size(num_inputs, num_aux, num_constraints))
+}
+
+/// Estimates the size in bytes of the Groth parameter file for the PoSt circuit of
+/// `post_config`, from the number of inputs, auxiliary variables and constraints of its circuit.
+pub fn expected_post_param_file_size(post_config: PoStConfig) -> Result<u64> {
+    let (num_inputs, num_aux, num_constraints) = circuit_shape(post_blank_circuit(post_config))?;
+
+    Ok(param_file_size(num_inputs, num_aux, num_constraints))
+}
+
+/// Estimates how long generating the PoRep and PoSt parameters for each of `sector_sizes`, with the
+/// PoRep using the corresponding number of `partitions`, takes. The estimate is based on the
+/// number of constraints of the circuits and `nanos_per_constraint`, as measured by
+/// `measure_param_gen_nanos_per_constraint`.
+pub fn estimate_param_gen_time(
+    sector_sizes: &[u64],
+    partitions: &[PoRepProofPartitions],
+    nanos_per_constraint: u64,
+) -> Result<Duration> {
+    ensure!(
+        sector_sizes.len() == partitions.len(),
+        "{} sector sizes, but {} partition counts",
+        sector_sizes.len(),
+        partitions.len()
+    );
+
+    let mut num_constraints = 0;
+    for (sector_size, partitions) in sector_sizes.iter().zip(partitions) {
+        let porep_config = PoRepConfig::new(SectorSize(*sector_size), *partitions)?;
+        let post_config = PoStConfig::new(SectorSize(*sector_size))?;
+
+        num_constraints += circuit_shape(porep_blank_circuit(porep_config))?.2;
+        num_constraints += circuit_shape(post_blank_circuit(post_config))?.2;
+    }
+
+    Ok(Duration::from_nanos(num_constraints * nanos_per_constraint))
+}
+
+fn porep_blank_circuit(
+    porep_config: PoRepConfig,
+) -> StackedCircuit<'static, Bls12, DefaultTreeHasher, DefaultPieceHasher> {
+    let public_params = public_params(
+        PaddedBytesAmount::from(porep_config),
+        usize::from(PoRepProofPartitions::from(porep_config)),
+    );
+
+    <StackedCompound as CompoundProof<
+        _,
+        StackedDrg<DefaultTreeHasher, DefaultPieceHasher>,
+        _,
+    >>::blank_circuit(&public_params)
+}
+
+fn post_blank_circuit(post_config: PoStConfig) -> ElectionPoStCircuit<Bls12, DefaultTreeHasher> {
+    let post_public_params = post_public_params(post_config);
+
+    <ElectionPoStCompound<DefaultTreeHasher> as CompoundProof<
+        Bls12,
+        ElectionPoSt<DefaultTreeHasher>,
+        ElectionPoStCircuit<Bls12, DefaultTreeHasher>,
+    >>::blank_circuit(&post_public_params)
+}
+
+/// The number of inputs, auxiliary variables and constraints of `circuit`, counted without
+/// keeping the circuit around.
+fn circuit_shape<C: Circuit<Bls12>>(circuit: C) -> Result<(u64, u64, u64)> {
+    let mut cs = CountingCS::<Bls12>::new();
+    circuit.synthesize(&mut cs)?;
+
+    Ok((
+        cs.num_inputs() as u64,
+        cs.num_aux() as u64,
+        cs.num_constraints() as u64,
+    ))
+}
+
+/// Size of a parameter file as written by `groth16::Parameters::write`, for a circuit with the
+/// given shape. This is an upper bound, as points at infinity are left out of the `a` and `b`
+/// queries.
+fn param_file_size(num_inputs: u64, num_aux: u64, num_constraints: u64) -> u64 {
+    let g1 = G1_UNCOMPRESSED_BYTES;
+    let g2 = G2_UNCOMPRESSED_BYTES;
+    // Every query is prefixed with its length.
+    let len = 4;
+
+    // Each input is enforced by an extra constraint during parameter generation.
+    let domain_size = (num_constraints + num_inputs).next_power_of_two();
+    let num_vars = num_inputs + num_aux;
+
+    // alpha_g1, beta_g1, beta_g2, gamma_g2, delta_g1, delta_g2 and ic
+    let vk = 3 * g1 + 3 * g2 + len + num_inputs * g1;
+    let h = len + (domain_size - 1) * g1;
+    let l = len + num_aux * g1;
+    let a = len + num_vars * g1;
+    let b_g1 = len + num_vars * g1;
+    let b_g2 = len + num_vars * g2;
+
+    vk + h + l + a + b_g1 + b_g2
+}
+
 fn select_challenges(
     partitions: usize,
     minimum_total_challenges: usize,
@@ -108,7 +284,8 @@ fn select_challenges(
 mod tests {
     use super::*;
 
-    use crate::types::PoRepProofPartitions;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     #[test]
     fn partition_layer_challenges_test() {
@@ -120,4 +297,51 @@ mod tests {
         assert_eq!(6, f(2));
         assert_eq!(3, f(4));
     }
+
+    #[test]
+    fn expected_param_file_size_matches_generated() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for n in &[10, 100, 1000] {
+            let params =
+                groth16::generate_random_parameters::<Bls12, _, _>(Squarings { n: *n }, rng)
+                    .unwrap();
+            let mut written = Vec::new();
+            params.write(&mut written).unwrap();
+            let actual = written.len() as u64;
+
+            let (num_inputs, num_aux, num_constraints) =
+                circuit_shape(Squarings { n: *n }).unwrap();
+            let estimate = param_file_size(num_inputs, num_aux, num_constraints);
+
+            assert!(estimate >= actual, "{} < {}", estimate, actual);
+            assert!(estimate <= 2 * actual, "{} > 2 * {}", estimate, actual);
+        }
+    }
+
+    #[test]
+    fn estimate_param_gen_time_scales_with_sector_sizes() {
+        use crate::constants::SECTOR_SIZE_ONE_KIB;
+
+        let partitions = PoRepProofPartitions(2);
+        let rate = 1000;
+
+        let one = estimate_param_gen_time(&[SECTOR_SIZE_ONE_KIB], &[partitions], rate).unwrap();
+        let two = estimate_param_gen_time(
+            &[SECTOR_SIZE_ONE_KIB, SECTOR_SIZE_ONE_KIB],
+            &[partitions, partitions],
+            rate,
+        )
+        .unwrap();
+
+        assert!(one > Duration::from_secs(0));
+        assert_eq!(two, 2 * one);
+        assert_eq!(
+            estimate_param_gen_time(&[SECTOR_SIZE_ONE_KIB], &[partitions], 2 * rate).unwrap(),
+            2 * one
+        );
+
+        assert!(estimate_param_gen_time(&[SECTOR_SIZE_ONE_KIB], &[], rate).is_err());
+        assert!(estimate_param_gen_time(&[SECTOR_SIZE_ONE_KIB + 1], &[partitions], rate).is_err());
+    }
 }
@@ -1,427 +1,2746 @@
-use std::io::Cursor;
-use std::io::Read;
+#[cfg(test)]
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::iter::Iterator;
+use std::ops::Range;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::Result;
-use storage_proofs::hasher::{HashFunction, Hasher};
+use serde::{Deserialize, Serialize};
+use storage_proofs::hasher::{Domain, HashFunction, Hasher};
+use storage_proofs::merkle::MerkleProof;
 use storage_proofs::util::NODE_SIZE;
 
 use crate::constants::{
     DefaultPieceHasher,
     MINIMUM_RESERVED_BYTES_FOR_PIECE_IN_FULLY_ALIGNED_SECTOR as MINIMUM_PIECE_SIZE,
 };
+use crate::fr32::write_padded;
 use crate::types::{
     Commitment, PaddedBytesAmount, PieceInfo, SectorSize, UnpaddedByteIndex, UnpaddedBytesAmount,
 };
 
 /// Verify that the provided `piece_infos` and `comm_d` match.
+///
+/// Pieces which do not fit into the sector are an error, like in `compute_comm_d`. Results are
+/// served from the verification cache, if it has been enabled through `set_verify_cache_capacity`;
+/// errors are never cached.
 pub fn verify_pieces(
     comm_d: &Commitment,
     piece_infos: &[PieceInfo],
     sector_size: SectorSize,
 ) -> Result<bool> {
-    let comm_d_calculated = compute_comm_d(sector_size, piece_infos)?;
+    let key = verify_cache_key(comm_d, piece_infos, sector_size);
+
+    if let Some(valid) = VERIFY_CACHE.lock().unwrap().get(&key) {
+        return Ok(valid);
+    }
+
+    #[cfg(test)]
+    VERIFY_COMPUTE_COUNT.with(|count| count.set(count.get() + 1));
+
+    let valid = match verify_pieces_detailed(comm_d, piece_infos, sector_size)? {
+        PieceVerification::Ok => true,
+        PieceVerification::Mismatch { .. } => false,
+        PieceVerification::InvalidPieceSize { size, .. } => bail!(
+            "Piece size ({:?}) must be a power of 2.",
+            PaddedBytesAmount::from(size)
+        ),
+        PieceVerification::Overflow { .. } => bail!("Piece is larger than sector."),
+    };
 
-    Ok(&comm_d_calculated == comm_d)
+    VERIFY_CACHE.lock().unwrap().insert(key, valid);
+
+    Ok(valid)
 }
 
-pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Result<Commitment> {
-    info!("verifying {} pieces", piece_infos.len());
-    ensure!(!piece_infos.is_empty(), "Missing piece infos");
+/// Outcome of `verify_pieces_detailed`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PieceVerification {
+    /// The pieces produce the expected `comm_d`.
+    Ok,
+    /// The pieces fit into the sector, but produce a different `comm_d`.
+    Mismatch {
+        expected: Commitment,
+        computed: Commitment,
+    },
+    /// The padded size of the piece at `index` is not a power of two.
+    InvalidPieceSize {
+        index: usize,
+        size: UnpaddedBytesAmount,
+    },
+    /// The pieces, laid out with their alignment padding, need more space than the sector has.
+    Overflow {
+        required: UnpaddedBytesAmount,
+        available: UnpaddedBytesAmount,
+    },
+}
 
-    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+impl PieceVerification {
+    pub fn is_ok(&self) -> bool {
+        *self == PieceVerification::Ok
+    }
+}
 
-    ensure!(
-        piece_infos.len() as u64 <= u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE,
-        "Too many pieces"
-    );
+/// Verify that the provided `piece_infos` and `comm_d` match, reporting why they don't.
+///
+/// Unlike `verify_pieces`, results are never cached.
+pub fn verify_pieces_detailed(
+    comm_d: &Commitment,
+    piece_infos: &[PieceInfo],
+    sector_size: SectorSize,
+) -> Result<PieceVerification> {
+    for (index, piece_info) in piece_infos.iter().enumerate() {
+        if !u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two() {
+            return Ok(PieceVerification::InvalidPieceSize {
+                index,
+                size: piece_info.size,
+            });
+        }
+    }
 
-    // make sure the piece sizes are at most a sector size large
-    let piece_size: u64 = piece_infos
-        .iter()
-        .map(|info| u64::from(PaddedBytesAmount::from(info.size)))
-        .sum();
+    let sizes: Vec<_> = piece_infos.iter().map(|piece| piece.size).collect();
+    let required = sum_piece_bytes_with_alignment(&sizes);
+    let available: UnpaddedBytesAmount = sector_size.into();
+    if required > available {
+        return Ok(PieceVerification::Overflow {
+            required,
+            available,
+        });
+    }
 
-    ensure!(
-        piece_size <= u64::from(sector_size),
-        "Piece is larger than sector."
-    );
+    let computed = compute_comm_d(sector_size, piece_infos)?;
+    if &computed != comm_d {
+        return Ok(PieceVerification::Mismatch {
+            expected: *comm_d,
+            computed,
+        });
+    }
 
-    let mut stack = Stack::new();
+    Ok(PieceVerification::Ok)
+}
 
-    let first = piece_infos.first().unwrap().clone();
+/// Verify that the pieces given as parallel slices of commitments and unpadded sizes match
+/// `comm_d`, like `verify_pieces`.
+pub fn verify_pieces_raw(
+    comm_d: &Commitment,
+    commitments: &[[u8; 32]],
+    sizes: &[u64],
+    sector_size: SectorSize,
+) -> Result<bool> {
     ensure!(
-        u64::from(PaddedBytesAmount::from(first.size)).is_power_of_two(),
-        "Piece size ({:?}) must be a power of 2.",
-        PaddedBytesAmount::from(first.size)
+        commitments.len() == sizes.len(),
+        "got {} piece commitments, but {} piece sizes",
+        commitments.len(),
+        sizes.len()
     );
-    stack.shift(first);
 
-    for piece_info in piece_infos.iter().skip(1) {
-        ensure!(
-            u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two(),
-            "Piece size ({:?}) must be a power of 2.",
-            PaddedBytesAmount::from(piece_info.size)
-        );
+    let piece_infos = commitments
+        .iter()
+        .zip(sizes.iter())
+        .map(|(commitment, size)| PieceInfo::new(*commitment, UnpaddedBytesAmount(*size)))
+        .collect::<Vec<_>>();
 
-        while stack.peek().size < piece_info.size {
-            stack.shift_reduce(zero_padding(stack.peek().size))
-        }
+    verify_pieces(comm_d, &piece_infos, sector_size)
+}
 
-        stack.shift_reduce(piece_info.clone());
-    }
+/// Set the number of results `verify_pieces` keeps around, evicting the least recently used
+/// results beyond that. A capacity of 0, the default, disables the cache.
+pub fn set_verify_cache_capacity(capacity: usize) {
+    let mut cache = VERIFY_CACHE.lock().unwrap();
+    cache.capacity = capacity;
+    cache.evict();
+}
 
-    while stack.len() > 1 {
-        stack.shift_reduce(zero_padding(stack.peek().size));
-    }
+/// Drop all results cached by `verify_pieces`.
+pub fn clear_verify_cache() {
+    let mut cache = VERIFY_CACHE.lock().unwrap();
+    cache.entries.clear();
+    cache.order.clear();
+}
 
-    assert_eq!(stack.len(), 1);
+/// Hash of everything the result of `verify_pieces` depends on.
+fn verify_cache_key(
+    comm_d: &Commitment,
+    piece_infos: &[PieceInfo],
+    sector_size: SectorSize,
+) -> [u8; 32] {
+    let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+    state.update(&u64::from(sector_size).to_le_bytes());
+    state.update(comm_d);
+    state.update(&(piece_infos.len() as u64).to_le_bytes());
+    for info in piece_infos {
+        state.update(&u64::from(info.size).to_le_bytes());
+        state.update(&info.commitment);
+    }
 
-    let comm_d_calculated = stack.pop().commitment;
+    let mut key = [0u8; 32];
+    key.copy_from_slice(state.finalize().as_bytes());
+    key
+}
 
-    Ok(comm_d_calculated)
+/// Least recently used cache of `verify_pieces` results.
+#[derive(Debug, Default)]
+struct VerifyCache {
+    capacity: usize,
+    entries: HashMap<[u8; 32], bool>,
+    /// Keys of `entries`, from least to most recently used.
+    order: VecDeque<[u8; 32]>,
 }
 
-/// Stack used for piece reduction.
-struct Stack(Vec<PieceInfo>);
+impl VerifyCache {
+    fn get(&mut self, key: &[u8; 32]) -> Option<bool> {
+        let valid = *self.entries.get(key)?;
+        self.touch(*key);
+        Some(valid)
+    }
 
-impl Stack {
-    /// Creates a new stack.
-    pub fn new() -> Self {
-        Stack(Vec::new())
+    fn insert(&mut self, key: [u8; 32], valid: bool) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.insert(key, valid);
+        self.touch(key);
+        self.evict();
     }
 
-    /// Pushes a single element onto the stack.
-    pub fn shift(&mut self, el: PieceInfo) {
-        self.0.push(el)
+    fn touch(&mut self, key: [u8; 32]) {
+        if let Some(pos) = self.order.iter().position(|k| *k == key) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(key);
     }
 
-    /// Look at the last element of the stack.
-    pub fn peek(&self) -> &PieceInfo {
-        &self.0[self.0.len() - 1]
+    fn evict(&mut self) {
+        while self.order.len() > self.capacity {
+            if let Some(key) = self.order.pop_front() {
+                self.entries.remove(&key);
+            }
+        }
     }
+}
+
+lazy_static! {
+    static ref VERIFY_CACHE: Mutex<VerifyCache> = Default::default();
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of times `verify_pieces` computed `comm_d` on the current thread.
+    static VERIFY_COMPUTE_COUNT: Cell<usize> = Cell::new(0);
+}
+
+/// Check whether the piece lists `a` and `b` result in the same `comm_d`.
+pub fn piece_lists_equivalent(
+    a: &[PieceInfo],
+    b: &[PieceInfo],
+    sector_size: SectorSize,
+) -> Result<bool> {
+    let total_size = |pieces: &[PieceInfo]| -> u64 {
+        pieces
+            .iter()
+            .map(|info| u64::from(PaddedBytesAmount::from(info.size)))
+            .sum()
+    };
 
-    /// Look at the second to last element of the stack.
-    pub fn peek2(&self) -> &PieceInfo {
-        &self.0[self.0.len() - 2]
+    if total_size(a) != total_size(b) {
+        return Ok(false);
     }
 
-    /// Pop the last element of the stack.
-    pub fn pop(&mut self) -> PieceInfo {
-        self.0.pop().expect("empty stack popped")
+    Ok(compute_comm_d(sector_size, a)? == compute_comm_d(sector_size, b)?)
+}
+
+/// The tree of subtree roots a `comm_d` is computed from, as reported by
+/// `explain_comm_d_mismatch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommDDiff {
+    /// The root the pieces were expected to produce.
+    pub expected: Commitment,
+    /// The root the pieces actually produce.
+    pub computed: Commitment,
+    /// Height, in nodes, of the subtrees in the lowest level, i.e. of the smallest piece.
+    pub base_height: usize,
+    /// Subtree roots from the lowest level up to the root. Subtrees within a piece are unknown,
+    /// and thus `None`.
+    pub levels: Vec<Vec<Option<Commitment>>>,
+}
+
+/// A subtree of `comm_d`, identified by its height in nodes and its index among all subtrees of
+/// that height in the sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subtree {
+    pub height: usize,
+    pub index: usize,
+}
+
+impl CommDDiff {
+    /// Whether the computed root matches the expected one.
+    pub fn matches(&self) -> bool {
+        self.expected == self.computed
     }
 
-    pub fn reduce1(&mut self) -> bool {
-        if self.len() < 2 {
-            return false;
+    /// Finds the smallest subtree which explains why the computed roots of `self` and
+    /// `reference` differ, by descending from the root into the divergent child as long as
+    /// exactly one child differs. Returns `None` if the roots are the same.
+    pub fn first_divergence(&self, reference: &CommDDiff) -> Option<Subtree> {
+        if self.computed == reference.computed {
+            return None;
         }
 
-        if self.peek().size == self.peek2().size {
-            let right = self.pop();
-            let left = self.pop();
-            let joined = join_piece_infos(left, right);
-            self.shift(joined);
-            return true;
+        let mut level = self.levels.len() - 1;
+        let mut index = 0;
+
+        // Only levels both trees have can be compared.
+        while level > 0 && level + self.base_height > reference.base_height {
+            let reference_level = level + self.base_height - reference.base_height - 1;
+            let differs = |i: usize| {
+                let ours = self.levels[level - 1][i];
+                let theirs = reference.levels[reference_level][i];
+                ours.is_none() || theirs.is_none() || ours != theirs
+            };
+
+            let (left, right) = (2 * index, 2 * index + 1);
+            index = match (differs(left), differs(right)) {
+                (true, false) => left,
+                (false, true) => right,
+                _ => break,
+            };
+            level -= 1;
+
+            // Descending into a piece, there is nothing more to compare.
+            if self.levels[level][index].is_some()
+                && (level == 0 || self.levels[level - 1][2 * index].is_none())
+            {
+                break;
+            }
         }
 
-        false
+        Some(Subtree {
+            height: level + self.base_height,
+            index,
+        })
     }
+}
+
+/// Recompute `comm_d` from `pieces`, keeping the roots of all intermediate subtrees, to help
+/// debugging a `comm_d` which does not match the `expected` one.
+///
+/// The expected root alone only tells whether the roots differ. To find out where they diverge,
+/// compare the result against the explanation of a reference layout, such as the pieces the
+/// sector was sealed with, using `CommDDiff::first_divergence`.
+pub fn explain_comm_d_mismatch(
+    expected: &Commitment,
+    sector_size: SectorSize,
+    pieces: &[PieceInfo],
+) -> Result<CommDDiff> {
+    let tree = piece_tree(sector_size, pieces)?;
+
+    Ok(CommDDiff {
+        expected: *expected,
+        computed: tree.root,
+        base_height: tree.base_height,
+        levels: tree.levels,
+    })
+}
 
-    pub fn reduce(&mut self) {
-        while self.reduce1() {}
+/// The subtree roots of `comm_d` down to the size of the smallest piece.
+struct PieceTree {
+    root: Commitment,
+    /// Height, in nodes, of the subtrees in the lowest level.
+    base_height: usize,
+    /// Subtree roots from the lowest level up to the root, `None` within pieces.
+    levels: Vec<Vec<Option<Commitment>>>,
+}
+
+fn piece_tree(sector_size: SectorSize, pieces: &[PieceInfo]) -> Result<PieceTree> {
+    // Validates the pieces, so they are known to fit into the sector below.
+    let root = compute_comm_d(sector_size, pieces)?;
+
+    let node_height = |size: UnpaddedBytesAmount| {
+        (u64::from(PaddedBytesAmount::from(size)) / NODE_SIZE as u64).trailing_zeros() as usize
+    };
+    let sector_height = (u64::from(sector_size) / NODE_SIZE as u64).trailing_zeros() as usize;
+    let base_height = pieces
+        .iter()
+        .map(|piece| node_height(piece.size))
+        .min()
+        .expect("pieces are not empty");
+
+    // Start with an all zero base level, then place each piece, aligned to its size.
+    let num_levels = sector_height - base_height + 1;
+    let mut levels: Vec<Vec<Option<Commitment>>> = (0..num_levels)
+        .map(|level| vec![None; 1 << (num_levels - 1 - level)])
+        .collect();
+    let zero = zero_root::<DefaultPieceHasher>(base_height);
+    for node in levels[0].iter_mut() {
+        *node = Some(zero);
     }
 
-    pub fn shift_reduce(&mut self, piece: PieceInfo) {
-        self.shift(piece);
-        self.reduce();
+    let mut offset = 0u64;
+    for piece in pieces {
+        let padded_size = u64::from(PaddedBytesAmount::from(piece.size));
+        let start = (offset + padded_size - 1) / padded_size * padded_size;
+        offset = start + padded_size;
+
+        let height = node_height(piece.size);
+        let level = height - base_height;
+        let index = (start / padded_size) as usize;
+        levels[level][index] = Some(piece.commitment);
+
+        // Nothing is known about the subtrees within the piece.
+        for below in 0..level {
+            let width = 1 << (level - below);
+            for node in &mut levels[below][index * width..(index + 1) * width] {
+                *node = None;
+            }
+        }
     }
 
-    pub fn len(&self) -> usize {
-        self.0.len()
+    for level in 1..num_levels {
+        for index in 0..levels[level].len() {
+            if levels[level][index].is_some() {
+                continue;
+            }
+            if let (Some(left), Some(right)) = (
+                levels[level - 1][2 * index],
+                levels[level - 1][2 * index + 1],
+            ) {
+                levels[level][index] = Some(hash_pair(&left, &right));
+            }
+        }
     }
+
+    debug_assert_eq!(levels[num_levels - 1][0], Some(root));
+
+    Ok(PieceTree {
+        root,
+        base_height,
+        levels,
+    })
 }
 
-/// Create a padding `PieceInfo` of size `size`.
-fn zero_padding(size: UnpaddedBytesAmount) -> PieceInfo {
-    let padded_size: PaddedBytesAmount = size.into();
-    let mut commitment = [0u8; 32];
+fn hash_pair(left: &Commitment, right: &Commitment) -> Commitment {
+    let mut node = [0u8; 32];
+    node.copy_from_slice(piece_hash::<DefaultPieceHasher>(left, right).as_ref());
+    node
+}
 
-    // TODO: cache common piece hashes
-    let mut hashed_size = 64;
-    let h1 = piece_hash(&commitment, &commitment);
-    commitment.copy_from_slice(h1.as_ref());
+/// A proof of the inclusion of several pieces in `comm_d`, sharing the authentication nodes
+/// their individual inclusion proofs have in common.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceMultiProof {
+    /// Height, in nodes, of the whole tree.
+    pub tree_height: usize,
+    /// The proven pieces, together with the subtree they are placed at.
+    pub pieces: Vec<(Subtree, Commitment)>,
+    /// The authentication nodes, ordered by height and index, none of which can be computed from
+    /// the pieces or the other nodes.
+    pub nodes: Vec<(Subtree, Commitment)>,
+}
 
-    while hashed_size < u64::from(padded_size) {
-        let h = piece_hash(&commitment, &commitment);
-        commitment.copy_from_slice(h.as_ref());
-        hashed_size *= 2;
-    }
+impl PieceMultiProof {
+    /// Verifies that `pieces`, the proven pieces in the order of the proof, are committed to by
+    /// `comm_d`.
+    ///
+    /// Each piece must be placed at a subtree of its size below the root. The inclusion proof of
+    /// every piece is rebuilt from the authentication nodes and the other pieces, and validated as
+    /// a `MerkleProof` of the subtree the piece is placed at.
+    pub fn verify(&self, comm_d: &Commitment, pieces: &[PieceInfo]) -> bool {
+        if pieces.is_empty()
+            || pieces.len() != self.pieces.len()
+            || self.tree_height >= std::mem::size_of::<usize>() * 8
+        {
+            return false;
+        }
+        for ((subtree, commitment), piece) in self.pieces.iter().zip(pieces) {
+            let padded_size = u64::from(PaddedBytesAmount::from(piece.size));
+            if commitment != &piece.commitment
+                || !padded_size.is_power_of_two()
+                || (padded_size / NODE_SIZE as u64).trailing_zeros() as usize != subtree.height
+            {
+                return false;
+            }
+        }
 
-    assert_eq!(hashed_size, u64::from(padded_size));
+        let mut known: BTreeMap<(usize, usize), Commitment> = BTreeMap::new();
+        for (subtree, commitment) in self.pieces.iter().chain(self.nodes.iter()) {
+            if subtree.height >= self.tree_height
+                || subtree.index >> (self.tree_height - subtree.height) != 0
+            {
+                return false;
+            }
+            if let Some(existing) = known.insert((subtree.height, subtree.index), *commitment) {
+                if existing != *commitment {
+                    return false;
+                }
+            }
+        }
 
-    PieceInfo { size, commitment }
-}
+        // Fill in the ancestors of pairs of known siblings, lowest first, so that the path of
+        // every piece can be read off below the root.
+        let lowest = known.keys().next().expect("pieces are not empty").0;
+        for height in lowest..self.tree_height - 1 {
+            let at_height: Vec<(usize, Commitment)> = known
+                .range((height, 0)..(height + 1, 0))
+                .map(|(&(_, index), &commitment)| (index, commitment))
+                .collect();
+            for pair in at_height.windows(2) {
+                let ((left_index, left), (right_index, right)) = (pair[0], pair[1]);
+                if left_index % 2 != 0 || right_index != left_index + 1 {
+                    continue;
+                }
+                let parent = hash_pair(&left, &right);
+                if let Some(existing) = known.insert((height + 1, left_index / 2), parent) {
+                    if existing != parent {
+                        return false;
+                    }
+                }
+            }
+        }
 
-/// Join two equally sized `PieceInfo`s together, by hashing them and adding their sizes.
-fn join_piece_infos(mut left: PieceInfo, right: PieceInfo) -> PieceInfo {
-    assert_eq!(left.size, right.size);
-    let h = piece_hash(&left.commitment, &right.commitment);
+        let as_domain = |commitment: &Commitment| {
+            <DefaultPieceHasher as Hasher>::Domain::try_from_bytes(commitment).ok()
+        };
 
-    left.commitment.copy_from_slice(AsRef::<[u8]>::as_ref(&h));
-    left.size = left.size + right.size;
-    left
+        self.pieces.iter().all(|(subtree, commitment)| {
+            let path = (subtree.height..self.tree_height)
+                .map(|height| {
+                    let index = subtree.index >> (height - subtree.height);
+                    let sibling = known.get(&(height, index ^ 1))?;
+                    Some((as_domain(sibling)?, index % 2 == 1))
+                })
+                .collect::<Option<Vec<_>>>();
+
+            match (path, as_domain(comm_d), as_domain(commitment)) {
+                (Some(path), Some(root), Some(leaf)) => {
+                    MerkleProof::<DefaultPieceHasher>::from_parts(root, leaf, path)
+                        .validate(subtree.index)
+                }
+                _ => false,
+            }
+        })
+    }
 }
 
-fn piece_hash(a: &[u8], b: &[u8]) -> <DefaultPieceHasher as Hasher>::Domain {
-    let mut buf = [0u8; NODE_SIZE * 2];
-    buf[..NODE_SIZE].copy_from_slice(a);
-    buf[NODE_SIZE..].copy_from_slice(b);
-    <DefaultPieceHasher as Hasher>::Function::hash(&buf)
-}
+/// Build a single inclusion proof of the pieces at `indices` against `comm_d`, which contains
+/// every authentication node only once.
+///
+/// The proof combines the `piece_inclusion_proof`s of the pieces, leaving out the siblings which
+/// can be computed from the other proven pieces. Pieces filling the whole sector are their own
+/// `comm_d`, and can not be proven.
+pub fn piece_multiproof(
+    sector_size: SectorSize,
+    pieces: &[PieceInfo],
+    indices: &[usize],
+) -> Result<PieceMultiProof> {
+    ensure!(!indices.is_empty(), "No pieces to prove");
 
-#[derive(Debug, Clone)]
-pub struct PieceAlignment {
-    pub left_bytes: UnpaddedBytesAmount,
-    pub right_bytes: UnpaddedBytesAmount,
-}
+    let proven: BTreeSet<usize> = indices.iter().cloned().collect();
+    let paths = proven
+        .iter()
+        .map(|i| inclusion_path(sector_size, pieces, *i))
+        .collect::<Result<Vec<_>>>()?;
 
-impl PieceAlignment {
-    pub fn sum(&self, piece_size: UnpaddedBytesAmount) -> UnpaddedBytesAmount {
-        self.left_bytes + piece_size + self.right_bytes
+    let tree_height = paths[0].0.height + paths[0].1.len();
+    for (i, (subtree, _)) in proven.iter().zip(paths.iter()) {
+        ensure!(
+            subtree.height < tree_height,
+            "Target piece {} fills the whole sector",
+            i
+        );
     }
-}
 
-/// Given a list of pieces, sum the number of bytes taken by those pieces in that order.
-pub fn sum_piece_bytes_with_alignment(pieces: &[UnpaddedBytesAmount]) -> UnpaddedBytesAmount {
-    pieces
+    // The proven pieces and their ancestors are known to the verifier, all other siblings along
+    // their paths are authentication nodes.
+    let known: BTreeSet<(usize, usize)> = paths
         .iter()
-        .fold(UnpaddedBytesAmount(0), |acc, piece_bytes| {
-            acc + get_piece_alignment(acc, *piece_bytes).sum(*piece_bytes)
+        .flat_map(|(subtree, path)| {
+            (0..=path.len()).map(move |level| (subtree.height + level, subtree.index >> level))
         })
-}
-
-/// Given a list of pieces, find the byte where a given piece does or would start.
-pub fn get_piece_start_byte(
-    pieces: &[UnpaddedBytesAmount],
-    piece_bytes: UnpaddedBytesAmount,
-) -> UnpaddedByteIndex {
-    // sum up all the bytes taken by the ordered pieces
-    let last_byte = sum_piece_bytes_with_alignment(&pieces);
-    let alignment = get_piece_alignment(last_byte, piece_bytes);
+        .collect();
+
+    let mut nodes: BTreeMap<(usize, usize), Commitment> = BTreeMap::new();
+    for (subtree, path) in &paths {
+        for (level, sibling) in path.iter().enumerate() {
+            let position = (subtree.height + level, (subtree.index >> level) ^ 1);
+            if !known.contains(&position) {
+                nodes.insert(position, *sibling);
+            }
+        }
+    }
 
-    // add only the left padding of the target piece to give the start of that piece's data
-    UnpaddedByteIndex::from(last_byte + alignment.left_bytes)
+    Ok(PieceMultiProof {
+        tree_height,
+        pieces: proven
+            .iter()
+            .zip(paths.iter())
+            .map(|(i, (subtree, _))| (*subtree, pieces[*i].commitment))
+            .collect(),
+        nodes: nodes
+            .into_iter()
+            .map(|((height, index), commitment)| (Subtree { height, index }, commitment))
+            .collect(),
+    })
 }
 
-/// Given a number of bytes already written to a staged sector (ignoring bit padding) and a number
-/// of bytes (before bit padding) to be added, return the alignment required to create a piece where
-/// len(piece) == len(sector size)/(2^n) and sufficient left padding to ensure simple merkle proof
-/// construction.
-pub fn get_piece_alignment(
-    written_bytes: UnpaddedBytesAmount,
-    piece_bytes: UnpaddedBytesAmount,
-) -> PieceAlignment {
-    let mut piece_bytes_needed = MINIMUM_PIECE_SIZE as u64;
+/// Find the pieces two sectors have in common, returning the index pairs `(i, j)` of all pieces
+/// `a[i]` and `b[j]` with the same commitment and size, ordered by `i` and then `j`.
+pub fn shared_pieces(a: &[PieceInfo], b: &[PieceInfo]) -> Vec<(usize, usize)> {
+    let key = |piece: &PieceInfo| (piece.commitment, u64::from(piece.size));
 
-    // Calculate the next power of two multiple that will fully contain the piece's data.
-    // This is required to ensure a clean piece merkle root, without being affected by
-    // preceding or following pieces.
-    while piece_bytes_needed < u64::from(piece_bytes) {
-        piece_bytes_needed *= 2;
+    let mut indices: HashMap<(Commitment, u64), Vec<usize>> = HashMap::new();
+    for (j, piece) in b.iter().enumerate() {
+        indices.entry(key(piece)).or_default().push(j);
     }
 
-    // Calculate the bytes being affected from the left of the piece by the previous piece.
-    let encroaching = u64::from(written_bytes) % piece_bytes_needed;
+    a.iter()
+        .enumerate()
+        .flat_map(|(i, piece)| {
+            indices
+                .get(&key(piece))
+                .into_iter()
+                .flatten()
+                .map(move |j| (i, *j))
+        })
+        .collect()
+}
 
-    // Calculate the bytes to push from the left to ensure a clean piece merkle root.
-    let left_bytes = if encroaching > 0 {
-        piece_bytes_needed - encroaching
-    } else {
-        0
+/// Merge adjacent, equally sized zero padding pieces of `layout` into larger padding pieces.
+///
+/// Two padding pieces are only merged if they are siblings in the piece tree, i.e. the first one
+/// starts at an offset aligned to their joined size, so the resulting layout has the same `comm_d`.
+pub fn coalesce_padding(layout: &[PieceInfo]) -> Vec<PieceInfo> {
+    let is_padding = |piece: &PieceInfo| {
+        piece.commitment == zero_padding::<DefaultPieceHasher>(piece.size).commitment
     };
 
-    let right_bytes = piece_bytes_needed - u64::from(piece_bytes);
+    // (padded offset, piece, is padding)
+    let mut coalesced: Vec<(u64, PieceInfo, bool)> = Vec::with_capacity(layout.len());
+    let mut offset = 0;
+
+    for piece in layout {
+        let size = u64::from(PaddedBytesAmount::from(piece.size));
+        // Pieces are aligned to their own size in the piece tree.
+        offset = (offset + size - 1) / size * size;
+        coalesced.push((offset, piece.clone(), is_padding(piece)));
+        offset += size;
+
+        while coalesced.len() >= 2 {
+            let (left_offset, ref left, left_padding) = coalesced[coalesced.len() - 2];
+            let (_, ref right, right_padding) = coalesced[coalesced.len() - 1];
+            let size = u64::from(PaddedBytesAmount::from(left.size));
+
+            if !(left_padding && right_padding)
+                || left.size != right.size
+                || left_offset % (2 * size) != 0
+            {
+                break;
+            }
 
-    PieceAlignment {
-        left_bytes: UnpaddedBytesAmount(left_bytes),
-        right_bytes: UnpaddedBytesAmount(right_bytes),
+            let (_, right, _) = coalesced.pop().unwrap();
+            let (left_offset, left, _) = coalesced.pop().unwrap();
+            coalesced.push((
+                left_offset,
+                join_piece_infos::<DefaultPieceHasher>(left, right),
+                true,
+            ));
+        }
     }
-}
-
-/// Wraps a Readable source with null bytes on either end according to a provided PieceAlignment.
-fn with_alignment(source: impl Read, piece_alignment: PieceAlignment) -> impl Read {
-    let PieceAlignment {
-        left_bytes,
-        right_bytes,
-    } = piece_alignment;
 
-    let left_padding = Cursor::new(vec![0; left_bytes.into()]);
-    let right_padding = Cursor::new(vec![0; right_bytes.into()]);
+    coalesced.into_iter().map(|(_, piece, _)| piece).collect()
+}
 
-    left_padding.chain(source).chain(right_padding)
+pub fn compute_comm_d(sector_size: SectorSize, piece_infos: &[PieceInfo]) -> Result<Commitment> {
+    compute_comm_d_with::<DefaultPieceHasher>(sector_size, piece_infos)
 }
 
-/// Given an enumeration of pieces in a staged sector and a piece to be added (represented by a Read
-/// and corresponding length, in UnpaddedBytesAmount) to the staged sector, produce a new Read and
-/// UnpaddedBytesAmount pair which includes the appropriate amount of alignment bytes for the piece
-/// to be written to the target staged sector.
-pub fn get_aligned_source<T: Read>(
-    source: T,
-    pieces: &[UnpaddedBytesAmount],
-    piece_bytes: UnpaddedBytesAmount,
-) -> (UnpaddedBytesAmount, PieceAlignment, impl Read) {
-    let written_bytes = sum_piece_bytes_with_alignment(pieces);
-    let piece_alignment = get_piece_alignment(written_bytes, piece_bytes);
-    let expected_num_bytes_written =
-        piece_alignment.left_bytes + piece_bytes + piece_alignment.right_bytes;
+/// Compute `comm_d` from the provided `piece_infos`, running inside the supplied thread `pool`
+/// instead of rayon's global pool, so callers can bound the parallelism used per call.
+pub fn compute_comm_d_with_pool(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+    pool: &rayon::ThreadPool,
+) -> Result<Commitment> {
+    pool.install(|| compute_comm_d(sector_size, piece_infos))
+}
 
-    (
-        expected_num_bytes_written,
-        piece_alignment.clone(),
-        with_alignment(source, piece_alignment),
-    )
+/// Compute `comm_d` from the provided `piece_infos`, using `H` to hash the piece tree.
+pub fn compute_comm_d_with<H: Hasher>(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+) -> Result<Commitment> {
+    compute_comm_d_traced::<H, _>(sector_size, piece_infos, |_, _, _| {})
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::api::util::commitment_from_fr;
+/// Compute `comm_d` from the provided `piece_infos`, calling `trace` after each piece is added.
+///
+/// The callback receives the index of the piece, its commitment and the top of the piece stack
+/// after the piece has been merged with its completed siblings, which helps to track down where
+/// an unexpected `comm_d` comes from.
+pub fn compute_comm_d_traced<H, F>(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+    mut trace: F,
+) -> Result<Commitment>
+where
+    H: Hasher,
+    F: FnMut(usize, &Commitment, &PieceInfo),
+{
+    info!("verifying {} pieces", piece_infos.len());
+    ensure!(!piece_infos.is_empty(), "Missing piece infos");
 
-    use paired::bls12_381::{Bls12, Fr};
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+
+    ensure!(
+        piece_infos.len() as u64 <= u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE,
+        "Too many pieces"
+    );
+
+    // make sure the piece sizes are at most a sector size large
+    let piece_size: u64 = piece_infos
+        .iter()
+        .map(|info| u64::from(PaddedBytesAmount::from(info.size)))
+        .sum();
+
+    ensure!(
+        piece_size <= u64::from(sector_size),
+        "Piece is larger than sector."
+    );
+
+    let mut stack = Stack::new();
+
+    for (i, piece_info) in piece_infos.iter().enumerate() {
+        push_piece::<H>(&mut stack, piece_info.clone())?;
+        trace(
+            i,
+            &piece_info.commitment,
+            stack.peek().expect("stack can not be empty"),
+        );
+    }
+
+    Ok(finish_comm_d::<H>(stack))
+}
+
+/// Compute `comm_d` from the pieces yielded by `pieces`, reducing the piece tree as they arrive,
+/// so they never need to be collected. The first error yielded by `pieces` is returned as is.
+pub fn compute_comm_d_iter<I>(sector_size: SectorSize, pieces: I) -> Result<Commitment>
+where
+    I: Iterator<Item = Result<PieceInfo>>,
+{
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    let max_pieces = u64::from(unpadded_sector) / MINIMUM_PIECE_SIZE;
+
+    let mut stack = Stack::new();
+    let mut num_pieces = 0;
+    let mut piece_size = 0;
+
+    for piece_info in pieces {
+        let piece_info = piece_info?;
+
+        num_pieces += 1;
+        ensure!(num_pieces <= max_pieces, "Too many pieces");
+
+        // make sure the piece sizes are at most a sector size large
+        piece_size += u64::from(PaddedBytesAmount::from(piece_info.size));
+        ensure!(
+            piece_size <= u64::from(sector_size),
+            "Piece is larger than sector."
+        );
+
+        push_piece::<DefaultPieceHasher>(&mut stack, piece_info)?;
+    }
+
+    info!("verified {} pieces", num_pieces);
+    ensure!(num_pieces > 0, "Missing piece infos");
+
+    Ok(finish_comm_d::<DefaultPieceHasher>(stack))
+}
+
+/// Compute `comm_d` of a sector holding the unpadded bytes read from `source`, followed by zeros.
+///
+/// The bytes are padded and hashed into the data tree as they are read, so neither the sector nor
+/// the piece infos of the data are ever held in memory.
+pub fn compute_comm_d_streaming<R: Read>(source: R, sector_size: SectorSize) -> Result<Commitment> {
+    streaming_comm_d(source, sector_size).map(|(comm_d, _)| comm_d)
+}
+
+/// Compute `comm_d` like `compute_comm_d_streaming`, also returning the number of bytes read.
+pub(crate) fn streaming_comm_d<R: Read>(
+    source: R,
+    sector_size: SectorSize,
+) -> Result<(Commitment, u64)> {
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    let mut builder = SparseTreeBuilder::default();
+
+    // The tail of the sector is zero, so a partial last unit is completed with zeros.
+    let read = for_each_padded_chunk(source, |read, padded| {
+        ensure!(
+            read <= u64::from(unpadded_sector),
+            "source exceeds the sector ({:?})",
+            unpadded_sector
+        );
+        for node in padded.chunks(NODE_SIZE) {
+            builder.push(node);
+        }
+        Ok(())
+    })?;
+
+    builder.pad_with_zeros(u64::from(sector_size) / NODE_SIZE as u64);
+
+    Ok((builder.root()?, read))
+}
+
+/// Compute `comm_d` from pieces of arbitrary (unpadded) sizes.
+///
+/// Each piece is rounded up to the smallest valid piece size holding it, using the right
+/// alignment from `get_piece_alignment`, so its commitment must be the one of the piece zero
+/// extended to that size. `compute_comm_d` then aligns the pieces to their size, like `add_piece`
+/// does.
+pub fn compute_comm_d_unaligned(
+    sector_size: SectorSize,
+    raw_pieces: &[(Commitment, UnpaddedBytesAmount)],
+) -> Result<Commitment> {
+    let piece_infos = raw_pieces
+        .iter()
+        .map(|(commitment, size)| {
+            ensure!(u64::from(*size) > 0, "Piece size must not be zero");
+
+            let alignment = get_piece_alignment(UnpaddedBytesAmount(0), *size);
+            Ok(PieceInfo {
+                commitment: *commitment,
+                size: *size + alignment.right_bytes,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    compute_comm_d(sector_size, &piece_infos)
+}
+
+/// Compute `comm_d` of a sector which starts with `leading` zero bytes, followed by `pieces`.
+///
+/// The zero region is covered by the largest aligned zero padding pieces, so `leading` must be a
+/// multiple of the minimum piece size.
+pub fn comm_d_with_leading_zeros(
+    sector_size: SectorSize,
+    leading: UnpaddedBytesAmount,
+    pieces: &[PieceInfo],
+) -> Result<Commitment> {
+    ensure!(
+        u64::from(leading) % MINIMUM_PIECE_SIZE == 0,
+        "Leading zeros ({:?}) must be a multiple of the minimum piece size ({})",
+        leading,
+        MINIMUM_PIECE_SIZE
+    );
+
+    let mut piece_infos = Vec::with_capacity(pieces.len() + 64);
+
+    // Starting at the beginning of the sector, each next largest power of two is aligned.
+    let mut remaining = u64::from(PaddedBytesAmount::from(leading));
+    while remaining > 0 {
+        let size = 1u64 << (63 - remaining.leading_zeros());
+        piece_infos.push(zero_padding::<DefaultPieceHasher>(
+            PaddedBytesAmount(size).into(),
+        ));
+        remaining -= size;
+    }
+    piece_infos.extend_from_slice(pieces);
+
+    compute_comm_d(sector_size, &piece_infos)
+}
+
+/// Add the next piece to the piece tree, after the zero padding which aligns it to its size.
+fn push_piece<H: Hasher>(stack: &mut Stack, piece_info: PieceInfo) -> Result<()> {
+    ensure!(
+        u64::from(PaddedBytesAmount::from(piece_info.size)).is_power_of_two(),
+        "Piece size ({:?}) must be a power of 2.",
+        PaddedBytesAmount::from(piece_info.size)
+    );
+
+    while let Some(size) = stack.peek().map(|top| top.size) {
+        if size >= piece_info.size {
+            break;
+        }
+        stack.shift_reduce::<H>(zero_padding::<H>(size))
+    }
+
+    stack.shift_reduce::<H>(piece_info);
+
+    Ok(())
+}
+
+/// Pad the piece tree with zeros and return its root.
+fn finish_comm_d<H: Hasher>(mut stack: Stack) -> Commitment {
+    // Pad until only a single element is left, i.e. there is no second to last element.
+    while let Some(size) = stack.peek2().and(stack.peek()).map(|top| top.size) {
+        stack.shift_reduce::<H>(zero_padding::<H>(size));
+    }
+
+    assert_eq!(stack.len(), 1);
+
+    stack.pop().commitment
+}
+
+/// Build a merkle inclusion proof of the piece at `target_index` against `comm_d`.
+///
+/// The leaf of the returned proof is the commitment of the target piece, and the path runs from
+/// the subtree of that piece up to `comm_d`. The proof validates against the index of the piece
+/// among all subtrees of its size in the sector.
+pub fn piece_inclusion_proof(
+    sector_size: SectorSize,
+    pieces: &[PieceInfo],
+    target_index: usize,
+) -> Result<MerkleProof<DefaultPieceHasher>> {
+    ensure!(
+        target_index < pieces.len(),
+        "Target piece {} out of range, only {} pieces",
+        target_index,
+        pieces.len()
+    );
+
+    // Validates the pieces, so the reduction below can not fail.
+    let comm_d = compute_comm_d(sector_size, pieces)?;
+
+    /// Reduction stack entries, marking the one which contains the target piece.
+    struct Entry {
+        piece: PieceInfo,
+        is_target: bool,
+    }
+
+    fn shift_reduce(
+        stack: &mut Vec<Entry>,
+        path: &mut Vec<(Commitment, bool)>,
+        piece: PieceInfo,
+        is_target: bool,
+    ) {
+        stack.push(Entry { piece, is_target });
+
+        while stack.len() > 1
+            && stack[stack.len() - 1].piece.size == stack[stack.len() - 2].piece.size
+        {
+            let right = stack.pop().expect("empty stack popped");
+            let left = stack.pop().expect("empty stack popped");
+
+            if left.is_target {
+                path.push((right.piece.commitment, false));
+            } else if right.is_target {
+                path.push((left.piece.commitment, true));
+            }
+
+            stack.push(Entry {
+                piece: join_piece_infos::<DefaultPieceHasher>(left.piece, right.piece),
+                is_target: left.is_target || right.is_target,
+            });
+        }
+    }
+
+    let mut stack = Vec::new();
+    let mut path = Vec::new();
+
+    for (i, piece_info) in pieces.iter().enumerate() {
+        while !stack.is_empty() && stack[stack.len() - 1].piece.size < piece_info.size {
+            let padding = zero_padding::<DefaultPieceHasher>(stack[stack.len() - 1].piece.size);
+            shift_reduce(&mut stack, &mut path, padding, false);
+        }
+
+        shift_reduce(&mut stack, &mut path, piece_info.clone(), i == target_index);
+    }
+
+    while stack.len() > 1 {
+        let padding = zero_padding::<DefaultPieceHasher>(stack[stack.len() - 1].piece.size);
+        shift_reduce(&mut stack, &mut path, padding, false);
+    }
+
+    let as_domain = |commitment: &Commitment| {
+        <DefaultPieceHasher as Hasher>::Domain::try_from_bytes(commitment)
+    };
+
+    Ok(MerkleProof::from_parts(
+        as_domain(&comm_d)?,
+        as_domain(&pieces[target_index].commitment)?,
+        path.iter()
+            .map(|(commitment, is_right)| Ok((as_domain(commitment)?, *is_right)))
+            .collect::<Result<_>>()?,
+    ))
+}
+
+/// A proof that a piece is committed to by `comm_d`, which reveals none of the other pieces.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PieceInclusionProof {
+    /// The subtree the piece is placed at.
+    pub position: Subtree,
+    /// The siblings of the piece and its ancestors, from the piece up to `comm_d`.
+    pub path: Vec<Commitment>,
+}
+
+/// Build the inclusion proof of the piece at `target_index` against `comm_d`, the merkle path of
+/// `DefaultPieceHasher` nodes from the piece up to `comm_d`.
+pub fn generate_piece_inclusion_proof(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+    target_index: usize,
+) -> Result<PieceInclusionProof> {
+    let (position, path) = inclusion_path(sector_size, piece_infos, target_index)?;
+
+    Ok(PieceInclusionProof { position, path })
+}
+
+/// The subtree the piece at `target_index` is placed at, and the siblings along its
+/// `piece_inclusion_proof`, from the piece up to `comm_d`.
+fn inclusion_path(
+    sector_size: SectorSize,
+    piece_infos: &[PieceInfo],
+    target_index: usize,
+) -> Result<(Subtree, Vec<Commitment>)> {
+    let proof = piece_inclusion_proof(sector_size, piece_infos, target_index)?;
+
+    let height = (u64::from(PaddedBytesAmount::from(piece_infos[target_index].size))
+        / NODE_SIZE as u64)
+        .trailing_zeros() as usize;
+    let mut index = 0;
+    let mut path = Vec::with_capacity(proof.path().len());
+    for (level, (sibling, is_right)) in proof.path().iter().enumerate() {
+        if *is_right {
+            index |= 1 << level;
+        }
+
+        let mut commitment = [0u8; 32];
+        commitment.copy_from_slice(AsRef::<[u8]>::as_ref(sibling));
+        path.push(commitment);
+    }
+
+    Ok((Subtree { height, index }, path))
+}
+
+/// Verify that `proof` shows `piece_info` to be committed to by `comm_d`.
+pub fn verify_piece_inclusion_proof(
+    comm_d: &Commitment,
+    proof: &PieceInclusionProof,
+    piece_info: &PieceInfo,
+) -> bool {
+    let padded_size = u64::from(PaddedBytesAmount::from(piece_info.size));
+    if !padded_size.is_power_of_two()
+        || (padded_size / NODE_SIZE as u64).trailing_zeros() as usize != proof.position.height
+        || proof.path.len() >= std::mem::size_of::<usize>() * 8
+        || proof.position.index >> proof.path.len() != 0
+    {
+        return false;
+    }
+
+    let root =
+        proof
+            .path
+            .iter()
+            .enumerate()
+            .fold(piece_info.commitment, |node, (level, sibling)| {
+                if (proof.position.index >> level) & 1 == 0 {
+                    hash_pair(&node, sibling)
+                } else {
+                    hash_pair(sibling, &node)
+                }
+            });
+
+    &root == comm_d
+}
+
+/// Writer which builds the data tree of everything written through it, so that `comm_d` is
+/// available as soon as the write completes, without reading the sector back.
+///
+/// The inner writer must start out empty, and bytes are written as they are laid out in the
+/// sector, i.e. already padded. The sector is filled up with zeros on `finalize`, which matches
+/// `compute_comm_d` for the same pieces. As `write_padded` rewrites the last, partially written,
+/// byte when continuing an unaligned write, the most recent bytes are only hashed once
+/// following bytes have been written.
+#[derive(Debug)]
+pub struct CommDWriter<W> {
+    inner: W,
+    sector_size: SectorSize,
+    /// Current position in the inner writer.
+    position: u64,
+    /// Number of bytes which have already been hashed.
+    hashed: u64,
+    /// Bytes which have been written, but not yet hashed.
+    pending: Vec<u8>,
+    /// Roots of the completed subtrees, together with their height.
+    stack: Vec<(usize, Commitment)>,
+}
+
+impl<W: Write> CommDWriter<W> {
+    pub fn new(inner: W, sector_size: SectorSize) -> Self {
+        CommDWriter {
+            inner,
+            sector_size,
+            position: 0,
+            hashed: 0,
+            pending: Vec::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Flush the inner writer and return the `comm_d` of the sector.
+    pub fn finalize(mut self) -> Result<Commitment> {
+        self.inner.flush()?;
+
+        let sector_bytes = u64::from(self.sector_size);
+        ensure!(
+            self.hashed + self.pending.len() as u64 <= sector_bytes,
+            "Wrote {} bytes, which is more than the sector size {}",
+            self.hashed + self.pending.len() as u64,
+            sector_bytes
+        );
+
+        let padded_len = (self.pending.len() + NODE_SIZE - 1) / NODE_SIZE * NODE_SIZE;
+        self.pending.resize(padded_len, 0);
+        self.hash_pending(false);
+
+        let height = (sector_bytes / NODE_SIZE as u64).trailing_zeros() as usize;
+        if self.stack.is_empty() {
+            shift_node(&mut self.stack, 0, [0; 32]);
+        }
+        while self.stack.len() > 1 || self.stack[0].0 < height {
+            let top = self.stack[self.stack.len() - 1].0;
+            shift_node(&mut self.stack, top, zero_root::<DefaultPieceHasher>(top));
+        }
+
+        Ok(self.stack[0].1)
+    }
+
+    /// Hash all complete nodes in `pending`, except for the last one if `keep_last` is set and no
+    /// bytes follow it.
+    fn hash_pending(&mut self, keep_last: bool) {
+        let keep = if keep_last { 1 } else { 0 };
+        let nodes = (self.pending.len().saturating_sub(keep)) / NODE_SIZE;
+
+        for node in self.pending[..nodes * NODE_SIZE].chunks(NODE_SIZE) {
+            let mut commitment = [0u8; 32];
+            commitment.copy_from_slice(node);
+            shift_node(&mut self.stack, 0, commitment);
+        }
+
+        self.pending.drain(..nodes * NODE_SIZE);
+        self.hashed += (nodes * NODE_SIZE) as u64;
+    }
+}
+
+impl<W: Write> Write for CommDWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.hashed + self.pending.len() as u64;
+        if self.position < self.hashed || self.position > end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "can not write at {}, only bytes in {}..{} can be written",
+                    self.position, self.hashed, end
+                ),
+            ));
+        }
+
+        let n = self.inner.write(buf)?;
+
+        let offset = (self.position - self.hashed) as usize;
+        let overwritten = std::cmp::min(n, self.pending.len() - offset);
+        self.pending[offset..offset + overwritten].copy_from_slice(&buf[..overwritten]);
+        self.pending.extend_from_slice(&buf[overwritten..n]);
+        self.position += n as u64;
+
+        self.hash_pending(true);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Read> Read for CommDWriter<W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Seek> Seek for CommDWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Push the root of a subtree of the given height onto the stack, joining equally high subtrees.
+fn shift_node(stack: &mut Vec<(usize, Commitment)>, mut height: usize, mut node: Commitment) {
+    while let Some((top_height, top)) = stack.last() {
+        if *top_height != height {
+            break;
+        }
+
+        let h = piece_hash::<DefaultPieceHasher>(top, &node);
+        node.copy_from_slice(h.as_ref());
+        height += 1;
+        stack.pop();
+    }
+
+    stack.push((height, node));
+}
+
+/// Builds the root of the tree over a stream of nodes, without hashing runs of zero nodes.
+///
+/// Zero nodes are only counted as they arrive. Once the run ends, it is added as the largest zero
+/// subtrees aligned to their position, whose roots come from the zero root table, so a run of `n`
+/// zero nodes costs `O(log n)` instead of `n` hashes.
+#[derive(Debug)]
+pub(crate) struct SparseTreeBuilder {
+    /// Roots of the completed subtrees, together with their height and whether they are all zero.
+    stack: Vec<(usize, Commitment, bool)>,
+    /// Number of nodes in the completed subtrees.
+    nodes: u64,
+    /// Number of zero nodes following the completed subtrees, which have not been added yet.
+    zeros: u64,
+    zero_roots: Arc<Vec<Commitment>>,
+}
+
+impl Default for SparseTreeBuilder {
+    fn default() -> Self {
+        SparseTreeBuilder {
+            stack: Vec::new(),
+            nodes: 0,
+            zeros: 0,
+            zero_roots: zero_roots::<DefaultPieceHasher>(),
+        }
+    }
+}
+
+impl SparseTreeBuilder {
+    /// Append the next node, which must be `NODE_SIZE` bytes long.
+    pub fn push(&mut self, node: &[u8]) {
+        if node.iter().all(|byte| *byte == 0) {
+            self.zeros += 1;
+            return;
+        }
+
+        self.add_zeros();
+        let mut commitment = [0u8; 32];
+        commitment.copy_from_slice(node);
+        self.push_subtree(0, commitment, false);
+    }
+
+    /// Append zero nodes until the tree holds `nodes` nodes.
+    pub fn pad_with_zeros(&mut self, nodes: u64) {
+        self.zeros = std::cmp::max(self.zeros, nodes.saturating_sub(self.nodes));
+    }
+
+    /// Add the pending zero nodes as the largest zero subtrees aligned to their position.
+    fn add_zeros(&mut self) {
+        while self.zeros > 0 {
+            let mut height = 63 - self.zeros.leading_zeros() as usize;
+            if self.nodes > 0 {
+                height = std::cmp::min(height, self.nodes.trailing_zeros() as usize);
+            }
+
+            self.push_subtree(height, self.zero_roots[height], true);
+            self.zeros -= 1 << height;
+        }
+    }
+
+    /// Append the root of a complete subtree of the given height, whose position must be aligned
+    /// to its size.
+    fn push_subtree(&mut self, mut height: usize, mut commitment: Commitment, mut is_zero: bool) {
+        let size = 1 << height;
+
+        while let Some((top_height, top, top_is_zero)) = self.stack.last() {
+            if *top_height != height {
+                break;
+            }
+
+            if is_zero && *top_is_zero {
+                commitment = self.zero_roots[height + 1];
+            } else {
+                let h = piece_hash::<DefaultPieceHasher>(top, &commitment);
+                commitment.copy_from_slice(h.as_ref());
+                is_zero = false;
+            }
+            height += 1;
+            self.stack.pop();
+        }
+
+        self.stack.push((height, commitment, is_zero));
+        self.nodes += size;
+    }
+
+    /// Returns the root of the tree, which requires a power of two number of nodes.
+    pub fn root(mut self) -> Result<Commitment> {
+        self.add_zeros();
+        ensure!(
+            self.nodes.is_power_of_two(),
+            "{} nodes do not form a complete tree",
+            self.nodes
+        );
+
+        Ok(self.stack[0].1)
+    }
+}
+
+/// Number of 127 byte units `for_each_padded_chunk` reads at once.
+const PADDED_CHUNK_UNITS: usize = 1024;
+
+/// Read `source` to the end, calling `f` with the number of bytes read so far and the padded bytes
+/// of each chunk read. Returns the number of bytes read.
+///
+/// Chunks of whole 127 byte units pad to whole nodes, independently of each other. Only the last
+/// chunk can be short, a partial unit at its end is completed with zeros.
+pub(crate) fn for_each_padded_chunk<R, F>(mut source: R, mut f: F) -> Result<u64>
+where
+    R: Read,
+    F: FnMut(u64, &[u8]) -> Result<()>,
+{
+    let chunk_size = 127 * PADDED_CHUNK_UNITS;
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut padded = Vec::new();
+    let mut read = 0;
+
+    loop {
+        chunk.clear();
+        source
+            .by_ref()
+            .take(chunk_size as u64)
+            .read_to_end(&mut chunk)?;
+        if chunk.is_empty() {
+            break;
+        }
+        read += chunk.len() as u64;
+
+        let last = chunk.len() < chunk_size;
+        let partial = chunk.len() % 127;
+        if partial != 0 {
+            chunk.resize(chunk.len() + 127 - partial, 0);
+        }
+
+        padded.clear();
+        write_padded(&chunk[..], Cursor::new(&mut padded))?;
+        f(read, &padded)?;
+
+        if last {
+            break;
+        }
+    }
+
+    Ok(read)
+}
+
+/// Stack used for piece reduction.
+#[derive(Debug, Clone)]
+struct Stack(Vec<PieceInfo>);
+
+impl Stack {
+    /// Creates a new stack.
+    pub fn new() -> Self {
+        Stack(Vec::new())
+    }
+
+    /// Pushes a single element onto the stack.
+    pub fn shift(&mut self, el: PieceInfo) {
+        self.0.push(el)
+    }
+
+    /// Look at the last element of the stack, if any.
+    pub fn peek(&self) -> Option<&PieceInfo> {
+        self.0.last()
+    }
+
+    /// Look at the second to last element of the stack, if any.
+    pub fn peek2(&self) -> Option<&PieceInfo> {
+        self.0.len().checked_sub(2).map(|i| &self.0[i])
+    }
+
+    /// Pop the last element of the stack.
+    pub fn pop(&mut self) -> PieceInfo {
+        self.0.pop().expect("empty stack popped")
+    }
+
+    pub fn reduce1<H: Hasher>(&mut self) -> bool {
+        let same_size = match (self.peek(), self.peek2()) {
+            (Some(a), Some(b)) => a.size == b.size,
+            _ => false,
+        };
+
+        if same_size {
+            let right = self.pop();
+            let left = self.pop();
+            let joined = join_piece_infos::<H>(left, right);
+            self.shift(joined);
+            return true;
+        }
+
+        false
+    }
+
+    pub fn reduce<H: Hasher>(&mut self) {
+        while self.reduce1::<H>() {}
+    }
+
+    pub fn shift_reduce<H: Hasher>(&mut self, piece: PieceInfo) {
+        self.shift(piece);
+        self.reduce::<H>();
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Number of heights in a zero root table, enough for subtrees of any sector size.
+const ZERO_ROOT_HEIGHTS: usize = 64;
+
+type ZeroRootTables = HashMap<String, Arc<Vec<Commitment>>>;
+
+lazy_static! {
+    /// Roots of the subtrees with only zero leaves, indexed by height, keyed by the name of the
+    /// piece hasher.
+    static ref ZERO_ROOTS: RwLock<ZeroRootTables> = Default::default();
+}
+
+#[cfg(test)]
+static ZERO_PADDING_CACHE_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Compute the zero roots of subtrees up to `max_sector_size`, so that later calls to
+/// `compute_comm_d` don't have to compute them.
+pub fn precompute_zero_padding(max_sector_size: SectorSize) {
+    let height = (u64::from(max_sector_size) / NODE_SIZE as u64).trailing_zeros() as usize;
+    zero_root::<DefaultPieceHasher>(height);
+}
+
+/// Create a padding `PieceInfo` of size `size`.
+fn zero_padding<H: Hasher>(size: UnpaddedBytesAmount) -> PieceInfo {
+    let padded_size = u64::from(PaddedBytesAmount::from(size));
+    assert!(
+        padded_size.is_power_of_two() && padded_size >= NODE_SIZE as u64,
+        "Invalid padding size: {:?}",
+        size
+    );
+    let height = (padded_size / NODE_SIZE as u64).trailing_zeros() as usize;
+
+    PieceInfo {
+        size,
+        commitment: zero_root::<H>(height),
+    }
+}
+
+/// Root of a subtree of the given height with only zero leaves.
+fn zero_root<H: Hasher>(height: usize) -> Commitment {
+    zero_roots::<H>()[height]
+}
+
+/// The zero root table of `H`, which is computed on first use.
+fn zero_roots<H: Hasher>() -> Arc<Vec<Commitment>> {
+    let name = H::name();
+    if let Some(roots) = ZERO_ROOTS.read().unwrap().get(&name) {
+        #[cfg(test)]
+        ZERO_PADDING_CACHE_HITS.fetch_add(1, Ordering::SeqCst);
+
+        return roots.clone();
+    }
+
+    let mut root = [0u8; 32];
+    root.copy_from_slice(H::Domain::zero().as_ref());
+    let mut roots = Vec::with_capacity(ZERO_ROOT_HEIGHTS);
+    roots.push(root);
+    while roots.len() < ZERO_ROOT_HEIGHTS {
+        let h = piece_hash::<H>(&root, &root);
+        root.copy_from_slice(h.as_ref());
+        roots.push(root);
+    }
+
+    ZERO_ROOTS
+        .write()
+        .unwrap()
+        .entry(name)
+        .or_insert_with(|| Arc::new(roots))
+        .clone()
+}
+
+/// Compute `comm_d` of a sector whose (padded) bytes are `pattern` repeated over the whole sector,
+/// without building the sector.
+///
+/// All subtrees of the same height whose offsets are equal modulo the pattern's period in nodes
+/// have the same root, so only `period` roots are computed per level. For a single byte pattern
+/// this reduces to hashing one node with itself once per level.
+pub fn pattern_comm_d(sector_size: SectorSize, pattern: &[u8]) -> Result<Commitment> {
+    ensure!(!pattern.is_empty(), "Pattern must not be empty");
+
+    let sector_bytes = u64::from(sector_size) as usize;
+    ensure!(
+        sector_bytes >= NODE_SIZE && sector_bytes.is_power_of_two(),
+        "Invalid sector size: {:?}",
+        sector_size
+    );
+    let sector_nodes = sector_bytes / NODE_SIZE;
+
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            let t = a % b;
+            a = b;
+            b = t;
+        }
+        a
+    };
+    // Number of nodes after which the leaves repeat.
+    let period = pattern.len() / gcd(pattern.len(), NODE_SIZE);
+
+    let mut roots = pattern
+        .iter()
+        .cycle()
+        .take(period * NODE_SIZE)
+        .cloned()
+        .collect::<Vec<u8>>()
+        .chunks(NODE_SIZE)
+        .map(|node| {
+            let mut commitment = [0u8; 32];
+            commitment.copy_from_slice(node);
+            commitment
+        })
+        .collect::<Vec<Commitment>>();
+
+    let mut subtree_nodes = 1;
+    while subtree_nodes < sector_nodes {
+        roots = (0..period)
+            .map(|offset| {
+                let left = &roots[offset];
+                let right = &roots[(offset + subtree_nodes) % period];
+
+                let mut commitment = [0u8; 32];
+                commitment.copy_from_slice(piece_hash::<DefaultPieceHasher>(left, right).as_ref());
+                commitment
+            })
+            .collect();
+        subtree_nodes *= 2;
+    }
+
+    Ok(roots[0])
+}
+
+/// Join two equally sized `PieceInfo`s together, by hashing them and adding their sizes.
+fn join_piece_infos<H: Hasher>(mut left: PieceInfo, right: PieceInfo) -> PieceInfo {
+    assert_eq!(left.size, right.size);
+    let h = piece_hash::<H>(&left.commitment, &right.commitment);
+
+    left.commitment.copy_from_slice(AsRef::<[u8]>::as_ref(&h));
+    left.size = left.size + right.size;
+    left
+}
+
+fn piece_hash<H: Hasher>(a: &[u8], b: &[u8]) -> H::Domain {
+    let mut buf = [0u8; NODE_SIZE * 2];
+    buf[..NODE_SIZE].copy_from_slice(a);
+    buf[NODE_SIZE..].copy_from_slice(b);
+    <H::Function as HashFunction<H::Domain>>::hash(&buf)
+}
+
+#[derive(Debug, Clone)]
+pub struct PieceAlignment {
+    pub left_bytes: UnpaddedBytesAmount,
+    pub right_bytes: UnpaddedBytesAmount,
+}
+
+impl PieceAlignment {
+    pub fn sum(&self, piece_size: UnpaddedBytesAmount) -> UnpaddedBytesAmount {
+        self.left_bytes + piece_size + self.right_bytes
+    }
+}
+
+/// Given a list of pieces, sum the number of bytes taken by those pieces in that order.
+pub fn sum_piece_bytes_with_alignment(pieces: &[UnpaddedBytesAmount]) -> UnpaddedBytesAmount {
+    pieces
+        .iter()
+        .fold(UnpaddedBytesAmount(0), |acc, piece_bytes| {
+            acc + get_piece_alignment(acc, *piece_bytes).sum(*piece_bytes)
+        })
+}
+
+/// Ensure that the given pieces, laid out with their alignment padding, fill the sector exactly.
+pub fn assert_sector_full(sector_size: SectorSize, pieces: &[PieceInfo]) -> Result<()> {
+    let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+    let sizes: Vec<_> = pieces.iter().map(|piece| piece.size).collect();
+    let written = sum_piece_bytes_with_alignment(&sizes);
+
+    ensure!(
+        written <= unpadded_sector,
+        "pieces ({:?}) overflow the sector ({:?})",
+        written,
+        unpadded_sector
+    );
+    ensure!(
+        written == unpadded_sector,
+        "sector is not full, {} bytes missing",
+        u64::from(unpadded_sector) - u64::from(written)
+    );
+
+    Ok(())
+}
+
+/// Given a list of pieces, find the byte where a given piece does or would start.
+pub fn get_piece_start_byte(
+    pieces: &[UnpaddedBytesAmount],
+    piece_bytes: UnpaddedBytesAmount,
+) -> UnpaddedByteIndex {
+    // sum up all the bytes taken by the ordered pieces
+    let last_byte = sum_piece_bytes_with_alignment(&pieces);
+    let alignment = get_piece_alignment(last_byte, piece_bytes);
+
+    // add only the left padding of the target piece to give the start of that piece's data
+    UnpaddedByteIndex::from(last_byte + alignment.left_bytes)
+}
+
+/// Given a list of pieces, as written by `add_piece`, return the range of data tree leaf indices
+/// occupied by the piece at `index`, including its right alignment padding. The range always spans
+/// a power of two number of leaves, and starts at a multiple of its length.
+///
+/// Panics if `index` is out of bounds.
+pub fn piece_leaf_range(pieces: &[UnpaddedBytesAmount], index: usize) -> Range<usize> {
+    let start = get_piece_start_byte(&pieces[..index], pieces[index]);
+    let alignment = get_piece_alignment(UnpaddedBytesAmount::from(start), pieces[index]);
+    let len = pieces[index] + alignment.right_bytes;
+
+    let leaf =
+        |bytes: UnpaddedBytesAmount| u64::from(PaddedBytesAmount::from(bytes)) as usize / NODE_SIZE;
+
+    leaf(start.into())..leaf(start.into()) + leaf(len)
+}
+
+/// Given a number of bytes already written to a staged sector (ignoring bit padding) and a number
+/// of bytes (before bit padding) to be added, return the alignment required to create a piece where
+/// len(piece) == len(sector size)/(2^n) and sufficient left padding to ensure simple merkle proof
+/// construction.
+pub fn get_piece_alignment(
+    written_bytes: UnpaddedBytesAmount,
+    piece_bytes: UnpaddedBytesAmount,
+) -> PieceAlignment {
+    get_piece_alignment_with_min(
+        written_bytes,
+        piece_bytes,
+        UnpaddedBytesAmount(MINIMUM_PIECE_SIZE),
+    )
+}
+
+/// Like `get_piece_alignment`, but pieces take up a power of two multiple of `min_piece_size`,
+/// instead of the default minimum piece size. A piece smaller than `min_piece_size` is padded up
+/// to it on the right.
+pub fn get_piece_alignment_with_min(
+    written_bytes: UnpaddedBytesAmount,
+    piece_bytes: UnpaddedBytesAmount,
+    min_piece_size: UnpaddedBytesAmount,
+) -> PieceAlignment {
+    assert!(
+        u64::from(min_piece_size) > 0,
+        "minimum piece size must not be zero"
+    );
+
+    let mut piece_bytes_needed = u64::from(min_piece_size);
+
+    // Calculate the next power of two multiple that will fully contain the piece's data.
+    // This is required to ensure a clean piece merkle root, without being affected by
+    // preceding or following pieces.
+    while piece_bytes_needed < u64::from(piece_bytes) {
+        piece_bytes_needed *= 2;
+    }
+
+    // Calculate the bytes being affected from the left of the piece by the previous piece.
+    let encroaching = u64::from(written_bytes) % piece_bytes_needed;
+
+    // Calculate the bytes to push from the left to ensure a clean piece merkle root.
+    let left_bytes = if encroaching > 0 {
+        piece_bytes_needed - encroaching
+    } else {
+        0
+    };
+
+    let right_bytes = piece_bytes_needed - u64::from(piece_bytes);
+
+    PieceAlignment {
+        left_bytes: UnpaddedBytesAmount(left_bytes),
+        right_bytes: UnpaddedBytesAmount(right_bytes),
+    }
+}
+
+/// Wraps a Readable source with null bytes on either end according to a provided PieceAlignment.
+fn with_alignment(source: impl Read, piece_alignment: PieceAlignment) -> impl Read {
+    let PieceAlignment {
+        left_bytes,
+        right_bytes,
+    } = piece_alignment;
+
+    let left_padding = Cursor::new(vec![0; left_bytes.into()]);
+    let right_padding = Cursor::new(vec![0; right_bytes.into()]);
+
+    left_padding.chain(source).chain(right_padding)
+}
+
+/// Given an enumeration of pieces in a staged sector and a piece to be added (represented by a Read
+/// and corresponding length, in UnpaddedBytesAmount) to the staged sector, produce a new Read and
+/// UnpaddedBytesAmount pair which includes the appropriate amount of alignment bytes for the piece
+/// to be written to the target staged sector.
+pub fn get_aligned_source<T: Read>(
+    source: T,
+    pieces: &[UnpaddedBytesAmount],
+    piece_bytes: UnpaddedBytesAmount,
+) -> (UnpaddedBytesAmount, PieceAlignment, impl Read) {
+    let written_bytes = sum_piece_bytes_with_alignment(pieces);
+    let piece_alignment = get_piece_alignment(written_bytes, piece_bytes);
+    let expected_num_bytes_written =
+        piece_alignment.left_bytes + piece_bytes + piece_alignment.right_bytes;
+
+    (
+        expected_num_bytes_written,
+        piece_alignment.clone(),
+        with_alignment(source, piece_alignment),
+    )
+}
+
+/// Callback of `StagedSector::with_trace`, called with the index of each added piece, its
+/// commitment and the top of the piece stack, like the one of `compute_comm_d_traced`.
+pub type PieceTrace = Arc<dyn Fn(usize, &Commitment, &PieceInfo) + Send + Sync>;
+
+/// A sector being staged piece by piece, which keeps track of the pieces added so far and the
+/// alignment the next piece needs.
+#[derive(Clone)]
+pub struct StagedSector {
+    sector_size: SectorSize,
+    piece_lengths: Vec<UnpaddedBytesAmount>,
+    piece_infos: Vec<PieceInfo>,
+    /// The piece tree of the pieces added so far, reduced as far as possible.
+    stack: Stack,
+    trace: Option<PieceTrace>,
+}
+
+impl std::fmt::Debug for StagedSector {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("StagedSector")
+            .field("sector_size", &self.sector_size)
+            .field("piece_lengths", &self.piece_lengths)
+            .field("piece_infos", &self.piece_infos)
+            .field("stack", &self.stack)
+            .field("trace", &self.trace.is_some())
+            .finish()
+    }
+}
+
+impl StagedSector {
+    pub fn new(sector_size: SectorSize) -> Self {
+        StagedSector {
+            sector_size,
+            piece_lengths: Vec::new(),
+            piece_infos: Vec::new(),
+            stack: Stack::new(),
+            trace: None,
+        }
+    }
+
+    /// Call `trace` after each piece is added, with the index of the piece, its commitment and
+    /// the top of the piece stack after the piece has been merged with its completed siblings.
+    pub fn with_trace<F>(mut self, trace: F) -> Self
+    where
+        F: Fn(usize, &Commitment, &PieceInfo) + Send + Sync + 'static,
+    {
+        self.trace = Some(Arc::new(trace));
+        self
+    }
+
+    /// Write the `len` bytes of `source`, preceded by their alignment, to `sink`, which must be
+    /// positioned at the end of the pieces added so far.
+    ///
+    /// Fails without writing anything if the piece does not fit into the remaining space.
+    pub fn add_piece<R, W>(
+        &mut self,
+        source: R,
+        len: UnpaddedBytesAmount,
+        sink: W,
+    ) -> Result<PieceInfo>
+    where
+        R: Read,
+        W: Read + Write + Seek,
+    {
+        let required = get_piece_alignment(self.used(), len).sum(len);
+        ensure!(
+            required <= self.remaining(),
+            "piece of {:?} needs {:?} with its alignment, but only {:?} are left in the sector",
+            len,
+            required,
+            self.remaining()
+        );
+
+        let (_, commitment) = crate::api::add_piece(source, sink, len, &self.piece_lengths)?;
+        let piece_info = PieceInfo::new(commitment, len);
+
+        push_piece::<DefaultPieceHasher>(&mut self.stack, piece_info.clone())?;
+        self.piece_lengths.push(len);
+        self.piece_infos.push(piece_info.clone());
+
+        if let Some(trace) = &self.trace {
+            trace(
+                self.piece_infos.len() - 1,
+                &piece_info.commitment,
+                self.stack.peek().expect("stack can not be empty"),
+            );
+        }
+
+        Ok(piece_info)
+    }
+
+    /// The pieces added so far, in order.
+    pub fn pieces(&self) -> &[PieceInfo] {
+        &self.piece_infos
+    }
+
+    /// Number of bytes taken up by the pieces added so far, including their alignment.
+    pub fn used(&self) -> UnpaddedBytesAmount {
+        sum_piece_bytes_with_alignment(&self.piece_lengths)
+    }
+
+    /// Number of bytes left in the sector.
+    pub fn remaining(&self) -> UnpaddedBytesAmount {
+        UnpaddedBytesAmount::from(self.sector_size) - self.used()
+    }
+
+    /// The `comm_d` of the sector, with the space after the pieces added so far filled with zeros.
+    pub fn comm_d(&self) -> Result<Commitment> {
+        if self.piece_infos.is_empty() {
+            return Ok(zero_padding::<DefaultPieceHasher>(self.sector_size.into()).commitment);
+        }
+
+        compute_comm_d(self.sector_size, &self.piece_infos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::util::commitment_from_bls_fr;
+    use crate::fr32::write_padded;
+    use crate::types::sector_nodes;
+
+    use paired::bls12_381::Fr;
     use rand::{Rng, RngCore, SeedableRng};
     use rand_xorshift::XorShiftRng;
     use storage_proofs::drgraph::{new_seed, Graph, BASE_DEGREE};
+    use storage_proofs::hasher::Blake2sHasher;
+    use storage_proofs::pieces::generate_piece_commitment_bytes_from_source;
     use storage_proofs::stacked::{StackedBucketGraph, EXP_DEGREE};
 
-    use std::io::{Seek, SeekFrom};
+    use std::io::{Seek, SeekFrom};
+
+    #[test]
+    fn test_get_piece_alignment() {
+        let table = vec![
+            (0, 0, (0, 127)),
+            (0, 127, (0, 0)),
+            (0, 254, (0, 0)),
+            (0, 508, (0, 0)),
+            (0, 1016, (0, 0)),
+            (127, 127, (0, 0)),
+            (127, 254, (127, 0)),
+            (127, 508, (381, 0)),
+            (100, 100, (27, 27)),
+            (200, 200, (54, 54)),
+            (300, 300, (208, 208)),
+        ];
+
+        for (bytes_in_sector, bytes_in_piece, (expected_left_align, expected_right_align)) in
+            table.clone()
+        {
+            let PieceAlignment {
+                left_bytes: UnpaddedBytesAmount(actual_left_align),
+                right_bytes: UnpaddedBytesAmount(actual_right_align),
+            } = get_piece_alignment(
+                UnpaddedBytesAmount(bytes_in_sector),
+                UnpaddedBytesAmount(bytes_in_piece),
+            );
+            assert_eq!(
+                (expected_left_align, expected_right_align),
+                (actual_left_align, actual_right_align)
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_piece_alignment_with_min() {
+        let table = vec![
+            // The default minimum behaves like `get_piece_alignment`.
+            (127, 0, 0, (0, 127)),
+            (127, 127, 254, (127, 0)),
+            (127, 300, 300, (208, 208)),
+            // A minimum larger than the pieces.
+            (254, 0, 0, (0, 254)),
+            (254, 0, 127, (0, 127)),
+            (254, 127, 127, (127, 127)),
+            (254, 254, 300, (254, 208)),
+            // A minimum smaller than the default.
+            (32, 0, 10, (0, 22)),
+            (32, 10, 10, (22, 22)),
+            (32, 32, 40, (32, 24)),
+            (32, 0, 127, (0, 1)),
+        ];
+
+        for (min_piece_size, bytes_in_sector, bytes_in_piece, (expected_left, expected_right)) in
+            table
+        {
+            let PieceAlignment {
+                left_bytes: UnpaddedBytesAmount(actual_left),
+                right_bytes: UnpaddedBytesAmount(actual_right),
+            } = get_piece_alignment_with_min(
+                UnpaddedBytesAmount(bytes_in_sector),
+                UnpaddedBytesAmount(bytes_in_piece),
+                UnpaddedBytesAmount(min_piece_size),
+            );
+            assert_eq!(
+                (expected_left, expected_right),
+                (actual_left, actual_right),
+                "minimum {}, {} bytes written, piece of {} bytes",
+                min_piece_size,
+                bytes_in_sector,
+                bytes_in_piece
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_piece_start_byte() {
+        let pieces = [
+            UnpaddedBytesAmount(31),
+            UnpaddedBytesAmount(32),
+            UnpaddedBytesAmount(33),
+        ];
+
+        assert_eq!(
+            get_piece_start_byte(&pieces[..0], pieces[0]),
+            UnpaddedByteIndex(0)
+        );
+        assert_eq!(
+            get_piece_start_byte(&pieces[..1], pieces[1]),
+            UnpaddedByteIndex(127)
+        );
+        assert_eq!(
+            get_piece_start_byte(&pieces[..2], pieces[2]),
+            UnpaddedByteIndex(254)
+        );
+    }
+
+    #[test]
+    fn test_piece_leaf_range() {
+        let pieces = [
+            UnpaddedBytesAmount(127),
+            UnpaddedBytesAmount(300),
+            UnpaddedBytesAmount(127),
+            UnpaddedBytesAmount(1000),
+        ];
+
+        let ranges = (0..pieces.len())
+            .map(|i| piece_leaf_range(&pieces, i))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![0..4, 16..32, 32..36, 64..96]);
+
+        for (i, range) in ranges.iter().enumerate() {
+            let len = range.end - range.start;
+            assert!(len.is_power_of_two());
+            assert_eq!(range.start % len, 0);
+
+            let padded = u64::from(PaddedBytesAmount::from(pieces[i])) as usize;
+            assert!(len * NODE_SIZE >= padded);
+
+            // The ranges are ordered and do not overlap. Gaps are left padding.
+            if i > 0 {
+                assert!(ranges[i - 1].end <= range.start);
+            }
+        }
+
+        // Contiguous where no alignment padding is needed.
+        assert_eq!(ranges[1].end, ranges[2].start);
+    }
 
     #[test]
-    fn test_get_piece_alignment() {
-        let table = vec![
-            (0, 0, (0, 127)),
-            (0, 127, (0, 0)),
-            (0, 254, (0, 0)),
-            (0, 508, (0, 0)),
-            (0, 1016, (0, 0)),
-            (127, 127, (0, 0)),
-            (127, 254, (127, 0)),
-            (127, 508, (381, 0)),
-            (100, 100, (27, 27)),
-            (200, 200, (54, 54)),
-            (300, 300, (208, 208)),
+    fn test_generate_piece_inclusion_proof() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        //     g
+        //   /  \
+        //  e    f
+        // / \  / \
+        // a  b c  d
+
+        let (a, b, c, d): ([u8; 32], [u8; 32], [u8; 32], [u8; 32]) = rng.gen();
+        let e = hash_pair(&a, &b);
+        let f = hash_pair(&c, &d);
+        let g = hash_pair(&e, &f);
+
+        let pieces = [a, b, c, d]
+            .iter()
+            .map(|commitment| PieceInfo::new(*commitment, UnpaddedBytesAmount(127)))
+            .collect::<Vec<_>>();
+        let sector_size = SectorSize(4 * 128);
+
+        let proof = generate_piece_inclusion_proof(sector_size, &pieces, 2).unwrap();
+        assert_eq!(
+            proof.position,
+            Subtree {
+                height: 2,
+                index: 2
+            }
+        );
+        assert_eq!(proof.path, vec![d, e]);
+        assert!(verify_piece_inclusion_proof(&g, &proof, &pieces[2]));
+
+        // Other pieces, roots and positions don't verify.
+        assert!(!verify_piece_inclusion_proof(&g, &proof, &pieces[3]));
+        assert!(!verify_piece_inclusion_proof(&f, &proof, &pieces[2]));
+        let mut moved = proof.clone();
+        moved.position.index = 3;
+        assert!(!verify_piece_inclusion_proof(&g, &moved, &pieces[2]));
+        moved.position.index = 6;
+        assert!(!verify_piece_inclusion_proof(&g, &moved, &pieces[2]));
+        let larger = PieceInfo::new(c, UnpaddedBytesAmount(254));
+        assert!(!verify_piece_inclusion_proof(&g, &proof, &larger));
+
+        assert!(generate_piece_inclusion_proof(sector_size, &pieces, 4).is_err());
+    }
+
+    #[test]
+    fn test_verify_simple_pieces() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        //     g
+        //   /  \
+        //  e    f
+        // / \  / \
+        // a  b c  d
+
+        let (a, b, c, d): ([u8; 32], [u8; 32], [u8; 32], [u8; 32]) = rng.gen();
+
+        let mut e = [0u8; 32];
+        let h = piece_hash::<DefaultPieceHasher>(&a, &b);
+        e.copy_from_slice(h.as_ref());
+
+        let mut f = [0u8; 32];
+        let h = piece_hash::<DefaultPieceHasher>(&c, &d);
+        f.copy_from_slice(h.as_ref());
+
+        let mut g = [0u8; 32];
+        let h = piece_hash::<DefaultPieceHasher>(&e, &f);
+        g.copy_from_slice(h.as_ref());
+
+        let a = PieceInfo::new(a, UnpaddedBytesAmount(127));
+        let b = PieceInfo::new(b, UnpaddedBytesAmount(127));
+        let c = PieceInfo::new(c, UnpaddedBytesAmount(127));
+        let d = PieceInfo::new(d, UnpaddedBytesAmount(127));
+
+        let e = PieceInfo::new(e, UnpaddedBytesAmount(254));
+        let f = PieceInfo::new(f, UnpaddedBytesAmount(254));
+        let g = PieceInfo::new(g, UnpaddedBytesAmount(508));
+
+        let sector_size = SectorSize(4 * 128);
+        let comm_d = g.commitment;
+
+        // println!("e: {:?}", e);
+        // println!("f: {:?}", f);
+        // println!("g: {:?}", g);
+
+        assert!(
+            verify_pieces(
+                &comm_d,
+                &vec![a.clone(), b.clone(), c.clone(), d.clone()],
+                sector_size
+            )
+            .expect("failed to verify"),
+            "[a, b, c, d]"
+        );
+
+        assert!(
+            verify_pieces(&comm_d, &vec![e.clone(), c.clone(), d.clone()], sector_size)
+                .expect("failed to verify"),
+            "[e, c, d]"
+        );
+
+        assert!(
+            verify_pieces(&comm_d, &vec![e.clone(), f.clone()], sector_size)
+                .expect("failed to verify"),
+            "[e, f]"
+        );
+
+        assert!(
+            verify_pieces(&comm_d, &vec![a.clone(), b.clone(), f.clone()], sector_size)
+                .expect("failed to verify"),
+            "[a, b, f]"
+        );
+
+        assert!(
+            verify_pieces(&comm_d, &vec![g], sector_size).expect("failed to verify"),
+            "[g]"
+        );
+    }
+
+    #[test]
+    fn test_pattern_comm_d() {
+        let sector_size = SectorSize(32 * 64);
+
+        let patterns: Vec<Vec<u8>> = vec![
+            vec![0],
+            vec![0x11],
+            vec![1, 2, 3],
+            (0..64).collect(),
+            (0..100).collect(),
         ];
 
-        for (bytes_in_sector, bytes_in_piece, (expected_left_align, expected_right_align)) in
-            table.clone()
-        {
-            let PieceAlignment {
-                left_bytes: UnpaddedBytesAmount(actual_left_align),
-                right_bytes: UnpaddedBytesAmount(actual_right_align),
-            } = get_piece_alignment(
-                UnpaddedBytesAmount(bytes_in_sector),
-                UnpaddedBytesAmount(bytes_in_piece),
-            );
+        for pattern in &patterns {
+            let sector = pattern
+                .iter()
+                .cycle()
+                .take(u64::from(sector_size) as usize)
+                .cloned()
+                .collect::<Vec<u8>>();
+            let commitment = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+                &mut Cursor::new(&sector),
+            )
+            .unwrap();
+            let piece = PieceInfo::new(commitment, UnpaddedBytesAmount::from(sector_size));
+
             assert_eq!(
-                (expected_left_align, expected_right_align),
-                (actual_left_align, actual_right_align)
+                pattern_comm_d(sector_size, pattern).unwrap(),
+                compute_comm_d(sector_size, &[piece]).unwrap(),
+                "pattern {:?}",
+                pattern
             );
         }
+
+        assert_eq!(
+            pattern_comm_d(sector_size, &[0]).unwrap(),
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount::from(sector_size)).commitment
+        );
+        assert!(pattern_comm_d(sector_size, &[]).is_err());
+    }
+
+    #[test]
+    fn test_comm_d_writer() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 64);
+
+        // Padded bytes of a piece filling half of the sector, written in uneven chunks.
+        let mut piece_bytes = Vec::new();
+        let unpadded = UnpaddedBytesAmount::from(PaddedBytesAmount(1024));
+        let data = (0..u64::from(unpadded))
+            .map(|_| rng.gen())
+            .collect::<Vec<u8>>();
+        write_padded(&data[..], Cursor::new(&mut piece_bytes)).unwrap();
+
+        let mut writer = CommDWriter::new(Vec::new(), sector_size);
+        for chunk in piece_bytes.chunks(100) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.get_ref(), &piece_bytes);
+
+        let commitment = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+            &mut Cursor::new(&piece_bytes),
+        )
+        .unwrap();
+        let expected =
+            compute_comm_d(sector_size, &[PieceInfo::new(commitment, unpadded)]).unwrap();
+        assert_eq!(writer.finalize().unwrap(), expected);
+
+        // Unaligned writes through `write_padded`, which rewrite the last partial byte.
+        let mut writer = CommDWriter::new(Cursor::new(Vec::new()), sector_size);
+        write_padded(&data[..100], &mut writer).unwrap();
+        write_padded(&data[100..333], &mut writer).unwrap();
+        write_padded(&data[333..], &mut writer).unwrap();
+
+        let mut sector = writer.get_ref().get_ref().clone();
+        assert_eq!(sector, piece_bytes);
+        sector.resize(u64::from(sector_size) as usize, 0);
+        let from_scratch = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+            &mut Cursor::new(&sector),
+        )
+        .unwrap();
+        assert_eq!(from_scratch, expected);
+        assert_eq!(writer.finalize().unwrap(), from_scratch);
+
+        // Nothing written is an empty sector.
+        let writer = CommDWriter::new(Vec::new(), sector_size);
+        assert_eq!(
+            writer.finalize().unwrap(),
+            pattern_comm_d(sector_size, &[0]).unwrap()
+        );
+
+        let mut writer = CommDWriter::new(Vec::new(), sector_size);
+        writer.write_all(&sector).unwrap();
+        writer.write_all(&[0]).unwrap();
+        assert!(writer.finalize().is_err());
+    }
+
+    #[test]
+    fn test_sparse_tree_builder() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let nodes = 1 << 12;
+
+        // A few random nodes around a long zero run, followed by zeros up to the end.
+        let mut tree_bytes = vec![0u8; nodes * NODE_SIZE];
+        for node in (0..3).chain(3000..3005) {
+            for byte in &mut tree_bytes[node * NODE_SIZE..(node + 1) * NODE_SIZE - 1] {
+                *byte = rng.gen();
+            }
+        }
+
+        let mut builder = SparseTreeBuilder::default();
+        for node in tree_bytes[..3000 * NODE_SIZE].chunks(NODE_SIZE) {
+            builder.push(node);
+        }
+        // The zero run is only counted, until the next non zero node ends it.
+        assert_eq!(builder.stack.len(), 2);
+        assert_eq!(builder.zeros, 2997);
+
+        for node in tree_bytes[3000 * NODE_SIZE..3005 * NODE_SIZE].chunks(NODE_SIZE) {
+            builder.push(node);
+        }
+        builder.pad_with_zeros(nodes as u64);
+
+        let expected = generate_piece_commitment_bytes_from_source::<DefaultPieceHasher>(
+            &mut Cursor::new(&tree_bytes),
+        )
+        .unwrap();
+        assert_eq!(builder.root().unwrap(), expected);
+
+        // Nodes which do not fill a tree have no root.
+        let mut builder = SparseTreeBuilder::default();
+        builder.pad_with_zeros(3);
+        assert!(builder.root().is_err());
+    }
+
+    #[test]
+    fn test_piece_lists_equivalent() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(4 * 128);
+
+        let (a, b, c, d): ([u8; 32], [u8; 32], [u8; 32], [u8; 32]) = rng.gen();
+        let a = PieceInfo::new(a, UnpaddedBytesAmount(127));
+        let b = PieceInfo::new(b, UnpaddedBytesAmount(127));
+        let c = PieceInfo::new(c, UnpaddedBytesAmount(127));
+        let d = PieceInfo::new(d, UnpaddedBytesAmount(127));
+        let e = join_piece_infos::<DefaultPieceHasher>(a.clone(), b.clone());
+        let f = join_piece_infos::<DefaultPieceHasher>(c.clone(), d.clone());
+
+        let pieces = vec![a.clone(), b.clone(), c.clone(), d.clone()];
+
+        assert!(piece_lists_equivalent(&pieces, &[e.clone(), f.clone()], sector_size).unwrap());
+        assert!(piece_lists_equivalent(&pieces, &[a.clone(), b.clone(), f], sector_size).unwrap());
+        assert!(!piece_lists_equivalent(
+            &pieces,
+            &[b.clone(), a.clone(), c.clone(), d.clone()],
+            sector_size
+        )
+        .unwrap());
+        assert!(!piece_lists_equivalent(&pieces, &[e], sector_size).unwrap());
+    }
+
+    #[test]
+    fn test_explain_comm_d_mismatch() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 128);
+
+        let pieces = vec![
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(4 * 127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(2 * 127)),
+        ];
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        let reference = explain_comm_d_mismatch(&comm_d, sector_size, &pieces).unwrap();
+        assert!(reference.matches());
+        assert_eq!(reference.computed, comm_d);
+        assert_eq!(reference.first_divergence(&reference), None);
+
+        // Tamper with the third piece, which is placed right after the second one, at 1024 bytes.
+        let mut tampered = pieces.clone();
+        tampered[2].commitment = rng.gen();
+
+        let diff = explain_comm_d_mismatch(&comm_d, sector_size, &tampered).unwrap();
+        assert!(!diff.matches());
+        assert_eq!(diff.expected, comm_d);
+        assert_eq!(
+            diff.first_divergence(&reference),
+            Some(Subtree {
+                height: 2,
+                index: 1024 / 128
+            })
+        );
+
+        // Tampering with a larger piece localizes to that piece as well.
+        let mut tampered = pieces.clone();
+        tampered[1].commitment = rng.gen();
+
+        let diff = explain_comm_d_mismatch(&comm_d, sector_size, &tampered).unwrap();
+        assert_eq!(
+            diff.first_divergence(&reference),
+            Some(Subtree {
+                height: 4,
+                index: 512 / 512
+            })
+        );
+    }
+
+    #[test]
+    fn test_piece_multiproof() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 128);
+
+        let sizes = [1, 4, 2, 8, 1, 1];
+        let pieces = sizes
+            .iter()
+            .map(|size| PieceInfo::new(rng.gen(), UnpaddedBytesAmount(size * 127)))
+            .collect::<Vec<_>>();
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        for indices in &[vec![0], vec![0, 4], vec![1, 2, 5], vec![0, 1, 2, 3, 4, 5]] {
+            let proof = piece_multiproof(sector_size, &pieces, indices).unwrap();
+            let proven = indices
+                .iter()
+                .map(|i| pieces[*i].clone())
+                .collect::<Vec<_>>();
+
+            // The multiproof verifies, as do the single proofs of the same pieces.
+            assert!(proof.verify(&comm_d, &proven), "{:?}", indices);
+            for (i, (subtree, commitment)) in indices.iter().zip(proof.pieces.iter()) {
+                let single = piece_inclusion_proof(sector_size, &pieces, *i).unwrap();
+                assert_eq!(AsRef::<[u8]>::as_ref(single.root()), &comm_d[..]);
+                assert_eq!(AsRef::<[u8]>::as_ref(single.leaf()), &commitment[..]);
+                assert!(single.validate(subtree.index));
+            }
+
+            // Neither verifies against a different root.
+            let other: Commitment = rng.gen();
+            assert!(!proof.verify(&other, &proven));
+
+            // Nor without the expected pieces, or with pieces of another size.
+            assert!(!proof.verify(&comm_d, &[]));
+            let mut resized = proven.clone();
+            resized[0].size = UnpaddedBytesAmount(u64::from(resized[0].size) * 2);
+            assert!(!proof.verify(&comm_d, &resized));
+
+            // Authentication nodes are shared, so there are never more than the single proofs
+            // have together.
+            let single_nodes: usize = indices
+                .iter()
+                .map(|i| {
+                    piece_inclusion_proof(sector_size, &pieces, *i)
+                        .unwrap()
+                        .path()
+                        .len()
+                })
+                .sum();
+            assert!(proof.nodes.len() <= single_nodes);
+
+            let mut tampered = proof.clone();
+            tampered.pieces[0].1 = rng.gen();
+            assert!(!tampered.verify(&comm_d, &proven));
+
+            if !proof.nodes.is_empty() {
+                let mut incomplete = proof.clone();
+                incomplete.nodes.pop();
+                assert!(!incomplete.verify(&comm_d, &proven));
+            }
+        }
+
+        // Pieces 4 and 5 are siblings, so neither needs the other as an authentication node.
+        let pair = piece_multiproof(sector_size, &pieces, &[4, 5]).unwrap();
+        let single = piece_inclusion_proof(sector_size, &pieces, 4).unwrap();
+        assert_eq!(pair.nodes.len(), single.path().len() - 1);
+
+        // A piece claimed to be the root itself does not verify.
+        let at_root = PieceMultiProof {
+            tree_height: 7,
+            pieces: vec![(
+                Subtree {
+                    height: 7,
+                    index: 0,
+                },
+                comm_d,
+            )],
+            nodes: Vec::new(),
+        };
+        let root_piece = PieceInfo::new(comm_d, UnpaddedBytesAmount(128 * 127));
+        assert!(!at_root.verify(&comm_d, &[root_piece]));
+
+        assert!(piece_multiproof(sector_size, &pieces, &[]).is_err());
+        assert!(piece_multiproof(sector_size, &pieces, &[pieces.len()]).is_err());
+
+        let whole = vec![PieceInfo::new(rng.gen(), UnpaddedBytesAmount(128 * 127))];
+        assert!(piece_multiproof(sector_size, &whole, &[0]).is_err());
+    }
+
+    #[test]
+    fn test_shared_pieces() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        let shared = PieceInfo::new(rng.gen(), UnpaddedBytesAmount(254));
+        let a = vec![
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            shared.clone(),
+        ];
+        let b = vec![
+            shared.clone(),
+            // Same commitment, but a different size.
+            PieceInfo::new(a[0].commitment, UnpaddedBytesAmount(254)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+        ];
+
+        assert_eq!(shared_pieces(&a, &b), vec![(2, 0)]);
+        assert_eq!(shared_pieces(&b, &a), vec![(0, 2)]);
+        assert!(shared_pieces(&a, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_verify_pieces_detailed() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(4 * 128);
+
+        let pieces = vec![
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(254)),
+        ];
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        assert_eq!(
+            verify_pieces_detailed(&comm_d, &pieces, sector_size).unwrap(),
+            PieceVerification::Ok
+        );
+
+        let other_comm_d: Commitment = rng.gen();
+        assert_eq!(
+            verify_pieces_detailed(&other_comm_d, &pieces, sector_size).unwrap(),
+            PieceVerification::Mismatch {
+                expected: other_comm_d,
+                computed: comm_d,
+            }
+        );
+
+        let mut invalid = pieces.clone();
+        invalid[1].size = UnpaddedBytesAmount(3 * 127);
+        assert_eq!(
+            verify_pieces_detailed(&comm_d, &invalid, sector_size).unwrap(),
+            PieceVerification::InvalidPieceSize {
+                index: 1,
+                size: UnpaddedBytesAmount(3 * 127),
+            }
+        );
+        assert!(verify_pieces(&comm_d, &invalid, sector_size).is_err());
+
+        // The pieces add up to the sector size, but not once the second one is aligned.
+        let mut overflowing = pieces.clone();
+        overflowing.push(PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)));
+        assert_eq!(
+            verify_pieces_detailed(&comm_d, &overflowing, sector_size).unwrap(),
+            PieceVerification::Overflow {
+                required: UnpaddedBytesAmount(5 * 127),
+                available: UnpaddedBytesAmount(4 * 127),
+            }
+        );
+        assert!(verify_pieces(&comm_d, &overflowing, sector_size).is_err());
+    }
+
+    #[test]
+    fn test_verify_pieces_raw() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(4 * 128);
+
+        let pieces = vec![
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)),
+            PieceInfo::new(rng.gen(), UnpaddedBytesAmount(254)),
+        ];
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        let commitments = pieces.iter().map(|p| p.commitment).collect::<Vec<_>>();
+        let sizes = pieces.iter().map(|p| u64::from(p.size)).collect::<Vec<_>>();
+
+        assert!(verify_pieces_raw(&comm_d, &commitments, &sizes, sector_size).unwrap());
+        assert!(!verify_pieces_raw(&comm_d, &commitments, &[254, 127], sector_size).unwrap());
+
+        assert!(verify_pieces_raw(&comm_d, &commitments, &sizes[..1], sector_size).is_err());
+        assert!(verify_pieces_raw(&comm_d, &commitments[..1], &sizes, sector_size).is_err());
+    }
+
+    /// Sets the capacity of the verification cache, restoring the previous one when dropped, so
+    /// that the cache is not left enabled for other tests, even if the test using it fails.
+    struct VerifyCacheCapacity(usize);
+
+    impl VerifyCacheCapacity {
+        fn set(capacity: usize) -> Self {
+            let previous = VERIFY_CACHE.lock().unwrap().capacity;
+            set_verify_cache_capacity(capacity);
+            VerifyCacheCapacity(previous)
+        }
+    }
+
+    impl Drop for VerifyCacheCapacity {
+        fn drop(&mut self) {
+            set_verify_cache_capacity(self.0);
+        }
+    }
+
+    #[test]
+    fn test_verify_cache() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(4 * 128);
+
+        let pieces = (0..4)
+            .map(|_| PieceInfo::new(rng.gen(), UnpaddedBytesAmount(127)))
+            .collect::<Vec<_>>();
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        // Large enough that verifications in concurrently running tests do not evict entries.
+        let _capacity = VerifyCacheCapacity::set(4096);
+        let computed = || VERIFY_COMPUTE_COUNT.with(|count| count.get());
+
+        let before = computed();
+        assert!(verify_pieces(&comm_d, &pieces, sector_size).unwrap());
+        assert_eq!(computed(), before + 1);
+
+        // Served from the cache.
+        assert!(verify_pieces(&comm_d, &pieces, sector_size).unwrap());
+        assert_eq!(computed(), before + 1);
+
+        // Any change to the inputs misses.
+        let mut changed = pieces.clone();
+        changed[3].commitment[0] ^= 1;
+        assert!(!verify_pieces(&comm_d, &changed, sector_size).unwrap());
+        assert_eq!(computed(), before + 2);
+
+        let mut other_comm_d = comm_d;
+        other_comm_d[0] ^= 1;
+        assert!(!verify_pieces(&other_comm_d, &pieces, sector_size).unwrap());
+        assert_eq!(computed(), before + 3);
+
+        assert!(!verify_pieces(&comm_d, &pieces, SectorSize(8 * 128)).unwrap());
+        assert_eq!(computed(), before + 4);
+
+        // Cached negative results are served as well.
+        assert!(!verify_pieces(&comm_d, &changed, sector_size).unwrap());
+        assert_eq!(computed(), before + 4);
+
+        clear_verify_cache();
+        assert!(verify_pieces(&comm_d, &pieces, sector_size).unwrap());
+        assert_eq!(computed(), before + 5);
     }
 
     #[test]
-    fn test_get_piece_start_byte() {
-        let pieces = [
-            UnpaddedBytesAmount(31),
-            UnpaddedBytesAmount(32),
-            UnpaddedBytesAmount(33),
+    fn test_coalesce_padding() {
+        let sector_size = SectorSize(32 * 128);
+        let pad = zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(127));
+        let a = PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127));
+        let b = PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127));
+
+        // A(1) P(1) P(1) P(1) B(4) P(8) P(8) P(8)
+        let layout = vec![
+            a.clone(),
+            pad.clone(),
+            pad.clone(),
+            pad.clone(),
+            b.clone(),
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(8 * 127)),
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(8 * 127)),
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(8 * 127)),
         ];
 
+        let coalesced = coalesce_padding(&layout);
+
+        // The first padding piece is not aligned to a 2 node boundary, so it stays on its own.
+        let expected = vec![
+            a,
+            pad,
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(2 * 127)),
+            b,
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(8 * 127)),
+            zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(16 * 127)),
+        ];
+        assert_eq!(coalesced, expected);
+
         assert_eq!(
-            get_piece_start_byte(&pieces[..0], pieces[0]),
-            UnpaddedByteIndex(0)
-        );
-        assert_eq!(
-            get_piece_start_byte(&pieces[..1], pieces[1]),
-            UnpaddedByteIndex(127)
-        );
-        assert_eq!(
-            get_piece_start_byte(&pieces[..2], pieces[2]),
-            UnpaddedByteIndex(254)
+            compute_comm_d(sector_size, &coalesced).unwrap(),
+            compute_comm_d(sector_size, &layout).unwrap()
         );
     }
 
     #[test]
-    fn test_verify_simple_pieces() {
-        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+    fn test_precompute_zero_padding() {
+        let sector_size = SectorSize(1 << 20);
+        precompute_zero_padding(sector_size);
+
+        let sizes = (7..=20)
+            .map(|i| UnpaddedBytesAmount::from(PaddedBytesAmount(1 << i)))
+            .collect::<Vec<_>>();
+
+        let hits_before = ZERO_PADDING_CACHE_HITS.load(Ordering::SeqCst);
+        for size in &sizes {
+            let padding = zero_padding::<DefaultPieceHasher>(*size);
+            assert_eq!(padding.size, *size);
+        }
+        let hits_after = ZERO_PADDING_CACHE_HITS.load(Ordering::SeqCst);
 
-        //     g
-        //   /  \
-        //  e    f
-        // / \  / \
-        // a  b c  d
+        assert!(hits_after - hits_before >= sizes.len());
+    }
 
-        let (a, b, c, d): ([u8; 32], [u8; 32], [u8; 32], [u8; 32]) = rng.gen();
+    /// Root of a subtree of the given height with only zero leaves, hashed from scratch.
+    fn uncached_zero_root<H: Hasher>(height: usize) -> Commitment {
+        let mut root = [0u8; 32];
+        for _ in 0..height {
+            let h = piece_hash::<H>(&root, &root);
+            root.copy_from_slice(h.as_ref());
+        }
+        root
+    }
 
-        let mut e = [0u8; 32];
-        let h = piece_hash(&a, &b);
-        e.copy_from_slice(h.as_ref());
+    #[test]
+    fn test_zero_padding_cache_matches_uncached() {
+        let sizes = (7..=30).collect::<Vec<usize>>();
+
+        let check = |sizes: &[usize]| {
+            for &log2_size in sizes {
+                let size = UnpaddedBytesAmount::from(PaddedBytesAmount(1 << log2_size));
+                // A piece of 2^n bytes is the root of a tree with 2^n / NODE_SIZE leaves.
+                let expected = uncached_zero_root::<DefaultPieceHasher>(log2_size - 5);
+
+                // Filling the cache, and being served from it.
+                for _ in 0..2 {
+                    let padding = zero_padding::<DefaultPieceHasher>(size);
+                    assert_eq!(padding.size, size);
+                    assert_eq!(padding.commitment, expected, "size 2^{}", log2_size);
+                }
+            }
+        };
 
-        let mut f = [0u8; 32];
-        let h = piece_hash(&c, &d);
-        f.copy_from_slice(h.as_ref());
+        // Several sectors may fill the cache concurrently.
+        let threads = (0..4)
+            .map(|i| {
+                let mut sizes = sizes.clone();
+                sizes.rotate_left(i * 6);
+                std::thread::spawn(move || check(&sizes))
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().expect("thread panicked");
+        }
 
-        let mut g = [0u8; 32];
-        let h = piece_hash(&e, &f);
-        g.copy_from_slice(h.as_ref());
+        check(&sizes);
+    }
 
-        let a = PieceInfo::new(a, UnpaddedBytesAmount(127));
-        let b = PieceInfo::new(b, UnpaddedBytesAmount(127));
-        let c = PieceInfo::new(c, UnpaddedBytesAmount(127));
-        let d = PieceInfo::new(d, UnpaddedBytesAmount(127));
+    #[test]
+    fn test_stack_peek() {
+        let a = PieceInfo::new([1; 32], UnpaddedBytesAmount(127));
+        let b = PieceInfo::new([2; 32], UnpaddedBytesAmount(254));
 
-        let e = PieceInfo::new(e, UnpaddedBytesAmount(254));
-        let f = PieceInfo::new(f, UnpaddedBytesAmount(254));
-        let g = PieceInfo::new(g, UnpaddedBytesAmount(508));
+        let mut stack = Stack::new();
+        assert!(stack.peek().is_none());
+        assert!(stack.peek2().is_none());
 
-        let sector_size = SectorSize(4 * 128);
-        let comm_d = g.commitment;
+        stack.shift(a.clone());
+        assert_eq!(stack.peek(), Some(&a));
+        assert!(stack.peek2().is_none());
 
-        // println!("e: {:?}", e);
-        // println!("f: {:?}", f);
-        // println!("g: {:?}", g);
+        stack.shift(b.clone());
+        assert_eq!(stack.peek(), Some(&b));
+        assert_eq!(stack.peek2(), Some(&a));
 
-        assert!(
-            verify_pieces(
-                &comm_d,
-                &vec![a.clone(), b.clone(), c.clone(), d.clone()],
-                sector_size
-            )
-            .expect("failed to verify"),
-            "[a, b, c, d]"
-        );
+        // Differently sized pieces are not joined.
+        assert!(!stack.reduce1::<DefaultPieceHasher>());
+        assert_eq!(stack.len(), 2);
+    }
 
-        assert!(
-            verify_pieces(&comm_d, &vec![e.clone(), c.clone(), d.clone()], sector_size)
-                .expect("failed to verify"),
-            "[e, c, d]"
-        );
+    #[test]
+    fn test_piece_inclusion_proof() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let sector_size = SectorSize(32 * 128);
 
-        assert!(
-            verify_pieces(&comm_d, &vec![e.clone(), f.clone()], sector_size)
-                .expect("failed to verify"),
-            "[e, f]"
-        );
+        let sizes = [1, 4, 2, 8];
+        let pieces = sizes
+            .iter()
+            .map(|size| PieceInfo::new(rng.gen(), UnpaddedBytesAmount(size * 127)))
+            .collect::<Vec<_>>();
 
-        assert!(
-            verify_pieces(&comm_d, &vec![a.clone(), b.clone(), f.clone()], sector_size)
-                .expect("failed to verify"),
-            "[a, b, f]"
+        // Offsets of the pieces after alignment padding, in multiples of 128 bytes.
+        let offsets = [0, 4, 8, 16];
+
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+
+        for (i, piece) in pieces.iter().enumerate() {
+            let proof = piece_inclusion_proof(sector_size, &pieces, i).unwrap();
+
+            assert_eq!(AsRef::<[u8]>::as_ref(proof.root()), &comm_d[..]);
+            assert_eq!(AsRef::<[u8]>::as_ref(proof.leaf()), &piece.commitment[..]);
+            let index = (offsets[i] / sizes[i]) as usize;
+            assert!(proof.validate(index));
+            assert!(!proof.validate(index + 1));
+        }
+
+        assert!(piece_inclusion_proof(sector_size, &pieces, pieces.len()).is_err());
+    }
+
+    #[test]
+    fn test_compute_comm_d_unaligned() {
+        // The layout of `test_verify_padded_pieces`, from pieces which still need to be rounded:
+        // [
+        //   {(A0 00) (BB BB)} -> A(1) P(1) P(1) P(1) B(4)
+        //   {(CC 00) (00 00)} -> C(2)      P(1) P(1) P(1) P(1) P(1) P(1)
+        // ]
+        // [
+        //   {(DD DD) (DD DD)} -> D(8)
+        //   {(00 00) (00 00)} -> P(1) P(1) P(1) P(1) P(1) P(1) P(1) P(1)
+        // ]
+
+        let sector_size = SectorSize(32 * 128);
+        let pad = zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(127));
+
+        let raw_pieces = vec![
+            ([1u8; 32], UnpaddedBytesAmount(100)),
+            ([2u8; 32], UnpaddedBytesAmount(300)),
+            ([3u8; 32], UnpaddedBytesAmount(128)),
+            ([4u8; 32], UnpaddedBytesAmount(8 * 127)),
+        ];
+
+        let mut padded_pieces = vec![PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127))];
+        padded_pieces.extend(vec![pad.clone(); 3]);
+        padded_pieces.push(PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)));
+        padded_pieces.push(PieceInfo::new([3u8; 32], UnpaddedBytesAmount(2 * 127)));
+        padded_pieces.extend(vec![pad.clone(); 6]);
+        padded_pieces.push(PieceInfo::new([4u8; 32], UnpaddedBytesAmount(8 * 127)));
+        padded_pieces.extend(vec![pad; 8]);
+
+        assert_eq!(
+            compute_comm_d_unaligned(sector_size, &raw_pieces).unwrap(),
+            compute_comm_d(sector_size, &padded_pieces).unwrap()
         );
 
-        assert!(
-            verify_pieces(&comm_d, &vec![g], sector_size).expect("failed to verify"),
-            "[g]"
+        // Aligned sizes are taken as they are.
+        let rounded = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)),
+            PieceInfo::new([3u8; 32], UnpaddedBytesAmount(2 * 127)),
+            PieceInfo::new([4u8; 32], UnpaddedBytesAmount(8 * 127)),
+        ];
+        let rounded_raw = rounded
+            .iter()
+            .map(|piece| (piece.commitment, piece.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            compute_comm_d_unaligned(sector_size, &rounded_raw).unwrap(),
+            compute_comm_d(sector_size, &rounded).unwrap()
         );
+
+        let too_large = vec![([1u8; 32], UnpaddedBytesAmount(32 * 127 + 1))];
+        assert!(compute_comm_d_unaligned(sector_size, &too_large).is_err());
+        let empty = vec![([1u8; 32], UnpaddedBytesAmount(0))];
+        assert!(compute_comm_d_unaligned(sector_size, &empty).is_err());
     }
 
     #[test]
@@ -436,7 +2755,7 @@ mod tests {
         // ]
 
         let sector_size = SectorSize(32 * 128);
-        let pad = zero_padding(UnpaddedBytesAmount(127));
+        let pad = zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(127));
 
         let pieces = vec![
             PieceInfo {
@@ -494,7 +2813,7 @@ mod tests {
         ];
 
         let hash = |a, b| {
-            let hash = piece_hash(a, b);
+            let hash = piece_hash::<DefaultPieceHasher>(a, b);
             let mut res = [0u8; 32];
             res.copy_from_slice(hash.as_ref());
             res
@@ -542,6 +2861,196 @@ mod tests {
         assert!(verify_pieces(&comm_d, &pieces, sector_size).unwrap());
     }
 
+    #[test]
+    fn test_compute_comm_d_with_default_hasher() {
+        let sector_size = SectorSize(32 * 128);
+        let pieces = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)),
+            PieceInfo::new([3u8; 32], UnpaddedBytesAmount(2 * 127)),
+        ];
+
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+        let comm_d_with = compute_comm_d_with::<DefaultPieceHasher>(sector_size, &pieces).unwrap();
+        assert_eq!(comm_d, comm_d_with);
+
+        let comm_d_blake2s = compute_comm_d_with::<Blake2sHasher>(sector_size, &pieces).unwrap();
+        assert_ne!(comm_d, comm_d_blake2s);
+    }
+
+    #[test]
+    fn test_assert_sector_full() {
+        let sector_size = SectorSize(1024);
+        let piece = |size| PieceInfo::new([1u8; 32], UnpaddedBytesAmount(size));
+
+        assert!(assert_sector_full(sector_size, &[piece(508), piece(508)]).is_ok());
+        assert!(assert_sector_full(sector_size, &[piece(1016)]).is_ok());
+
+        // The second piece is aligned to 254 bytes, leaving 508 bytes of the sector unused.
+        let err = assert_sector_full(sector_size, &[piece(127), piece(254)]).unwrap_err();
+        assert_eq!(err.to_string(), "sector is not full, 508 bytes missing");
+
+        assert!(assert_sector_full(sector_size, &[piece(1016), piece(127)]).is_err());
+    }
+
+    #[test]
+    fn test_compute_comm_d_iter() {
+        let sector_size = SectorSize(32 * 128);
+        let pieces = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)),
+            PieceInfo::new([3u8; 32], UnpaddedBytesAmount(2 * 127)),
+        ];
+
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+        let comm_d_iter = compute_comm_d_iter(sector_size, pieces.iter().cloned().map(Ok)).unwrap();
+        assert_eq!(comm_d, comm_d_iter);
+
+        // The iterator is not consumed beyond the first error.
+        let mut consumed = 0;
+        let failing = pieces.iter().enumerate().map(|(i, piece)| {
+            consumed += 1;
+            if i == 1 {
+                Err(format_err!("failed to read piece {}", i))
+            } else {
+                Ok(piece.clone())
+            }
+        });
+        let err = compute_comm_d_iter(sector_size, failing).unwrap_err();
+        assert_eq!(err.to_string(), "failed to read piece 1");
+        assert_eq!(consumed, 2);
+
+        assert!(compute_comm_d_iter(sector_size, std::iter::empty()).is_err());
+        assert!(compute_comm_d_iter(
+            sector_size,
+            std::iter::repeat(PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127 * 16))).map(Ok)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_compute_comm_d_streaming() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        // Large enough for the source to be read in several chunks.
+        let sector_size = SectorSize(1 << 18);
+        let unpadded_sector: UnpaddedBytesAmount = sector_size.into();
+
+        for len in &[
+            0,
+            1,
+            127 * 4 + 5,
+            127 * 1024,
+            127 * 1500 + 3,
+            2 * 127 * 1024,
+        ] {
+            let data: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+
+            // The whole sector as a single, zero extended piece.
+            let source = Cursor::new(&data)
+                .chain(io::repeat(0))
+                .take(u64::from(unpadded_sector));
+            let piece = crate::api::generate_piece_commitment(source, unpadded_sector).unwrap();
+            let expected = compute_comm_d(sector_size, &[piece]).unwrap();
+
+            assert_eq!(
+                compute_comm_d_streaming(Cursor::new(&data), sector_size).unwrap(),
+                expected,
+                "length {}",
+                len
+            );
+        }
+
+        let overfull = vec![1u8; u64::from(unpadded_sector) as usize + 1];
+        assert!(compute_comm_d_streaming(Cursor::new(&overfull), sector_size).is_err());
+    }
+
+    #[test]
+    fn test_comm_d_with_leading_zeros() {
+        let sector_size = SectorSize(32 * 128);
+        let pieces = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)),
+        ];
+
+        let zeros = |size| zero_padding::<DefaultPieceHasher>(UnpaddedBytesAmount(size));
+
+        // 7 * 127 bytes of zeros are covered by padding pieces of 4, 2 and 1 times 127 bytes.
+        let mut explicit = vec![zeros(4 * 127), zeros(2 * 127), zeros(127)];
+        explicit.extend_from_slice(&pieces);
+        assert_eq!(
+            comm_d_with_leading_zeros(sector_size, UnpaddedBytesAmount(7 * 127), &pieces).unwrap(),
+            compute_comm_d(sector_size, &explicit).unwrap()
+        );
+
+        assert_eq!(
+            comm_d_with_leading_zeros(sector_size, UnpaddedBytesAmount(0), &pieces).unwrap(),
+            compute_comm_d(sector_size, &pieces).unwrap()
+        );
+
+        // A sector of only zeros.
+        assert_eq!(
+            comm_d_with_leading_zeros(sector_size, sector_size.into(), &[]).unwrap(),
+            zeros(u64::from(UnpaddedBytesAmount::from(sector_size))).commitment
+        );
+
+        assert!(comm_d_with_leading_zeros(sector_size, UnpaddedBytesAmount(100), &pieces).is_err());
+        assert!(
+            comm_d_with_leading_zeros(sector_size, UnpaddedBytesAmount(31 * 127), &pieces).is_err()
+        );
+    }
+
+    #[test]
+    fn test_compute_comm_d_traced() {
+        let sector_size = SectorSize(32 * 128);
+        let pieces = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([3u8; 32], UnpaddedBytesAmount(127)),
+        ];
+
+        let mut traced = Vec::new();
+        let comm_d = compute_comm_d_traced::<DefaultPieceHasher, _>(
+            sector_size,
+            &pieces,
+            |i, commitment, top| traced.push((i, *commitment, top.clone())),
+        )
+        .unwrap();
+
+        assert_eq!(comm_d, compute_comm_d(sector_size, &pieces).unwrap());
+        assert_eq!(traced.len(), pieces.len());
+        for (i, (index, commitment, _)) in traced.iter().enumerate() {
+            assert_eq!(*index, i);
+            assert_eq!(*commitment, pieces[i].commitment);
+        }
+
+        // The first two pieces are siblings, so the second one is merged with the first.
+        assert_eq!(
+            traced[1].2,
+            join_piece_infos::<DefaultPieceHasher>(pieces[0].clone(), pieces[1].clone())
+        );
+        // The third piece has no sibling yet, so it is on top of the stack as is.
+        assert_eq!(traced[2].2, pieces[2]);
+    }
+
+    #[test]
+    fn test_compute_comm_d_with_pool() {
+        let sector_size = SectorSize(32 * 128);
+        let pieces = vec![
+            PieceInfo::new([1u8; 32], UnpaddedBytesAmount(127)),
+            PieceInfo::new([2u8; 32], UnpaddedBytesAmount(4 * 127)),
+            PieceInfo::new([3u8; 32], UnpaddedBytesAmount(2 * 127)),
+        ];
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+
+        let comm_d = compute_comm_d(sector_size, &pieces).unwrap();
+        let comm_d_pool = compute_comm_d_with_pool(sector_size, &pieces, &pool).unwrap();
+        assert_eq!(comm_d, comm_d_pool);
+    }
+
     #[ignore] // slow test
     #[test]
     fn test_verify_random_pieces() -> Result<()> {
@@ -560,50 +3069,8 @@ mod tests {
                 println!(" - {} -", i);
                 let unpadded_sector_size: UnpaddedBytesAmount = sector_size.clone().into();
                 let sector_size = *sector_size;
-                let padded_sector_size: PaddedBytesAmount = sector_size.into();
-
-                let mut piece_sizes = Vec::new();
-                loop {
-                    let sum_piece_sizes: PaddedBytesAmount =
-                        sum_piece_bytes_with_alignment(&piece_sizes).into();
 
-                    if sum_piece_sizes > padded_sector_size {
-                        piece_sizes.pop();
-                        break;
-                    }
-                    if sum_piece_sizes == padded_sector_size {
-                        break;
-                    }
-
-                    'inner: loop {
-                        // pieces must be power of two
-                        let left = u64::from(padded_sector_size) - u64::from(sum_piece_sizes);
-                        let left_power_of_two = prev_power_of_two(left as u32);
-                        let max_exp = (left_power_of_two as f64).log2() as u32;
-
-                        let padded_exp = if max_exp > 7 {
-                            rng.gen_range(
-                                7, // 2**7 == 128,
-                                max_exp,
-                            )
-                        } else {
-                            7
-                        };
-                        let padded_piece_size = 2u64.pow(padded_exp);
-                        let piece_size: UnpaddedBytesAmount =
-                            PaddedBytesAmount(padded_piece_size).into();
-                        piece_sizes.push(piece_size);
-                        let sum: PaddedBytesAmount =
-                            sum_piece_bytes_with_alignment(&piece_sizes).into();
-
-                        if sum > padded_sector_size {
-                            // pieces might be too large after padding, so remove them and try again.
-                            piece_sizes.pop();
-                        } else {
-                            break 'inner;
-                        }
-                    }
-                }
+                let piece_sizes = random_piece_sizes(rng, sector_size);
 
                 // println!(
                 //     "  {:?}",
@@ -627,13 +3094,194 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_zero_padding_of_other_hashers() {
+        // The zero roots of each hasher are kept apart.
+        for log2_size in 7..=30 {
+            let padded_size = PaddedBytesAmount(1 << log2_size);
+            assert_eq!(
+                zero_padding::<Blake2sHasher>(padded_size.into()).commitment,
+                uncached_zero_root::<Blake2sHasher>(log2_size - 5)
+            );
+            assert_eq!(
+                zero_padding::<DefaultPieceHasher>(padded_size.into()).commitment,
+                uncached_zero_root::<DefaultPieceHasher>(log2_size - 5)
+            );
+        }
+    }
+
+    /// Random power of two piece sizes which fit into `sector_size` with their alignment.
+    fn random_piece_sizes(
+        rng: &mut XorShiftRng,
+        sector_size: SectorSize,
+    ) -> Vec<UnpaddedBytesAmount> {
+        let padded_sector_size: PaddedBytesAmount = sector_size.into();
+
+        let mut piece_sizes = Vec::new();
+        loop {
+            let sum_piece_sizes: PaddedBytesAmount =
+                sum_piece_bytes_with_alignment(&piece_sizes).into();
+
+            if sum_piece_sizes > padded_sector_size {
+                piece_sizes.pop();
+                break;
+            }
+            if sum_piece_sizes == padded_sector_size {
+                break;
+            }
+
+            'inner: loop {
+                // pieces must be power of two
+                let left = u64::from(padded_sector_size) - u64::from(sum_piece_sizes);
+                let left_power_of_two = prev_power_of_two(left as u32);
+                let max_exp = (left_power_of_two as f64).log2() as u32;
+
+                let padded_exp = if max_exp > 7 {
+                    rng.gen_range(
+                        7, // 2**7 == 128,
+                        max_exp,
+                    )
+                } else {
+                    7
+                };
+                let padded_piece_size = 2u64.pow(padded_exp);
+                let piece_size: UnpaddedBytesAmount = PaddedBytesAmount(padded_piece_size).into();
+                piece_sizes.push(piece_size);
+                let sum: PaddedBytesAmount = sum_piece_bytes_with_alignment(&piece_sizes).into();
+
+                if sum > padded_sector_size {
+                    // pieces might be too large after padding, so remove them and try again.
+                    piece_sizes.pop();
+                } else {
+                    break 'inner;
+                }
+            }
+        }
+
+        piece_sizes
+    }
+
+    #[test]
+    fn test_staged_sector() -> Result<()> {
+        let sector_size = SectorSize(32 * 128);
+        let piece_sizes = [
+            UnpaddedBytesAmount(127),
+            UnpaddedBytesAmount(4 * 127),
+            UnpaddedBytesAmount(2 * 127),
+            UnpaddedBytesAmount(8 * 127),
+            UnpaddedBytesAmount(8 * 127),
+        ];
+        let (comm_d, piece_infos) = build_sector(&piece_sizes, sector_size)?;
+
+        // Stage the same pieces as `build_sector` does.
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut staged = StagedSector::new(sector_size);
+        let mut staged_sector = Vec::with_capacity(u64::from(sector_size) as usize);
+        let mut staged_sector_io = Cursor::new(&mut staged_sector);
+
+        assert_eq!(
+            staged.comm_d()?,
+            compute_comm_d(
+                sector_size,
+                &[zero_padding::<DefaultPieceHasher>(sector_size.into())]
+            )?
+        );
+
+        for piece_size in &piece_sizes {
+            let mut piece_bytes = vec![1u8; u64::from(*piece_size) as usize];
+            rng.fill_bytes(&mut piece_bytes);
+
+            staged.add_piece(
+                Cursor::new(&piece_bytes),
+                *piece_size,
+                &mut staged_sector_io,
+            )?;
+        }
+
+        assert_eq!(staged.pieces(), &piece_infos[..]);
+        assert_eq!(staged.comm_d()?, comm_d);
+        assert_eq!(staged.used(), UnpaddedBytesAmount(32 * 127));
+        assert_eq!(staged.remaining(), UnpaddedBytesAmount(0));
+
+        // Pieces which don't fit are rejected, without writing anything.
+        let piece_bytes = vec![0u8; 127];
+        assert!(staged
+            .add_piece(
+                Cursor::new(&piece_bytes),
+                UnpaddedBytesAmount(127),
+                &mut staged_sector_io
+            )
+            .is_err());
+        assert_eq!(staged.pieces().len(), piece_sizes.len());
+        assert_eq!(staged_sector.len(), 32 * 128);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_staged_sector_trace() -> Result<()> {
+        let sector_size = SectorSize(32 * 128);
+        let piece_sizes = [
+            UnpaddedBytesAmount(127),
+            UnpaddedBytesAmount(127),
+            UnpaddedBytesAmount(2 * 127),
+        ];
+
+        let traced = Arc::new(Mutex::new(Vec::new()));
+        let trace_sink = traced.clone();
+        let mut staged = StagedSector::new(sector_size).with_trace(move |i, commitment, top| {
+            trace_sink
+                .lock()
+                .unwrap()
+                .push((i, *commitment, top.clone()))
+        });
+
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let mut staged_sector = Vec::new();
+        let mut staged_sector_io = Cursor::new(&mut staged_sector);
+        for (i, piece_size) in piece_sizes.iter().enumerate() {
+            let mut piece_bytes = vec![0u8; u64::from(*piece_size) as usize];
+            rng.fill_bytes(&mut piece_bytes);
+
+            staged.add_piece(
+                Cursor::new(&piece_bytes),
+                *piece_size,
+                &mut staged_sector_io,
+            )?;
+
+            // The callback fires from within `add_piece`.
+            assert_eq!(traced.lock().unwrap().len(), i + 1);
+        }
+
+        // The staged sector traces the same as computing `comm_d` over its pieces.
+        let mut expected = Vec::new();
+        compute_comm_d_traced::<DefaultPieceHasher, _>(
+            sector_size,
+            staged.pieces(),
+            |i, commitment, top| expected.push((i, *commitment, top.clone())),
+        )?;
+        assert_eq!(*traced.lock().unwrap(), expected);
+
+        // The first two pieces are siblings and merged, the third one completes their parent.
+        let pieces = staged.pieces();
+        let first_two =
+            join_piece_infos::<DefaultPieceHasher>(pieces[0].clone(), pieces[1].clone());
+        assert_eq!(traced.lock().unwrap()[1].2, first_two);
+        assert_eq!(
+            traced.lock().unwrap()[2].2,
+            join_piece_infos::<DefaultPieceHasher>(first_two, pieces[2].clone())
+        );
+
+        Ok(())
+    }
+
     fn build_sector(
         piece_sizes: &[UnpaddedBytesAmount],
         sector_size: SectorSize,
     ) -> Result<([u8; 32], Vec<PieceInfo>)> {
         let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
         let graph = StackedBucketGraph::<DefaultPieceHasher>::new_stacked(
-            u64::from(sector_size) as usize / NODE_SIZE,
+            sector_nodes(sector_size),
             BASE_DEGREE,
             EXP_DEGREE,
             new_seed(),
@@ -666,7 +3314,7 @@ mod tests {
 
         let data_tree = graph.merkle_tree(&staged_sector)?;
         let comm_d_root: Fr = data_tree.root().into();
-        let comm_d = commitment_from_fr::<Bls12>(comm_d_root);
+        let comm_d = commitment_from_bls_fr(comm_d_root);
 
         Ok((comm_d, piece_infos))
     }
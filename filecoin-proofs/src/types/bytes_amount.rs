@@ -1,6 +1,8 @@
+use std::convert::TryFrom;
 use std::ops::{Add, Sub};
 
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::fr32::padded_bytes;
 use crate::fr32::unpadded_bytes;
@@ -18,6 +20,80 @@ pub struct UnpaddedBytesAmount(pub u64);
 #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize, Eq, Ord)]
 pub struct PaddedBytesAmount(pub u64);
 
+/// Number of unpadded bytes which pad to a whole number of fr32 elements.
+const FR32_UNPADDED_UNIT: u64 = 127;
+
+/// Number of padded bytes in `FR32_UNPADDED_UNIT`.
+const FR32_PADDED_UNIT: u64 = 128;
+
+/// Byte amounts which can not be represented exactly in the requested form.
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ByteAmountError {
+    #[error("{:?} is not a multiple of {} padded bytes", _0, FR32_PADDED_UNIT)]
+    PartialPaddedUnit(PaddedBytesAmount),
+    #[error("{:?} is not a multiple of {} unpadded bytes", _0, FR32_UNPADDED_UNIT)]
+    PartialUnpaddedUnit(UnpaddedBytesAmount),
+    #[error("subtracting {} from {} bytes underflows", _1, _0)]
+    Underflow(u64, u64),
+}
+
+impl PaddedBytesAmount {
+    /// Converts to the unpadded amount, failing instead of truncating if the amount does not hold
+    /// a whole number of fr32 padded units.
+    pub fn checked_unpadded(self) -> Result<UnpaddedBytesAmount, ByteAmountError> {
+        if self.0 % FR32_PADDED_UNIT != 0 {
+            return Err(ByteAmountError::PartialPaddedUnit(self));
+        }
+        Ok(self.into())
+    }
+
+    /// Subtracts `other`, failing instead of underflowing.
+    pub fn checked_sub(self, other: PaddedBytesAmount) -> Result<Self, ByteAmountError> {
+        self.0
+            .checked_sub(other.0)
+            .map(PaddedBytesAmount)
+            .ok_or(ByteAmountError::Underflow(self.0, other.0))
+    }
+}
+
+impl UnpaddedBytesAmount {
+    /// Converts to the padded amount, failing instead of ending on a partially used byte if the
+    /// amount does not hold a whole number of fr32 unpadded units.
+    pub fn checked_padded(self) -> Result<PaddedBytesAmount, ByteAmountError> {
+        if self.0 % FR32_UNPADDED_UNIT != 0 {
+            return Err(ByteAmountError::PartialUnpaddedUnit(self));
+        }
+        Ok(self.into())
+    }
+
+    /// Subtracts `other`, failing instead of underflowing.
+    pub fn checked_sub(self, other: UnpaddedBytesAmount) -> Result<Self, ByteAmountError> {
+        self.0
+            .checked_sub(other.0)
+            .map(UnpaddedBytesAmount)
+            .ok_or(ByteAmountError::Underflow(self.0, other.0))
+    }
+}
+
+// The conversions by value are taken by the infallible `From` impls below, through the blanket
+// `TryFrom` impl, so the checked conversions are implemented for references.
+
+impl TryFrom<&PaddedBytesAmount> for UnpaddedBytesAmount {
+    type Error = ByteAmountError;
+
+    fn try_from(n: &PaddedBytesAmount) -> Result<Self, Self::Error> {
+        n.checked_unpadded()
+    }
+}
+
+impl TryFrom<&UnpaddedBytesAmount> for PaddedBytesAmount {
+    type Error = ByteAmountError;
+
+    fn try_from(n: &UnpaddedBytesAmount) -> Result<Self, Self::Error> {
+        n.checked_padded()
+    }
+}
+
 impl From<UnpaddedBytesAmount> for u64 {
     fn from(n: UnpaddedBytesAmount) -> Self {
         n.0
@@ -126,6 +202,8 @@ impl From<PoRepProofBytesAmount> for usize {
 mod tests {
     use super::*;
 
+    use std::convert::TryInto;
+
     #[test]
     fn allowed_operations() {
         let a = UnpaddedBytesAmount(1);
@@ -163,4 +241,36 @@ mod tests {
         // assert_eq!(1u64 + u64::from(e), 3u64);
         // assert_eq!(1usize + usize::from(e), 3usize);
     }
+
+    #[test]
+    fn checked_conversions() {
+        assert_eq!(
+            UnpaddedBytesAmount::try_from(&PaddedBytesAmount(1024)),
+            Ok(UnpaddedBytesAmount(1016))
+        );
+        let padded: Result<PaddedBytesAmount, _> = (&UnpaddedBytesAmount(1016)).try_into();
+        assert_eq!(padded, Ok(PaddedBytesAmount(1024)));
+
+        // 32 padded bytes only hold 31.75 unpadded bytes.
+        assert_eq!(
+            UnpaddedBytesAmount::try_from(&PaddedBytesAmount(32)),
+            Err(ByteAmountError::PartialPaddedUnit(PaddedBytesAmount(32)))
+        );
+        let padded: Result<PaddedBytesAmount, _> = (&UnpaddedBytesAmount(100)).try_into();
+        assert_eq!(
+            padded,
+            Err(ByteAmountError::PartialUnpaddedUnit(UnpaddedBytesAmount(
+                100
+            )))
+        );
+
+        assert_eq!(
+            UnpaddedBytesAmount(3).checked_sub(UnpaddedBytesAmount(2)),
+            Ok(UnpaddedBytesAmount(1))
+        );
+        assert_eq!(
+            PaddedBytesAmount(2).checked_sub(PaddedBytesAmount(3)),
+            Err(ByteAmountError::Underflow(2, 3))
+        );
+    }
 }
@@ -1,9 +1,12 @@
 use std::fmt;
 
+use serde::{Deserialize, Serialize};
+
 use crate::types::{Commitment, UnpaddedBytesAmount};
 
-#[derive(Clone, Default, PartialEq, Eq)]
+#[derive(Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PieceInfo {
+    #[serde(with = "commitment_hex")]
     pub commitment: Commitment,
     pub size: UnpaddedBytesAmount,
 }
@@ -22,3 +25,61 @@ impl PieceInfo {
         PieceInfo { commitment, size }
     }
 }
+
+/// (De)serializes a commitment as a hex string, the way it is shown by `Debug`.
+mod commitment_hex {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use crate::types::Commitment;
+
+    pub fn serialize<S: Serializer>(
+        commitment: &Commitment,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(commitment))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Commitment, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        let bytes = hex::decode(&encoded).map_err(D::Error::custom)?;
+
+        let mut commitment = Commitment::default();
+        if bytes.len() != commitment.len() {
+            return Err(D::Error::invalid_length(bytes.len(), &"32 bytes"));
+        }
+        commitment.copy_from_slice(&bytes);
+
+        Ok(commitment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_piece_info_serde_roundtrip() {
+        let mut commitment = [0u8; 32];
+        commitment[0] = 0xab;
+        commitment[31] = 0x01;
+        let piece_info = PieceInfo::new(commitment, UnpaddedBytesAmount(508));
+
+        let json = serde_json::to_string(&piece_info).unwrap();
+        assert_eq!(
+            json,
+            format!(
+                r#"{{"commitment":"{}","size":508}}"#,
+                hex::encode(&commitment)
+            )
+        );
+
+        let decoded: PieceInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, piece_info);
+
+        let too_short = r#"{"commitment":"abcd","size":508}"#;
+        assert!(serde_json::from_str::<PieceInfo>(too_short).is_err());
+        let not_hex = format!(r#"{{"commitment":"{}","size":508}}"#, "zz".repeat(32));
+        assert!(serde_json::from_str::<PieceInfo>(&not_hex).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use std::path::PathBuf;
 
+use anyhow::Result;
 use paired::bls12_381::Bls12;
 use storage_proofs::circuit::stacked::{StackedCircuit, StackedCompound};
 use storage_proofs::drgraph::DefaultTreeHasher;
 use storage_proofs::parameter_cache::{self, CacheableParameters};
 
-use crate::constants::DefaultPieceHasher;
+use crate::constants::{DefaultPieceHasher, PUBLISHED_SECTOR_SIZES};
 use crate::types::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -46,6 +47,17 @@ impl From<PoRepConfig> for SectorSize {
 }
 
 impl PoRepConfig {
+    /// Creates a config for a published sector size, with at least one partition.
+    pub fn new(sector_size: SectorSize, partitions: PoRepProofPartitions) -> Result<Self> {
+        ensure_published_sector_size(sector_size)?;
+        ensure!(partitions.0 > 0, "at least one partition is required");
+
+        Ok(PoRepConfig {
+            sector_size,
+            partitions,
+        })
+    }
+
     /// Returns the cache identifier as used by `storage-proofs::paramater_cache`.
     pub fn get_cache_identifier(&self) -> String {
         let params =
@@ -73,3 +85,37 @@ impl PoRepConfig {
         parameter_cache::parameter_cache_params_path(&id)
     }
 }
+
+/// Fails unless parameters are published for `sector_size`.
+pub(crate) fn ensure_published_sector_size(sector_size: SectorSize) -> Result<()> {
+    ensure!(
+        PUBLISHED_SECTOR_SIZES.contains(&u64::from(sector_size)),
+        "unsupported sector size {}, supported are {:?}",
+        u64::from(sector_size),
+        PUBLISHED_SECTOR_SIZES
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::SECTOR_SIZE_ONE_KIB;
+
+    #[test]
+    fn test_new_validates() {
+        let config =
+            PoRepConfig::new(SectorSize(SECTOR_SIZE_ONE_KIB), PoRepProofPartitions(2)).unwrap();
+        assert_eq!(u64::from(config.sector_size), SECTOR_SIZE_ONE_KIB);
+        assert_eq!(usize::from(config.partitions), 2);
+
+        assert!(
+            PoRepConfig::new(SectorSize(SECTOR_SIZE_ONE_KIB + 1), PoRepProofPartitions(2)).is_err()
+        );
+        assert!(
+            PoRepConfig::new(SectorSize(SECTOR_SIZE_ONE_KIB), PoRepProofPartitions(0)).is_err()
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use std::path::PathBuf;
 
+use anyhow::Result;
 use paired::bls12_381::Bls12;
 use storage_proofs::circuit::election_post::{ElectionPoStCircuit, ElectionPoStCompound};
 use storage_proofs::drgraph::DefaultTreeHasher;
 use storage_proofs::parameter_cache::{self, CacheableParameters};
 
+use crate::types::porep_config::ensure_published_sector_size;
 use crate::types::*;
 
 #[derive(Clone, Copy, Debug)]
@@ -29,6 +31,13 @@ impl From<PoStConfig> for UnpaddedBytesAmount {
 }
 
 impl PoStConfig {
+    /// Creates a config for a published sector size.
+    pub fn new(sector_size: SectorSize) -> Result<Self> {
+        ensure_published_sector_size(sector_size)?;
+
+        Ok(PoStConfig { sector_size })
+    }
+
     /// Returns the cache identifier as used by `storage-proofs::paramater_cache`.
     pub fn get_cache_identifier(self) -> String {
         let params = crate::parameters::post_public_params(self);
@@ -55,3 +64,21 @@ impl PoStConfig {
         parameter_cache::parameter_cache_params_path(&id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::{PUBLISHED_SECTOR_SIZES, SECTOR_SIZE_16_MIB};
+
+    #[test]
+    fn test_new_validates() {
+        for sector_size in PUBLISHED_SECTOR_SIZES.iter() {
+            let config = PoStConfig::new(SectorSize(*sector_size)).unwrap();
+            assert_eq!(u64::from(config.sector_size), *sector_size);
+        }
+
+        assert!(PoStConfig::new(SectorSize(SECTOR_SIZE_16_MIB * 3)).is_err());
+        assert!(PoStConfig::new(SectorSize(0)).is_err());
+    }
+}
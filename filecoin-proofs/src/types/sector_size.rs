@@ -1,7 +1,12 @@
+use std::ops::Range;
+
+use anyhow::{ensure, Result};
+use storage_proofs::util::NODE_SIZE;
+
 use crate::fr32::unpadded_bytes;
 use crate::types::*;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct SectorSize(pub u64);
 
 impl From<SectorSize> for UnpaddedBytesAmount {
@@ -21,3 +26,59 @@ impl From<SectorSize> for u64 {
         x.0
     }
 }
+
+/// Returns the number of nodes in a sector of the given size.
+pub fn sector_nodes(sector_size: SectorSize) -> usize {
+    u64::from(sector_size) as usize / NODE_SIZE
+}
+
+/// Returns the node ranges of the windows a sector of the given size is split into.
+pub fn sector_windows(
+    sector_size: SectorSize,
+    window_size_nodes: usize,
+) -> Result<Vec<Range<usize>>> {
+    let nodes = sector_nodes(sector_size);
+
+    ensure!(window_size_nodes > 0, "window size must not be 0");
+    ensure!(
+        nodes % window_size_nodes == 0,
+        "window size ({}) must divide the number of nodes in the sector ({})",
+        window_size_nodes,
+        nodes
+    );
+
+    Ok((0..nodes / window_size_nodes)
+        .map(|i| i * window_size_nodes..(i + 1) * window_size_nodes)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sector_nodes() {
+        assert_eq!(sector_nodes(SectorSize(4 * 128)), 16);
+        assert_eq!(sector_nodes(SectorSize(1 << 30)), 1 << 25);
+    }
+
+    #[test]
+    fn test_sector_windows() {
+        let sector_size = SectorSize(1024);
+        let window_size_nodes = 8;
+
+        let windows = sector_windows(sector_size, window_size_nodes).unwrap();
+        assert_eq!(windows.len(), 4);
+
+        let mut next = 0;
+        for window in windows {
+            assert_eq!(window.start, next);
+            assert_eq!(window.len(), window_size_nodes);
+            next = window.end;
+        }
+        assert_eq!(next, sector_nodes(sector_size));
+
+        assert!(sector_windows(sector_size, 0).is_err());
+        assert!(sector_windows(sector_size, 12).is_err());
+    }
+}
@@ -9,6 +9,8 @@ use fil_sapling_crypto::jubjub::JubjubEngine;
 use paired::bls12_381::Bls12;
 use rand::{thread_rng, Rng};
 use storage_proofs::circuit::bench::BenchCS;
+use storage_proofs::hasher::blake2s::Blake2sFunction;
+use storage_proofs::hasher::HashFunction;
 
 struct Blake2sExample<'a> {
     data: &'a [Option<bool>],
@@ -56,6 +58,45 @@ fn blake2s_benchmark(c: &mut Criterion) {
     );
 }
 
+fn blake2s_batch_benchmark(c: &mut Criterion) {
+    let params = vec![64, 1024];
+
+    c.bench(
+        "hash-blake2s-batch",
+        ParameterizedBenchmark::new(
+            "scalar",
+            |b, count| {
+                let inputs = random_pairs(*count);
+                let inputs: Vec<&[u8]> = inputs.iter().map(|input| &input[..]).collect();
+
+                b.iter(|| {
+                    black_box(
+                        inputs
+                            .iter()
+                            .map(|input| Blake2sFunction::hash(input))
+                            .collect::<Vec<_>>(),
+                    )
+                })
+            },
+            params,
+        )
+        .with_function("batched", |b, count| {
+            let inputs = random_pairs(*count);
+            let inputs: Vec<&[u8]> = inputs.iter().map(|input| &input[..]).collect();
+
+            b.iter(|| black_box(Blake2sFunction::hash_batch(&inputs)))
+        }),
+    );
+}
+
+/// `count` random 64 byte inputs, the size of a pair of tree nodes.
+fn random_pairs(count: usize) -> Vec<Vec<u8>> {
+    let mut rng = thread_rng();
+    (0..count)
+        .map(|_| (0..64).map(|_| rng.gen()).collect())
+        .collect()
+}
+
 fn blake2s_circuit_benchmark(c: &mut Criterion) {
     let mut rng1 = thread_rng();
     let groth_params = generate_random_parameters::<Bls12, _, _>(
@@ -110,5 +151,10 @@ fn blake2s_circuit_benchmark(c: &mut Criterion) {
     );
 }
 
-criterion_group!(benches, blake2s_benchmark, blake2s_circuit_benchmark);
+criterion_group!(
+    benches,
+    blake2s_benchmark,
+    blake2s_batch_benchmark,
+    blake2s_circuit_benchmark
+);
 criterion_main!(benches);
@@ -0,0 +1,128 @@
+use bellperson::{ConstraintSystem, Index, LinearCombination, SynthesisError, Variable};
+use paired::Engine;
+use std::marker::PhantomData;
+
+/// Constraint system which only counts the inputs, auxiliary variables and constraints of a
+/// circuit. Neither annotations nor linear combinations are built, so unlike `MetricCS` it runs in
+/// constant memory, and can size the circuits of large sectors.
+#[derive(Debug)]
+pub struct CountingCS<E: Engine> {
+    num_inputs: usize,
+    num_aux: usize,
+    num_constraints: usize,
+    _e: PhantomData<E>,
+}
+
+impl<E: Engine> CountingCS<E> {
+    pub fn new() -> Self {
+        CountingCS::default()
+    }
+
+    pub fn num_constraints(&self) -> usize {
+        self.num_constraints
+    }
+
+    pub fn num_inputs(&self) -> usize {
+        self.num_inputs
+    }
+
+    pub fn num_aux(&self) -> usize {
+        self.num_aux
+    }
+}
+
+impl<E: Engine> Default for CountingCS<E> {
+    fn default() -> Self {
+        CountingCS {
+            // The input ONE.
+            num_inputs: 1,
+            num_aux: 0,
+            num_constraints: 0,
+            _e: PhantomData,
+        }
+    }
+}
+
+impl<E: Engine> ConstraintSystem<E> for CountingCS<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.num_aux += 1;
+
+        Ok(Variable::new_unchecked(Index::Aux(self.num_aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, _f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.num_inputs += 1;
+
+        Ok(Variable::new_unchecked(Index::Input(self.num_inputs - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, _a: LA, _b: LB, _c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.num_constraints += 1;
+    }
+
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bellperson::gadgets::num::AllocatedNum;
+    use ff::Field;
+    use paired::bls12_381::{Bls12, Fr};
+
+    use crate::circuit::metric::MetricCS;
+
+    #[test]
+    fn test_counting_cs_matches_metric_cs() {
+        fn synthesize<CS: ConstraintSystem<Bls12>>(cs: &mut CS) {
+            let mut acc = AllocatedNum::alloc(cs.namespace(|| "x"), || Ok(Fr::one())).unwrap();
+            for i in 0..16 {
+                acc = acc
+                    .square(cs.namespace(|| format!("square {}", i)))
+                    .unwrap();
+            }
+            acc.inputize(cs.namespace(|| "y")).unwrap();
+        }
+
+        let mut metric = MetricCS::<Bls12>::new();
+        synthesize(&mut metric);
+        let mut counting = CountingCS::<Bls12>::new();
+        synthesize(&mut counting);
+
+        assert_eq!(counting.num_inputs(), metric.num_inputs());
+        assert_eq!(counting.num_aux(), metric.num_aux());
+        assert_eq!(counting.num_constraints(), metric.num_constraints());
+        assert!(counting.num_constraints() > 0);
+    }
+}
@@ -69,6 +69,10 @@ impl<E: Engine> MetricCS<E> {
         self.inputs.len()
     }
 
+    pub fn num_aux(&self) -> usize {
+        self.aux.len()
+    }
+
     pub fn pretty_print(&self) -> String {
         let mut s = String::new();
 
@@ -15,5 +15,6 @@ pub mod xor;
 
 // FIXME: Can we make a config like for test?
 pub mod bench;
+pub mod counting;
 pub mod metric;
 pub mod test;
@@ -83,8 +83,30 @@ where
 
         info!("vanilla_proof:finish");
 
+        Self::prove_with_vanilla(pub_params, pub_in, &vanilla_proofs, groth_params)
+    }
+
+    /// Like `prove`, but generates the circuit proofs from already computed vanilla proofs, one
+    /// per partition, instead of proving `priv_in` first.
+    fn prove_with_vanilla<'b>(
+        pub_params: &PublicParams<'a, S>,
+        pub_in: &S::PublicInputs,
+        vanilla_proofs: &[S::Proof],
+        groth_params: &'b groth16::Parameters<E>,
+    ) -> Result<MultiProof<'b, E>>
+    where
+        E::Params: Sync,
+    {
+        let partition_count = Self::partition_count(pub_params);
+        ensure!(
+            vanilla_proofs.len() == partition_count,
+            "expected {} vanilla proofs, got {}",
+            partition_count,
+            vanilla_proofs.len()
+        );
+
         let sanity_check =
-            S::verify_all_partitions(&pub_params.vanilla_params, &pub_in, &vanilla_proofs)?;
+            S::verify_all_partitions(&pub_params.vanilla_params, &pub_in, vanilla_proofs)?;
         assert!(sanity_check, "sanity check failed");
 
         // Use a custom pool for this, so we can control the number of threads being used.
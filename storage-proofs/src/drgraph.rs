@@ -1,6 +1,7 @@
 use std::cmp;
 use std::marker::PhantomData;
 
+use merkletree::store::StoreConfig;
 use rand::{rngs::OsRng, Rng, SeedableRng};
 use rand_chacha::ChaChaRng;
 use sha2::{Digest, Sha256};
@@ -9,7 +10,7 @@ use crate::error::*;
 use crate::fr32::bytes_into_fr_repr_safe;
 use crate::hasher::pedersen::PedersenHasher;
 use crate::hasher::Hasher;
-use crate::merkle::{create_merkle_tree, MerkleTree};
+use crate::merkle::{create_merkle_tree, create_merkle_tree_external, MerkleTree};
 use crate::parameter_cache::ParameterSetMetadata;
 use crate::util::{data_at_node_offset, NODE_SIZE};
 
@@ -18,6 +19,9 @@ pub type DefaultTreeHasher = PedersenHasher;
 
 pub const PARALLEL_MERKLE: bool = true;
 
+/// Number of leaves in each subtree built by `Graph::merkle_tree_external` (32MiB of data).
+pub const EXTERNAL_MERKLE_SUBTREE_LEAVES: usize = 1 << 20;
+
 /// The base degree used for all DRG graphs. One degree from this value is used to ensure that a
 /// given node always has its immediate predecessor as a parent, thus ensuring unique topological
 /// ordering of the graph nodes.
@@ -37,6 +41,12 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
         create_merkle_tree::<H>(None, self.size(), data)
     }
 
+    /// Computes the root of the merkle tree over the given data, writing the tree to disk as it
+    /// is built, so that only a bounded part of it is held in memory.
+    fn merkle_tree_external<'a>(&self, data: &'a [u8], config: StoreConfig) -> Result<H::Domain> {
+        create_merkle_tree_external::<H>(config, self.size(), EXTERNAL_MERKLE_SUBTREE_LEAVES, data)
+    }
+
     /// Returns the merkle tree depth.
     fn merkle_tree_depth(&self) -> u64 {
         graph_height(self.size()) as u64
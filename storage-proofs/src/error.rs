@@ -27,6 +27,8 @@ pub enum Error {
     MalformedMerkleTree,
     #[error("invalid input size")]
     InvalidInputSize,
+    #[error("invalid domain byte length: expected {}, got {}", _0, _1)]
+    InvalidDomainLength(usize, usize),
     #[error("merkle tree generation error: {}", _0)]
     MerkleTreeGenerationError(String),
     #[error("Cannot (yet) generate inclusion proof for unaligned piece.")]
@@ -3,6 +3,7 @@ use std::hash::Hasher as StdHasher;
 
 use bellperson::gadgets::{blake2s as blake2s_circuit, boolean, multipack, num};
 use bellperson::{ConstraintSystem, SynthesisError};
+use blake2s_simd::many::{hash_many, HashManyJob};
 use blake2s_simd::{Hash as Blake2sHash, Params as Blake2s, State};
 use ff::{Field, PrimeField, PrimeFieldRepr};
 use fil_sapling_crypto::jubjub::JubjubEngine;
@@ -171,10 +172,8 @@ impl Domain for Blake2sDomain {
     }
 
     fn try_from_bytes(raw: &[u8]) -> Result<Self> {
-        ensure!(
-            raw.len() == 32 && u32::from(raw[31]) <= Fr::NUM_BITS,
-            Error::InvalidInputSize
-        );
+        ensure!(raw.len() == 32, Error::InvalidDomainLength(32, raw.len()));
+        ensure!(u32::from(raw[31]) <= Fr::NUM_BITS, Error::InvalidInputSize);
 
         let mut res = Blake2sDomain::default();
         res.0.copy_from_slice(&raw[0..32]);
@@ -213,6 +212,20 @@ impl HashFunction<Blake2sDomain> for Blake2sFunction {
             .into()
     }
 
+    fn hash_batch(inputs: &[&[u8]]) -> Vec<Blake2sDomain> {
+        let mut params = Blake2s::new();
+        params.hash_length(32);
+
+        // Independent inputs are hashed in parallel SIMD lanes.
+        let mut jobs: Vec<_> = inputs
+            .iter()
+            .map(|input| HashManyJob::new(&params, input))
+            .collect();
+        hash_many(jobs.iter_mut());
+
+        jobs.iter().map(|job| job.to_hash().into()).collect()
+    }
+
     fn hash_leaf_circuit<E: JubjubEngine, CS: ConstraintSystem<E>>(
         cs: CS,
         left: &[boolean::Boolean],
@@ -158,7 +158,10 @@ impl Domain for PedersenDomain {
     }
 
     fn try_from_bytes(raw: &[u8]) -> Result<Self> {
-        ensure!(raw.len() == PedersenDomain::byte_len(), Error::BadFrBytes);
+        ensure!(
+            raw.len() == PedersenDomain::byte_len(),
+            Error::InvalidDomainLength(PedersenDomain::byte_len(), raw.len())
+        );
         let mut res: FrRepr = Default::default();
         res.read_le(raw)?;
 
@@ -336,9 +339,19 @@ mod tests {
     use std::mem;
 
     use merkletree::hash::Hashable;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
 
     use crate::merkle::MerkleTree;
 
+    #[test]
+    fn test_zero() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        assert!(PedersenDomain::zero().is_zero());
+        assert!(!PedersenDomain::random(rng).is_zero());
+    }
+
     #[test]
     fn test_path() {
         let values = ["hello", "world", "you", "two"];
@@ -128,7 +128,7 @@ impl Domain for Sha256Domain {
     fn try_from_bytes(raw: &[u8]) -> Result<Self> {
         ensure!(
             raw.len() == Sha256Domain::byte_len(),
-            Error::InvalidInputSize
+            Error::InvalidDomainLength(Sha256Domain::byte_len(), raw.len())
         );
 
         let mut res = Sha256Domain::default();
@@ -299,6 +299,14 @@ mod tests {
     use rand::SeedableRng;
     use rand_xorshift::XorShiftRng;
 
+    #[test]
+    fn test_zero() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        assert!(Sha256Domain::zero().is_zero());
+        assert!(!Sha256Domain::random(rng).is_zero());
+    }
+
     #[test]
     fn hash_leaf_circuit() {
         let mut cs = TestConstraintSystem::<Bls12>::new();
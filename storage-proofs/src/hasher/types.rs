@@ -3,9 +3,12 @@ use bellperson::{ConstraintSystem, SynthesisError};
 use fil_sapling_crypto::jubjub::JubjubEngine;
 use merkletree::hash::{Algorithm as LightAlgorithm, Hashable as LightHashable};
 use merkletree::merkle::Element;
+use num_bigint::BigUint;
+use num_traits::cast::ToPrimitive;
 use paired::bls12_381::{Fr, FrRepr};
 use serde::de::DeserializeOwned;
 use serde::ser::Serialize;
+use subtle::{Choice, ConstantTimeEq};
 
 use crate::error::Result;
 
@@ -29,11 +32,43 @@ pub trait Domain:
 {
     fn serialize(&self) -> Vec<u8>;
     fn into_bytes(&self) -> Vec<u8>;
+    /// Read a domain element from exactly `byte_len()` bytes, as written by `into_bytes`. Slices
+    /// of any other length fail with `Error::InvalidDomainLength`.
     fn try_from_bytes(raw: &[u8]) -> Result<Self>;
     /// Write itself into the given slice, LittleEndian bytes.
     fn write_bytes(&self, _: &mut [u8]) -> Result<()>;
 
     fn random<R: rand::RngCore>(rng: &mut R) -> Self;
+
+    /// The zero element.
+    fn zero() -> Self {
+        Self::from(FrRepr::default())
+    }
+
+    /// Compares the byte representations in constant time, for values which must not leak
+    /// through timing, like replica ids.
+    fn ct_eq(&self, other: &Self) -> Choice {
+        ConstantTimeEq::ct_eq(AsRef::<[u8]>::as_ref(self), AsRef::<[u8]>::as_ref(other))
+    }
+
+    /// Returns true if this is the zero element.
+    fn is_zero(&self) -> bool {
+        self.as_ref().iter().all(|b| *b == 0)
+    }
+
+    /// Reduce the little endian bytes of this element to a challenged node index, the way
+    /// challenge derivation does. The result is in `1..num_leaves`, as the first node can not be
+    /// challenged.
+    fn to_challenge_index(&self, num_leaves: usize) -> usize {
+        assert!(num_leaves > 2, "Too few leaves: {}", num_leaves);
+
+        let big_challenge = BigUint::from_bytes_le(self.as_ref());
+        let big_mod_challenge = big_challenge % (num_leaves - 1);
+        let big_mod_challenge = big_mod_challenge
+            .to_usize()
+            .expect("`big_mod_challenge` exceeds size of `usize`");
+        big_mod_challenge + 1
+    }
 }
 
 pub trait HashFunction<T: Domain>:
@@ -41,6 +76,12 @@ pub trait HashFunction<T: Domain>:
 {
     fn hash(data: &[u8]) -> T;
 
+    /// Hash each of `inputs` on its own, like calling `hash` on every one of them. Functions which
+    /// can hash several independent inputs at once faster should override this.
+    fn hash_batch(inputs: &[&[u8]]) -> Vec<T> {
+        inputs.iter().map(|input| Self::hash(input)).collect()
+    }
+
     fn hash_leaf(data: &dyn LightHashable<Self>) -> T {
         let mut a = Self::default();
         data.hash(&mut a);
@@ -79,3 +120,104 @@ pub trait Hasher: Clone + ::std::fmt::Debug + Eq + Default + Send + Sync {
 
     fn name() -> String;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use crate::hasher::{Blake2sHasher, PedersenHasher, Sha256Hasher};
+
+    fn hash_batch_matches_hash<H: Hasher>() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        // Mostly pairs of nodes, as hashed when building a tree, and some single nodes.
+        let inputs: Vec<Vec<u8>> = (0..33)
+            .map(|i| {
+                let len = if i % 4 == 0 { 32 } else { 64 };
+                (0..len).map(|_| rng.gen()).collect()
+            })
+            .collect();
+        let inputs: Vec<&[u8]> = inputs.iter().map(|input| &input[..]).collect();
+
+        let expected: Vec<H::Domain> = inputs
+            .iter()
+            .map(|input| H::Function::hash(input))
+            .collect();
+        assert_eq!(H::Function::hash_batch(&inputs), expected);
+        assert!(H::Function::hash_batch(&[]).is_empty());
+    }
+
+    fn ct_eq_matches_eq<H: Hasher>() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        for _ in 0..10 {
+            let a = H::Domain::random(rng);
+            let b = H::Domain::random(rng);
+            let a_copy = H::Domain::try_from_bytes(&a.into_bytes()).unwrap();
+
+            assert!(bool::from(Domain::ct_eq(&a, &a_copy)));
+            assert_eq!(bool::from(Domain::ct_eq(&a, &b)), a == b);
+            assert_eq!(bool::from(Domain::ct_eq(&b, &a)), b == a);
+        }
+    }
+
+    #[test]
+    fn ct_eq_matches_eq_pedersen() {
+        ct_eq_matches_eq::<PedersenHasher>();
+    }
+
+    #[test]
+    fn ct_eq_matches_eq_sha256() {
+        ct_eq_matches_eq::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn ct_eq_matches_eq_blake2s() {
+        ct_eq_matches_eq::<Blake2sHasher>();
+    }
+
+    fn try_from_bytes_checks_length<H: Hasher>() {
+        assert!(H::Domain::try_from_bytes(&[0u8; 32]).is_ok());
+
+        for len in &[0, 31, 33] {
+            let err = H::Domain::try_from_bytes(&vec![0u8; *len]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("invalid domain byte length: expected 32, got {}", len)
+            );
+        }
+    }
+
+    #[test]
+    fn try_from_bytes_checks_length_pedersen() {
+        try_from_bytes_checks_length::<PedersenHasher>();
+    }
+
+    #[test]
+    fn try_from_bytes_checks_length_sha256() {
+        try_from_bytes_checks_length::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn try_from_bytes_checks_length_blake2s() {
+        try_from_bytes_checks_length::<Blake2sHasher>();
+    }
+
+    #[test]
+    fn hash_batch_matches_hash_pedersen() {
+        hash_batch_matches_hash::<PedersenHasher>();
+    }
+
+    #[test]
+    fn hash_batch_matches_hash_sha256() {
+        hash_batch_matches_hash::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn hash_batch_matches_hash_blake2s() {
+        hash_batch_matches_hash::<Blake2sHasher>();
+    }
+}
@@ -55,6 +55,16 @@ impl<H: Hasher> MerkleProof<H> {
         m
     }
 
+    /// Construct a proof from its root, leaf and the path from the leaf up to the root.
+    pub fn from_parts(root: H::Domain, leaf: H::Domain, path: Vec<(H::Domain, bool)>) -> Self {
+        MerkleProof {
+            path,
+            root,
+            leaf,
+            _h: PhantomData,
+        }
+    }
+
     pub fn new_from_proof(p: &proof::Proof<H::Domain>) -> MerkleProof<H> {
         MerkleProof {
             path: p
@@ -244,10 +254,106 @@ pub fn create_merkle_tree<H: Hasher>(
     }
 }
 
+/// Build the merkle tree over `data` into the store of `config`, without holding the full tree in
+/// memory, and return its root.
+///
+/// The leaves are split into subtrees of `subtree_leaves` leaves each. Every subtree is hashed in
+/// memory, written to its place in the store and dropped before the next one is hashed, so only a
+/// single subtree and the list of subtree roots are resident at any time. The stored tree is the
+/// same as the one `create_merkle_tree` builds, and can be opened with `DiskStore::new_from_disk`
+/// and `MerkleTree::from_data_store`.
+pub fn create_merkle_tree_external<H: Hasher>(
+    config: StoreConfig,
+    size: usize,
+    subtree_leaves: usize,
+    data: &[u8],
+) -> Result<H::Domain> {
+    let (root, _) = build_merkle_tree_external::<H>(config, size, subtree_leaves, data)?;
+
+    Ok(root)
+}
+
+/// Like `create_merkle_tree_external`, additionally returning the largest number of nodes held in
+/// memory at once.
+fn build_merkle_tree_external<H: Hasher>(
+    config: StoreConfig,
+    size: usize,
+    subtree_leaves: usize,
+    data: &[u8],
+) -> Result<(H::Domain, usize)> {
+    ensure!(
+        data.len() == (NODE_SIZE * size) as usize,
+        Error::InvalidMerkleTreeArgs(data.len(), NODE_SIZE, size)
+    );
+    ensure!(size.is_power_of_two(), "size must be a power of two");
+    ensure!(
+        subtree_leaves.is_power_of_two() && subtree_leaves > 1,
+        "subtree_leaves must be a power of two larger than 1"
+    );
+
+    let subtree_leaves = std::cmp::min(subtree_leaves, size);
+    let subtree_height = (subtree_leaves as f64).log2() as usize;
+    let tree_height = (size as f64).log2() as usize;
+
+    // All levels are stored one after another, starting with the leaves.
+    let level_offsets: Vec<usize> = (0..=tree_height)
+        .scan(0, |offset, level| {
+            let level_offset = *offset;
+            *offset += size >> level;
+            Some(level_offset)
+        })
+        .collect();
+    let tree_len = 2 * size - 1;
+
+    let tree_config = StoreConfig::from_config(&config, config.id.clone(), Some(tree_len));
+    let mut store: DiskStore<H::Domain> = DiskStore::new_with_config(tree_len, tree_config)?;
+
+    let mut write_level = |level: usize, index: usize, nodes: &[H::Domain]| {
+        let bytes: Vec<u8> = nodes.iter().flat_map(|node| node.into_bytes()).collect();
+        store.copy_from_slice(&bytes, level_offsets[level] + index)
+    };
+
+    let hash_level = |nodes: &[H::Domain], height: usize| -> Vec<H::Domain> {
+        nodes
+            .par_chunks(2)
+            .map(|pair| H::Function::default().node(pair[0], pair[1], height))
+            .collect()
+    };
+
+    let subtrees = size / subtree_leaves;
+    let mut roots = Vec::with_capacity(subtrees);
+    let mut working_set = 0;
+    for (i, chunk) in data.chunks(subtree_leaves * NODE_SIZE).enumerate() {
+        let mut nodes = chunk
+            .chunks(NODE_SIZE)
+            .map(H::Domain::try_from_bytes)
+            .collect::<Result<Vec<_>>>()?;
+        let mut resident = nodes.len();
+
+        write_level(0, i * subtree_leaves, &nodes)?;
+        for height in 0..subtree_height {
+            nodes = hash_level(&nodes, height);
+            resident += nodes.len();
+            write_level(height + 1, i * (subtree_leaves >> (height + 1)), &nodes)?;
+        }
+
+        roots.push(nodes[0]);
+        working_set = std::cmp::max(working_set, resident + subtrees);
+    }
+
+    for height in subtree_height..tree_height {
+        roots = hash_level(&roots, height);
+        write_level(height + 1, 0, &roots)?;
+    }
+
+    Ok((roots[0], working_set))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use merkletree::store::DEFAULT_CACHED_ABOVE_BASE_LAYER;
     use rand;
     use std::io::Write;
 
@@ -298,4 +404,54 @@ mod tests {
     fn merklepath_blake2s() {
         merklepath::<Blake2sHasher>();
     }
+
+    fn merkle_tree_external<H: Hasher>() {
+        let nodes = 1024;
+        let subtree_leaves = 64;
+        let g = BucketGraph::<H>::new(nodes, BASE_DEGREE, 0, new_seed());
+        let mut rng = rand::thread_rng();
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| H::Domain::random(&mut rng).into_bytes())
+            .collect();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::new(
+            cache_dir.path(),
+            "tree-external".to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+
+        let tree = g.merkle_tree(&data).unwrap();
+        let (root, working_set) =
+            build_merkle_tree_external::<H>(config.clone(), nodes, subtree_leaves, &data).unwrap();
+        assert_eq!(tree.root(), root);
+
+        // Only a single subtree and the subtree roots are resident at any time.
+        assert_eq!(working_set, 2 * subtree_leaves - 1 + nodes / subtree_leaves);
+        assert!(working_set * 4 < tree.len());
+
+        // The full tree is written to the store.
+        let store: DiskStore<H::Domain> = DiskStore::new_from_disk(tree.len(), &config).unwrap();
+        let stored: MerkleTree<H::Domain, H::Function> =
+            MerkleTree::from_data_store(store, nodes).unwrap();
+        assert_eq!(stored.root(), root);
+        for i in 0..tree.len() {
+            assert_eq!(stored.read_at(i).unwrap(), tree.read_at(i).unwrap());
+        }
+    }
+
+    #[test]
+    fn merkle_tree_external_pedersen() {
+        merkle_tree_external::<PedersenHasher>();
+    }
+
+    #[test]
+    fn merkle_tree_external_sha256() {
+        merkle_tree_external::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn merkle_tree_external_blake2s() {
+        merkle_tree_external::<Blake2sHasher>();
+    }
 }
@@ -12,6 +12,8 @@ use std::env;
 use std::fs::{self, create_dir_all, File};
 use std::io::{self, SeekFrom};
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
 /// Bump this when circuits change to invalidate the cache.
@@ -44,17 +46,6 @@ impl LockedFile {
 
         Ok(LockedFile(f))
     }
-
-    pub fn open_exclusive<P: AsRef<Path>>(p: P) -> io::Result<Self> {
-        let f = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(p)?;
-        f.lock_exclusive()?;
-
-        Ok(LockedFile(f))
-    }
 }
 
 impl io::Write for LockedFile {
@@ -189,25 +180,12 @@ where
     }
 
     fn get_groth_params(circuit: C, pub_params: &P) -> Result<groth16::Parameters<E>> {
-        // Always seed the rng identically so parameter generation will be deterministic.
         let id = Self::cache_identifier(pub_params);
 
-        let generate = || {
-            let rng = &mut XorShiftRng::from_seed(PARAMETER_RNG_SEED);
-            info!("Actually generating groth params. (id: {})", &id);
-            let start = Instant::now();
-            let parameters = groth16::generate_random_parameters::<E, _, _>(circuit, rng);
-            let generation_time = start.elapsed();
-            info!(
-                "groth_parameter_generation_time: {:?} (id: {})",
-                generation_time, &id
-            );
-            parameters
-        };
-
         // generate (or load) Groth parameters
         let cache_path = ensure_ancestor_dirs_exist(parameter_cache_params_path(&id))?;
-        read_cached_params(&cache_path).or_else(|_| write_cached_params(&cache_path, generate()?))
+        read_cached_params(&cache_path)
+            .or_else(|_| write_cached_params(&cache_path, generate_groth_params(circuit, &id)?))
     }
 
     fn get_verifying_key(circuit: C, pub_params: &P) -> Result<groth16::VerifyingKey<E>> {
@@ -224,6 +202,43 @@ where
         read_cached_verifying_key(&cache_path)
             .or_else(|_| write_cached_verifying_key(&cache_path, generate()?))
     }
+
+    /// Like `get_verifying_key`, but never writes the Groth parameters to the cache. They are
+    /// still generated (or loaded, if already cached) to derive the verifying key from.
+    fn get_verifying_key_only(circuit: C, pub_params: &P) -> Result<groth16::VerifyingKey<E>> {
+        let id = Self::cache_identifier(pub_params);
+
+        let generate = || -> Result<groth16::VerifyingKey<E>> {
+            let params_path = parameter_cache_params_path(&id);
+            let groth_params = read_cached_params(&params_path)
+                .or_else(|_| generate_groth_params(circuit, &id))?;
+            info!("Getting verifying key. (id: {})", &id);
+            Ok(groth_params.vk)
+        };
+
+        // generate (or load) verifying key
+        let cache_path = ensure_ancestor_dirs_exist(parameter_cache_verifying_key_path(&id))?;
+        read_cached_verifying_key(&cache_path)
+            .or_else(|_| write_cached_verifying_key(&cache_path, generate()?))
+    }
+}
+
+fn generate_groth_params<E: JubjubEngine, C: Circuit<E>>(
+    circuit: C,
+    id: &str,
+) -> Result<groth16::Parameters<E>> {
+    // Always seed the rng identically so parameter generation will be deterministic.
+    let rng = &mut XorShiftRng::from_seed(PARAMETER_RNG_SEED);
+    info!("Actually generating groth params. (id: {})", id);
+    let start = Instant::now();
+    let parameters = groth16::generate_random_parameters::<E, _, _>(circuit, rng)?;
+    let generation_time = start.elapsed();
+    info!(
+        "groth_parameter_generation_time: {:?} (id: {})",
+        generation_time, id
+    );
+
+    Ok(parameters)
 }
 
 fn ensure_parent(path: &PathBuf) -> Result<()> {
@@ -277,7 +292,7 @@ fn write_cached_metadata(
     cache_entry_path: &PathBuf,
     value: CacheEntryMetadata,
 ) -> Result<CacheEntryMetadata> {
-    with_exclusive_lock(cache_entry_path, |file| {
+    write_atomically(cache_entry_path, |file| {
         serde_json::to_writer(file, &value)?;
         info!("wrote metadata to cache {:?} ", cache_entry_path);
 
@@ -289,7 +304,7 @@ fn write_cached_verifying_key<E: JubjubEngine>(
     cache_entry_path: &PathBuf,
     value: groth16::VerifyingKey<E>,
 ) -> Result<groth16::VerifyingKey<E>> {
-    with_exclusive_lock(cache_entry_path, |file| {
+    write_atomically(cache_entry_path, |file| {
         value.write(file)?;
         info!("wrote verifying key to cache {:?} ", cache_entry_path);
 
@@ -301,7 +316,7 @@ fn write_cached_params<E: JubjubEngine>(
     cache_entry_path: &PathBuf,
     value: groth16::Parameters<E>,
 ) -> Result<groth16::Parameters<E>> {
-    with_exclusive_lock(cache_entry_path, |file| {
+    write_atomically(cache_entry_path, |file| {
         value.write(file)?;
         info!("wrote groth parameters to cache {:?} ", cache_entry_path);
 
@@ -309,11 +324,37 @@ fn write_cached_params<E: JubjubEngine>(
     })
 }
 
-fn with_exclusive_lock<T>(
-    file_path: &PathBuf,
-    f: impl FnOnce(&mut LockedFile) -> Result<T>,
-) -> Result<T> {
-    with_open_file(file_path, LockedFile::open_exclusive, f)
+/// Write `file_path` through a temporary file in the same directory, which is renamed into place
+/// once `f` succeeded. Concurrent writers of the same entry can therefore not interleave, and
+/// readers never observe a partially written entry.
+fn write_atomically<T>(file_path: &PathBuf, f: impl FnOnce(&mut File) -> Result<T>) -> Result<T> {
+    static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    ensure_parent(&file_path)?;
+    let tmp_path = file_path.with_extension(format!(
+        "{}.tmp-{}-{}",
+        file_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+
+    let write = || -> Result<T> {
+        let mut file = File::create(&tmp_path)?;
+        let value = f(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, file_path)?;
+        Ok(value)
+    };
+
+    let res = write();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    res
 }
 
 fn with_exclusive_read_lock<T>(
@@ -331,3 +372,28 @@ fn with_open_file<'a, T>(
     ensure_parent(&file_path)?;
     f(&mut open_file(&file_path)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("v1-id.params");
+
+        write_atomically(&path, |file| Ok(file.write_all(b"params")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"params");
+
+        // A failed write leaves the existing entry and no temporary files behind.
+        assert!(write_atomically(&path, |file| -> Result<()> {
+            file.write_all(b"partial")?;
+            Err(format_err!("generation failed"))
+        })
+        .is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"params");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
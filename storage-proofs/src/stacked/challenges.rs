@@ -1,8 +1,9 @@
-use num_bigint::BigUint;
-use num_traits::cast::ToPrimitive;
 use sha2::{Digest, Sha256};
 
+use crate::hasher::sha256::Sha256Domain;
 use crate::hasher::Domain;
+use crate::stacked::SOUNDNESS_SPACE_GAP;
+use crate::util::NODE_SIZE;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LayerChallenges {
@@ -19,6 +20,12 @@ impl LayerChallenges {
         LayerChallenges { layers, count }
     }
 
+    /// Challenges for `layers` layers, as many as `challenges_for_far` requires for a sector of
+    /// `sector_size` bytes to reach `target_far`.
+    pub fn for_sector_size(layers: usize, sector_size: u64, target_far: f64) -> Self {
+        LayerChallenges::new(layers, challenges_for_far(sector_size, target_far))
+    }
+
     pub fn layers(&self) -> usize {
         self.layers
     }
@@ -45,6 +52,21 @@ impl LayerChallenges {
         replica_id: &D,
         seed: &[u8; 32],
         k: u8,
+    ) -> Vec<usize> {
+        let preimage = challenge_derivation_preimage(replica_id, seed);
+        self.derive_from_preimage(challenges_count, leaves, &preimage, k)
+    }
+
+    /// Derive challenges from a preimage as returned by `challenge_derivation_preimage`.
+    ///
+    /// Challenge `i` of partition `k` is derived from `sha256(preimage || j)`, where
+    /// `j = challenges_count * k + i` is encoded as a little endian `u32`.
+    pub fn derive_from_preimage(
+        &self,
+        challenges_count: usize,
+        leaves: usize,
+        preimage: &[u8],
+        k: u8,
     ) -> Vec<usize> {
         assert!(leaves > 2, "Too few leaves: {}", leaves);
 
@@ -53,25 +75,78 @@ impl LayerChallenges {
                 let j: u32 = ((challenges_count * k as usize) + i) as u32;
 
                 let hash = Sha256::new()
-                    .chain(replica_id.into_bytes())
-                    .chain(seed)
+                    .chain(preimage)
                     .chain(&j.to_le_bytes())
                     .result();
 
-                let big_challenge = BigUint::from_bytes_le(hash.as_ref());
-
                 // For now, we cannot try to prove the first or last node, so make sure the challenge
                 // can never be 0.
-                let big_mod_challenge = big_challenge % (leaves - 1);
-                let big_mod_challenge = big_mod_challenge
-                    .to_usize()
-                    .expect("`big_mod_challenge` exceeds size of `usize`");
-                big_mod_challenge + 1
+                let mut challenge = [0u8; 32];
+                challenge.copy_from_slice(hash.as_ref());
+                Sha256Domain(challenge).to_challenge_index(leaves)
             })
             .collect()
     }
 }
 
+/// The probabilities of the challenges of a sector of `sector_size` bytes to miss all incorrectly
+/// replicated nodes, if a `SOUNDNESS_SPACE_GAP` fraction of its nodes is incorrect, given that all
+/// challenges before them missed.
+///
+/// The challenges are modeled as distinct nodes drawn uniformly without replacement, so challenge
+/// `i` misses with probability `(nodes - bad_nodes - i) / (nodes - i)`, and `n` challenges all miss
+/// with the product of the first `n` probabilities.
+pub(crate) fn miss_probabilities(sector_size: u64) -> impl Iterator<Item = f64> {
+    let nodes = (sector_size / NODE_SIZE as u64) as f64;
+    let bad_nodes = (nodes * SOUNDNESS_SPACE_GAP).ceil();
+
+    (0u64..).map(move |i| {
+        let i = i as f64;
+        if nodes - bad_nodes - i <= 0.0 {
+            0.0
+        } else {
+            (nodes - bad_nodes - i) / (nodes - i)
+        }
+    })
+}
+
+/// The minimum number of challenges for which a sector of `sector_size` bytes, with a
+/// `SOUNDNESS_SPACE_GAP` fraction of its nodes replicated incorrectly, passes all challenges with a
+/// probability (the false acceptance rate) of at most `target_far`, as given by
+/// `miss_probabilities`.
+pub fn challenges_for_far(sector_size: u64, target_far: f64) -> usize {
+    assert!(
+        target_far > 0.0 && target_far < 1.0,
+        "target false acceptance rate must be in (0, 1), got {}",
+        target_far
+    );
+
+    let mut far = 1.0;
+    let mut challenges = 0;
+    for miss_probability in miss_probabilities(sector_size) {
+        if far <= target_far {
+            break;
+        }
+        assert!(
+            miss_probability < 1.0,
+            "sector of {} bytes is too small",
+            sector_size
+        );
+
+        far *= miss_probability;
+        challenges += 1;
+    }
+
+    challenges
+}
+
+/// The bytes every challenge is derived from: `replica_id || seed`.
+pub fn challenge_derivation_preimage<D: Domain>(replica_id: &D, seed: &[u8; 32]) -> Vec<u8> {
+    let mut preimage = replica_id.into_bytes();
+    preimage.extend_from_slice(seed);
+    preimage
+}
+
 #[derive(Debug, Default)]
 pub struct ChallengeRequirements {
     pub minimum_challenges: usize,
@@ -81,6 +156,9 @@ pub struct ChallengeRequirements {
 mod test {
     use super::*;
     use crate::hasher::pedersen::PedersenDomain;
+    use crate::stacked::StackedConfig;
+    use num_bigint::BigUint;
+    use num_traits::cast::ToPrimitive;
     use rand::{thread_rng, Rng};
     use std::collections::HashMap;
 
@@ -147,4 +225,96 @@ mod test {
             assert_eq!(one_partition_challenges, many_partition_challenges);
         }
     }
+
+    #[test]
+    fn challenge_derivation_from_preimage() {
+        let n = 20;
+        let leaves = 1 << 20;
+        let rng = &mut thread_rng();
+        let replica_id: PedersenDomain = PedersenDomain::random(rng);
+        let seed: [u8; 32] = rng.gen();
+        let layer_challenges = LayerChallenges::new(4, n);
+
+        let preimage = challenge_derivation_preimage(&replica_id, &seed);
+
+        for k in 0..3u8 {
+            let expected = layer_challenges.derive_all(leaves, &replica_id, &seed, k);
+
+            let reproduced = (0..n)
+                .map(|i| {
+                    let j = (n * k as usize + i) as u32;
+                    let hash = Sha256::new()
+                        .chain(&preimage)
+                        .chain(&j.to_le_bytes())
+                        .result();
+                    let challenge = BigUint::from_bytes_le(hash.as_ref()) % (leaves - 1);
+                    challenge.to_usize().unwrap() + 1
+                })
+                .collect::<Vec<_>>();
+
+            assert_eq!(expected, reproduced);
+        }
+    }
+
+    #[test]
+    fn domain_to_challenge_index() {
+        let n = 20;
+        let leaves = 1 << 20;
+        let rng = &mut thread_rng();
+        let replica_id: PedersenDomain = PedersenDomain::random(rng);
+        let seed: [u8; 32] = rng.gen();
+        let k = 3;
+
+        let challenges = LayerChallenges::new(2, n).derive_all(leaves, &replica_id, &seed, k);
+
+        let preimage = challenge_derivation_preimage(&replica_id, &seed);
+        for (i, challenge) in challenges.iter().enumerate() {
+            let j = (n * k as usize + i) as u32;
+            let hash = Sha256::new()
+                .chain(&preimage)
+                .chain(&j.to_le_bytes())
+                .result();
+            let element = Sha256Domain::try_from_bytes(hash.as_ref()).unwrap();
+
+            assert_eq!(element.to_challenge_index(leaves), *challenge);
+        }
+
+        // Any domain element reduces into the challengeable range.
+        for _ in 0..100 {
+            let index = PedersenDomain::random(rng).to_challenge_index(leaves);
+            assert!(index >= 1 && index < leaves);
+        }
+    }
+
+    #[test]
+    fn challenges_for_target_far() {
+        let sector_size = 1 << 30;
+
+        let mut previous = 0;
+        for bits in &[1, 10, 20, 40, 80, 128] {
+            let target_far = 2f64.powi(-*bits);
+            let challenges = challenges_for_far(sector_size, target_far);
+            assert!(challenges >= previous);
+            previous = challenges;
+
+            // The estimated soundness of that many challenges reaches the target.
+            let config = StackedConfig::new(2, challenges, challenges);
+            assert!(config.meets_soundness(sector_size, f64::from(*bits)));
+            if challenges > 1 {
+                let config = StackedConfig::new(2, challenges - 1, challenges - 1);
+                assert!(!config.meets_soundness(sector_size, f64::from(*bits)));
+            }
+        }
+
+        // Every challenge hits an incorrect node with a probability of about 0.2.
+        assert_eq!(challenges_for_far(sector_size, 0.8), 1);
+        assert_eq!(challenges_for_far(sector_size, 0.7), 2);
+
+        let challenges = LayerChallenges::for_sector_size(4, sector_size, 1e-6);
+        assert_eq!(challenges.layers(), 4);
+        assert_eq!(
+            challenges.challenges_count_all(),
+            challenges_for_far(sector_size, 1e-6)
+        );
+    }
 }
@@ -1,9 +1,12 @@
 use std::marker::PhantomData;
 
+use merkletree::merkle::FromIndexedParallelIterator;
+use rayon::prelude::*;
+
 use crate::error::Result;
 use crate::hasher::pedersen::PedersenDomain;
 use crate::hasher::Hasher;
-use crate::merkle::MerkleProof;
+use crate::merkle::{MerkleProof, MerkleTree};
 use crate::stacked::{column_proof::ColumnProof, hash::hash_single_column, params::Tree};
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -43,6 +46,19 @@ impl<H: Hasher> Column<H> {
         hash_single_column(&self.rows[..])
     }
 
+    /// The `(window_index, layer)` each row of the column was read from, in row order.
+    ///
+    /// Rows are grouped by window, in increasing window order. Within a window they hold the
+    /// labels of layers `1..=layers` in increasing layer order, which for a replica excludes its
+    /// last layer.
+    pub fn row_indices(&self) -> Vec<(usize, usize)> {
+        let windows = self.rows.len() / self.layers;
+
+        (0..windows)
+            .flat_map(|window_index| (1..=self.layers).map(move |layer| (window_index, layer)))
+            .collect()
+    }
+
     pub fn get_node_at_layer(&self, window_index: usize, layer: usize) -> &H::Domain {
         assert!(layer > 0, "layer must be greater than 0");
         let row_layer_index = layer - 1;
@@ -58,3 +74,16 @@ impl<H: Hasher> Column<H> {
         Ok(ColumnProof::<H>::from_column(self, inclusion_proof))
     }
 }
+
+/// Rebuild `comm_c`, the root of the column tree, from the hashes of all columns, in column order.
+pub fn comm_c_from_columns<H: Hasher>(column_hashes: &[H::Domain]) -> Result<H::Domain> {
+    ensure!(
+        column_hashes.len() > 1 && column_hashes.len().is_power_of_two(),
+        "number of columns ({}) must be a power of two greater than one",
+        column_hashes.len()
+    );
+
+    let tree: Tree<H> = MerkleTree::from_par_iter(column_hashes.par_iter().cloned())?;
+
+    Ok(tree.root())
+}
@@ -61,3 +61,76 @@ impl<H: Hasher> EncodingProof<H> {
         true
     }
 }
+
+/// Verify a single encoding proof on its own: `encoded_node` must be the encoding of
+/// `decoded_node` under the key derived from `replica_id` and the parents in `proof`.
+///
+/// Only the last layer is encoded, so the nodes are the leaves of `comm_q` and `comm_d` at the
+/// challenged node of the proof's window, as checked when verifying a `WindowProof`.
+pub fn verify_encoding_proof<H: Hasher, G: Hasher>(
+    proof: &EncodingProof<H>,
+    replica_id: &H::Domain,
+    encoded_node: &H::Domain,
+    decoded_node: &G::Domain,
+) -> bool {
+    proof.verify::<G>(replica_id, encoded_node, decoded_node)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ff::Field;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    use crate::hasher::{Domain, PedersenHasher, Sha256Hasher};
+
+    #[test]
+    fn test_verify_encoding_proof() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        let parents = (0..14)
+            .map(|_| <PedersenHasher as Hasher>::Domain::random(rng))
+            .collect::<Vec<_>>();
+        let proof = EncodingProof::<PedersenHasher>::new(1, 7, parents);
+
+        let decoded_node = <Sha256Hasher as Hasher>::Domain::from(Fr::random(rng));
+        let key = proof.create_key(&replica_id);
+        let encoded_node = encode(key, Fr::from(decoded_node).into());
+
+        assert!(verify_encoding_proof::<_, Sha256Hasher>(
+            &proof,
+            &replica_id,
+            &encoded_node,
+            &decoded_node
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.parents[3] = <PedersenHasher as Hasher>::Domain::random(rng);
+        assert!(!verify_encoding_proof::<_, Sha256Hasher>(
+            &tampered,
+            &replica_id,
+            &encoded_node,
+            &decoded_node
+        ));
+
+        let mut tampered = proof.clone();
+        tampered.node += 1;
+        assert!(!verify_encoding_proof::<_, Sha256Hasher>(
+            &tampered,
+            &replica_id,
+            &encoded_node,
+            &decoded_node
+        ));
+
+        let other_replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        assert!(!verify_encoding_proof::<_, Sha256Hasher>(
+            &proof,
+            &other_replica_id,
+            &encoded_node,
+            &decoded_node
+        ));
+    }
+}
@@ -282,6 +282,20 @@ where
         self.base_graph().parents(raw_node, parents);
     }
 
+    /// The DRG parents of `node`, as used when labeling it and proving its column.
+    pub fn drg_parents(&self, node: usize) -> Vec<u32> {
+        let mut parents = vec![0; self.base_graph().degree()];
+        self.base_parents(node, &mut parents);
+        parents
+    }
+
+    /// The expansion parents of `node`, as used when labeling it and proving its column.
+    pub fn expansion_parents(&self, node: usize) -> Vec<u32> {
+        let mut parents = vec![0; self.expansion_degree()];
+        self.expanded_parents(node, &mut parents);
+        parents
+    }
+
     /// Assign `self.expansion_degree` parents to `node` using an invertible permutation
     /// that is applied one way for the forward layers and one way for the reversed
     /// ones.
@@ -13,15 +13,18 @@ mod porep;
 mod proof;
 mod proof_scheme;
 
-pub use self::challenges::{ChallengeRequirements, LayerChallenges};
-pub use self::column::Column;
+pub use self::challenges::{
+    challenge_derivation_preimage, challenges_for_far, ChallengeRequirements, LayerChallenges,
+};
+pub use self::column::{comm_c_from_columns, Column};
 pub use self::column_proof::ColumnProof;
-pub use self::encoding_proof::EncodingProof;
+pub use self::encoding_proof::{verify_encoding_proof, EncodingProof};
 pub use self::graph::{StackedBucketGraph, StackedGraph, EXP_DEGREE};
 pub use self::params::{
-    generate_replica_id, CacheKey, PersistentAux, PrivateInputs, Proof, PublicInputs, PublicParams,
+    generate_replica_id, load_encodings, proof_derived_comm_r, public_inputs_for, CacheKey,
+    ChallengeProof, PersistentAux, PrivateInputs, Proof, PublicInputs, PublicParams,
     ReplicaColumnProof, SetupParams, Tau, TemporaryAux, TemporaryAuxCache, WindowProof,
-    WrapperProof,
+    WrapperProof, PROOF_FORMAT_VERSION, PROOF_MAGIC,
 };
-pub use self::proof::{StackedConfig, StackedDrg};
+pub use self::proof::{StackedConfig, StackedDrg, TreeBuildTimings, SOUNDNESS_SPACE_GAP};
 pub use labeling_proof::LabelingProof;
@@ -1,10 +1,14 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::io::{self, Read, Write};
 use std::marker::PhantomData;
 
 use anyhow::Context;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use merkletree::merkle::get_merkle_tree_leafs;
 use merkletree::store::{DiskStore, Store, StoreConfig};
+use paired::bls12_381::Fr;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::drgraph::Graph;
@@ -14,7 +18,8 @@ use crate::hasher::{Domain, Hasher};
 use crate::merkle::{MerkleProof, MerkleTree};
 use crate::parameter_cache::ParameterSetMetadata;
 use crate::stacked::{
-    column::Column, column_proof::ColumnProof, graph::StackedBucketGraph, proof::StackedConfig,
+    challenges::challenge_derivation_preimage, column::Column, column_proof::ColumnProof,
+    graph::StackedBucketGraph, hash::hash3, proof::StackedConfig, verify_encoding_proof,
     EncodingProof, LabelingProof, LayerChallenges,
 };
 use crate::util::{data_at_node, NODE_SIZE};
@@ -113,6 +118,48 @@ where
     pub fn layer_size(&self) -> usize {
         self.wrapper_graph.size() * NODE_SIZE
     }
+
+    /// Total number of parents of a node, i.e. `base_degree() + expansion_degree()`.
+    pub fn degree(&self) -> usize {
+        self.window_graph.degree()
+    }
+
+    /// Number of DRG parents of a node.
+    pub fn base_degree(&self) -> usize {
+        self.window_graph.base_graph().degree()
+    }
+
+    /// Number of expander parents of a node.
+    pub fn expansion_degree(&self) -> usize {
+        self.window_graph.expansion_degree()
+    }
+
+    /// Returns the `(layer, node)` pairs of the labels in the column at `column_index`, in the
+    /// order in which they appear in the column.
+    pub fn column_openings(&self, column_index: usize) -> Vec<(usize, usize)> {
+        let layers = self.config.layers();
+        let window_size_nodes = self.window_size_nodes();
+
+        (0..self.num_windows())
+            .flat_map(|window_index| {
+                (1..layers)
+                    .map(move |layer| (layer, window_index * window_size_nodes + column_index))
+            })
+            .collect()
+    }
+
+    /// Returns the `(layer, node)` pairs of all labels opened by the `ReplicaColumnProof` for the
+    /// window challenge `challenge`. The column of the challenge comes first, followed by the
+    /// columns of its DRG and expander parents.
+    pub fn challenge_openings(&self, challenge: usize) -> Vec<(usize, usize)> {
+        let mut parents = vec![0; self.window_graph.degree()];
+        self.window_graph.parents(challenge, &mut parents);
+
+        std::iter::once(challenge)
+            .chain(parents.into_iter().map(|parent| parent as usize))
+            .flat_map(|column_index| self.column_openings(column_index))
+            .collect()
+    }
 }
 
 impl<H> ParameterSetMetadata for PublicParams<H>
@@ -157,6 +204,10 @@ pub struct PublicInputs<T: Domain, S: Domain> {
 }
 
 impl<T: Domain, S: Domain> PublicInputs<T, S> {
+    /// Derive the challenges for the given partition.
+    ///
+    /// Challenges are always derived from `replica_id` and `seed`, `tau.comm_r` is not part of
+    /// the derivation. When `partition_k` is `None` the first partition (`k = 0`) is used.
     pub fn all_challenges(
         &self,
         layer_challenges: &LayerChallenges,
@@ -167,6 +218,14 @@ impl<T: Domain, S: Domain> PublicInputs<T, S> {
 
         layer_challenges.derive_all::<T>(leaves, &self.replica_id, &self.seed, k as u8)
     }
+
+    /// The byte preimage challenges are derived from, see `LayerChallenges::derive_from_preimage`.
+    ///
+    /// The preimage is shared by all partitions, the partition only affects the challenge index
+    /// appended to it.
+    pub fn challenge_derivation_preimage(&self) -> Vec<u8> {
+        challenge_derivation_preimage(&self.replica_id, &self.seed)
+    }
 }
 
 #[derive(Debug)]
@@ -192,6 +251,164 @@ pub struct Proof<H: Hasher, G: Hasher> {
     pub comm_r_last: H::Domain,
 }
 
+/// The part of a `Proof` which proves a single challenge.
+#[derive(Debug, Clone)]
+pub enum ChallengeProof<H: Hasher, G: Hasher> {
+    /// Proof of a window challenge.
+    Window(WindowProof<H, G>),
+    /// Proof of a wrapper challenge.
+    Wrapper(WrapperProof<H>),
+}
+
+impl<H: Hasher, G: Hasher> Proof<H, G> {
+    /// Split the proof into the proofs of its challenges: the window challenges followed by the
+    /// wrapper challenges, each in challenge order.
+    pub fn per_challenge(&self) -> Vec<ChallengeProof<H, G>> {
+        self.window_proofs
+            .iter()
+            .cloned()
+            .map(ChallengeProof::Window)
+            .chain(
+                self.wrapper_proofs
+                    .iter()
+                    .cloned()
+                    .map(ChallengeProof::Wrapper),
+            )
+            .collect()
+    }
+
+    /// Reassemble a proof from the proofs of its challenges, as returned by `per_challenge`. The
+    /// roots are taken from the merkle proofs, which must agree on them.
+    pub fn from_challenge_proofs(challenge_proofs: Vec<ChallengeProof<H, G>>) -> Result<Self> {
+        let mut window_proofs = Vec::new();
+        let mut wrapper_proofs = Vec::new();
+        for challenge_proof in challenge_proofs {
+            match challenge_proof {
+                ChallengeProof::Window(proof) => window_proofs.push(proof),
+                ChallengeProof::Wrapper(proof) => wrapper_proofs.push(proof),
+            }
+        }
+
+        ensure!(!window_proofs.is_empty(), "missing window proofs");
+        ensure!(!wrapper_proofs.is_empty(), "missing wrapper proofs");
+
+        let comm_c = *window_proofs[0].comm_c();
+        let comm_q = *window_proofs[0]
+            .comm_q_proofs
+            .first()
+            .map(MerkleProof::root)
+            .ok_or_else(|| anyhow!("missing comm_q proofs"))?;
+        let comm_r_last = *wrapper_proofs[0].comm_r_last();
+
+        let proof = Proof {
+            window_proofs,
+            wrapper_proofs,
+            comm_c,
+            comm_q,
+            comm_r_last,
+        };
+
+        // Checks that all merkle proofs agree on the roots.
+        proof_derived_comm_r(&proof)?;
+
+        Ok(proof)
+    }
+}
+
+/// Magic bytes at the start of a versioned proof encoding.
+pub const PROOF_MAGIC: &[u8; 4] = b"FILP";
+
+/// Current version of the versioned proof encoding.
+pub const PROOF_FORMAT_VERSION: u16 = 1;
+
+impl<H: Hasher, G: Hasher> Proof<H, G> {
+    /// Encode the proof as bincode, prefixed by a header which allows detecting format changes:
+    /// `PROOF_MAGIC`, the little endian u16 `PROOF_FORMAT_VERSION`, and the names of `H` and `G`,
+    /// each prefixed by its little endian u16 length.
+    pub fn to_versioned_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PROOF_MAGIC);
+        bytes.write_u16::<LittleEndian>(PROOF_FORMAT_VERSION)?;
+        for name in &[H::name(), G::name()] {
+            bytes.write_u16::<LittleEndian>(name.len() as u16)?;
+            bytes.extend_from_slice(name.as_bytes());
+        }
+
+        bincode::serialize_into(&mut bytes, self)?;
+
+        Ok(bytes)
+    }
+
+    /// Decode a proof encoded by `to_versioned_bytes`, after validating its header.
+    pub fn from_versioned_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = bytes;
+
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("missing proof header")?;
+        ensure!(&magic == PROOF_MAGIC, "invalid proof magic {:?}", magic);
+
+        let version = reader.read_u16::<LittleEndian>()?;
+        ensure!(
+            version == PROOF_FORMAT_VERSION,
+            "unsupported proof format version {} (expected {})",
+            version,
+            PROOF_FORMAT_VERSION
+        );
+
+        for expected in &[H::name(), G::name()] {
+            let len = reader.read_u16::<LittleEndian>()? as usize;
+            ensure!(reader.len() >= len, "truncated proof header");
+            let (name, rest) = reader.split_at(len);
+            ensure!(
+                name == expected.as_bytes(),
+                "proof hasher {:?} does not match {}",
+                String::from_utf8_lossy(name),
+                expected
+            );
+            reader = rest;
+        }
+
+        Ok(bincode::deserialize(reader)?)
+    }
+}
+
+/// Recompute `comm_r` from the roots referenced by the merkle proofs contained in `proof`.
+///
+/// Fails if the proof is empty or its merkle proofs do not agree on the roots of the column,
+/// q and last replica trees.
+pub fn proof_derived_comm_r<H: Hasher, G: Hasher>(proof: &Proof<H, G>) -> Result<H::Domain> {
+    ensure!(!proof.window_proofs.is_empty(), "missing window proofs");
+    ensure!(!proof.wrapper_proofs.is_empty(), "missing wrapper proofs");
+
+    let comm_c = proof.window_proofs[0].comm_c();
+    let comm_q = proof.window_proofs[0]
+        .comm_q_proofs
+        .first()
+        .map(MerkleProof::root)
+        .ok_or_else(|| anyhow!("missing comm_q proofs"))?;
+    let comm_r_last = proof.wrapper_proofs[0].comm_r_last();
+
+    for window_proof in &proof.window_proofs {
+        ensure!(window_proof.comm_c() == comm_c, "inconsistent comm_c");
+        for comm_q_proof in &window_proof.comm_q_proofs {
+            ensure!(comm_q_proof.root() == comm_q, "inconsistent comm_q");
+        }
+    }
+    for wrapper_proof in &proof.wrapper_proofs {
+        ensure!(
+            wrapper_proof.comm_r_last() == comm_r_last,
+            "inconsistent comm_r_last"
+        );
+        for comm_q_proof in &wrapper_proof.comm_q_parents_proofs {
+            ensure!(comm_q_proof.root() == comm_q, "inconsistent comm_q");
+        }
+    }
+
+    Ok(Fr::from(hash3(comm_c, comm_q, comm_r_last)).into())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowProof<H: Hasher, G: Hasher> {
     /// One proof for every window.
@@ -257,13 +474,39 @@ impl<H: Hasher> WrapperProof<H> {
         wrapper_graph: &StackedBucketGraph<H>,
         comm_q: &H::Domain,
     ) -> bool {
-        let replica_id = &pub_inputs.replica_id;
+        check!(pub_inputs.tau.is_some());
 
+        self.verify_with_roots(
+            &pub_inputs.replica_id,
+            challenge,
+            wrapper_graph,
+            comm_q,
+            self.comm_r_last(),
+        )
+    }
+
+    /// Verify only the opening of the challenged node in `tree_r_last` against `comm_r_last`,
+    /// which isolates failures of that tree from the other parts of the proof.
+    pub fn verify_comm_r_last_proof(&self, comm_r_last: &H::Domain, challenge: usize) -> bool {
+        check_eq!(self.comm_r_last(), comm_r_last);
+        check!(self.comm_r_last_proof.proves_challenge(challenge));
+
+        true
+    }
+
+    /// Verify the proof against the roots shared by all challenges of its partition.
+    pub fn verify_with_roots(
+        &self,
+        replica_id: &H::Domain,
+        challenge: usize,
+        wrapper_graph: &StackedBucketGraph<H>,
+        comm_q: &H::Domain,
+        comm_r_last: &H::Domain,
+    ) -> bool {
         check!(challenge < wrapper_graph.size());
-        check!(pub_inputs.tau.is_some());
 
         trace!("verify final replica layer openings");
-        check!(self.comm_r_last_proof.proves_challenge(challenge));
+        check!(self.verify_comm_r_last_proof(comm_r_last, challenge));
 
         trace!("verify comm_q_parents");
         let mut parents = vec![0; wrapper_graph.expansion_degree()];
@@ -295,12 +538,34 @@ impl<H: Hasher, G: Hasher> WindowProof<H, G> {
         challenge: usize,
         comm_q: &H::Domain,
         comm_c: &H::Domain,
+    ) -> bool {
+        if let Some(ref tau) = pub_inputs.tau {
+            self.verify_with_roots(
+                pub_params,
+                &pub_inputs.replica_id,
+                challenge,
+                &tau.comm_d,
+                comm_q,
+                comm_c,
+            )
+        } else {
+            false
+        }
+    }
+
+    /// Verify the full proof against the roots shared by all challenges of its partition.
+    pub fn verify_with_roots(
+        &self,
+        pub_params: &PublicParams<H>,
+        replica_id: &H::Domain,
+        challenge: usize,
+        comm_d: &G::Domain,
+        comm_q: &H::Domain,
+        comm_c: &H::Domain,
     ) -> bool {
         let window_graph = &pub_params.window_graph;
-        let replica_id = &pub_inputs.replica_id;
 
         check!(challenge < window_graph.size());
-        check!(pub_inputs.tau.is_some());
 
         let num_windows = pub_params.num_windows();
         check_eq!(self.comm_d_proofs.len(), num_windows);
@@ -309,7 +574,7 @@ impl<H: Hasher, G: Hasher> WindowProof<H, G> {
 
         // Verify initial data layer
         trace!("verify initial data layer");
-        check!(self.verify_comm_d_proofs(challenge, pub_params, pub_inputs));
+        check!(self.verify_comm_d_proofs(pub_params, challenge, comm_d));
 
         // Verify q data layer
         trace!("verify q data layer");
@@ -335,7 +600,8 @@ impl<H: Hasher, G: Hasher> WindowProof<H, G> {
             .iter()
             .zip(self.comm_q_proofs.iter().zip(self.comm_d_proofs.iter()))
         {
-            check!(encoding_proof.verify::<G>(
+            check!(verify_encoding_proof::<H, G>(
+                encoding_proof,
                 replica_id,
                 comm_q_proof.leaf(),
                 comm_d_proof.leaf(),
@@ -346,18 +612,14 @@ impl<H: Hasher, G: Hasher> WindowProof<H, G> {
 
     fn verify_comm_d_proofs(
         &self,
-        challenge: usize,
         pub_params: &PublicParams<H>,
-        pub_inputs: &PublicInputs<<H as Hasher>::Domain, <G as Hasher>::Domain>,
+        challenge: usize,
+        comm_d: &G::Domain,
     ) -> bool {
         for (window_index, comm_d_proof) in self.comm_d_proofs.iter().enumerate() {
             let c = window_index * pub_params.window_size_nodes() + challenge;
             check!(comm_d_proof.proves_challenge(c));
-            if let Some(ref tau) = pub_inputs.tau {
-                check_eq!(comm_d_proof.root(), &tau.comm_d);
-            } else {
-                return false;
-            }
+            check_eq!(comm_d_proof.root(), comm_d);
         }
 
         true
@@ -453,6 +715,27 @@ impl<H: Hasher> ReplicaColumnProof<H> {
     }
 }
 
+/// Read the encodings of all layers, as written by `TemporaryAux::dump_encodings`.
+pub fn load_encodings<R: Read>(mut reader: R) -> Result<Vec<Vec<u8>>> {
+    let mut encodings = Vec::new();
+
+    loop {
+        let len = match reader.read_u64::<LittleEndian>() {
+            Ok(len) => len as usize,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut encoding = vec![0u8; len];
+        reader
+            .read_exact(&mut encoding)
+            .with_context(|| format!("truncated encoding of layer {}", encodings.len() + 1))?;
+        encodings.push(encoding);
+    }
+
+    Ok(encodings)
+}
+
 pub type TransformedLayers<H, G> = (
     Tau<<H as Hasher>::Domain, <G as Hasher>::Domain>,
     PersistentAux<<H as Hasher>::Domain>,
@@ -494,6 +777,28 @@ impl<H: Hasher, G: Hasher> TemporaryAux<H, G> {
         Ok(self.labels_for_layer(layer).read_at(node_index as usize)?)
     }
 
+    /// Write the encodings of all layers to `writer`, each prefixed by its length in bytes as a
+    /// little endian `u64`. They can be read back using `load_encodings`.
+    pub fn dump_encodings<W: Write>(&self, mut writer: W) -> Result<()> {
+        for (i, config) in self.labels.labels.iter().enumerate() {
+            // Label stores are sized by the number of bytes of the replicated data.
+            let size = config
+                .size
+                .ok_or_else(|| anyhow!("missing size of layer {}", i + 1))?;
+            let nodes = size / NODE_SIZE;
+
+            let store = self.labels_for_layer(i + 1);
+            writer.write_u64::<LittleEndian>((nodes * NODE_SIZE) as u64)?;
+            for node in store.read_range(0..nodes)? {
+                writer.write_all(AsRef::<[u8]>::as_ref(&node))?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
     pub fn column(&self, layers: usize, column_index: u32) -> Result<Column<H>> {
         self.labels.column(layers, column_index)
     }
@@ -599,6 +904,14 @@ impl<H: Hasher, G: Hasher> TemporaryAuxCache<H, G> {
     pub fn column(&self, column_index: u32, pub_params: &PublicParams<H>) -> Result<Column<H>> {
         self.labels.column(column_index, pub_params)
     }
+
+    pub fn columns(
+        &self,
+        column_indices: &[u32],
+        pub_params: &PublicParams<H>,
+    ) -> Result<Vec<Column<H>>> {
+        self.labels.columns(column_indices, pub_params)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -742,12 +1055,35 @@ impl<H: Hasher> LabelsCache<H> {
 
         Ok(Column::new(node, len - 1, rows))
     }
+
+    /// Build the columns for the given nodes in parallel, in the order of `nodes`.
+    pub fn columns(&self, nodes: &[u32], pub_params: &PublicParams<H>) -> Result<Vec<Column<H>>> {
+        nodes
+            .par_iter()
+            .map(|node| self.column(*node, pub_params))
+            .collect()
+    }
 }
 
 pub fn get_node<H: Hasher>(data: &[u8], index: usize) -> Result<H::Domain> {
     H::Domain::try_from_bytes(data_at_node(data, index).expect("invalid node math"))
 }
 
+/// Construct the public inputs for proving or verifying partition `k` of a replica.
+pub fn public_inputs_for<H: Hasher, G: Hasher>(
+    replica_id: H::Domain,
+    tau: Tau<H::Domain, G::Domain>,
+    seed: [u8; 32],
+    k: usize,
+) -> PublicInputs<H::Domain, G::Domain> {
+    PublicInputs {
+        replica_id,
+        seed,
+        tau: Some(tau),
+        k: Some(k),
+    }
+}
+
 /// Generate the replica id as expected for Stacked DRG.
 pub fn generate_replica_id<H: Hasher, T: AsRef<[u8]>>(
     prover_id: &[u8; 32],
@@ -766,3 +1102,161 @@ pub fn generate_replica_id<H: Hasher, T: AsRef<[u8]>>(
 
     bytes_into_fr_repr_safe(hash.as_ref()).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{Rng, SeedableRng};
+    use rand_xorshift::XorShiftRng;
+
+    use crate::drgraph::{new_seed, BASE_DEGREE};
+    use crate::hasher::{PedersenHasher, Sha256Hasher};
+    use crate::stacked::EXP_DEGREE;
+
+    use merkletree::store::DEFAULT_CACHED_ABOVE_BASE_LAYER;
+
+    #[test]
+    fn public_inputs_for_matches_manual_construction() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        let tau = Tau {
+            comm_d: <Sha256Hasher as Hasher>::Domain::random(rng),
+            comm_r: <PedersenHasher as Hasher>::Domain::random(rng),
+        };
+        let seed: [u8; 32] = rng.gen();
+        let layer_challenges = LayerChallenges::new(4, 5);
+        let leaves = 64;
+
+        for k in 0..3 {
+            let manual = PublicInputs {
+                replica_id,
+                seed,
+                tau: Some(tau.clone()),
+                k: Some(k),
+            };
+            let constructed =
+                public_inputs_for::<PedersenHasher, Sha256Hasher>(replica_id, tau.clone(), seed, k);
+
+            assert_eq!(constructed.k, Some(k));
+            assert_eq!(constructed.tau, Some(tau.clone()));
+            assert_eq!(
+                manual.all_challenges(&layer_challenges, leaves, manual.k),
+                constructed.all_challenges(&layer_challenges, leaves, constructed.k),
+            );
+        }
+    }
+
+    #[test]
+    fn public_params_degrees() {
+        let nodes = 64;
+        let seed = new_seed();
+        let window_graph = StackedBucketGraph::<PedersenHasher>::new_stacked(
+            nodes / 2,
+            BASE_DEGREE,
+            EXP_DEGREE,
+            seed,
+        );
+        let wrapper_graph =
+            StackedBucketGraph::<PedersenHasher>::new_stacked(nodes, BASE_DEGREE, EXP_DEGREE, seed);
+        let pp = PublicParams::new(
+            window_graph,
+            wrapper_graph,
+            StackedConfig::new(4, 1, 1),
+            nodes / 2,
+        );
+
+        assert_eq!(pp.base_degree(), BASE_DEGREE);
+        assert_eq!(pp.expansion_degree(), EXP_DEGREE);
+        assert_eq!(pp.degree(), BASE_DEGREE + EXP_DEGREE);
+    }
+
+    #[test]
+    fn proof_versioned_bytes_roundtrip() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+
+        let proof = Proof::<PedersenHasher, Sha256Hasher> {
+            window_proofs: Vec::new(),
+            wrapper_proofs: Vec::new(),
+            comm_c: <PedersenHasher as Hasher>::Domain::random(rng),
+            comm_q: <PedersenHasher as Hasher>::Domain::random(rng),
+            comm_r_last: <PedersenHasher as Hasher>::Domain::random(rng),
+        };
+
+        let bytes = proof.to_versioned_bytes().unwrap();
+        assert_eq!(&bytes[..4], PROOF_MAGIC);
+
+        let decoded = Proof::<PedersenHasher, Sha256Hasher>::from_versioned_bytes(&bytes).unwrap();
+        assert_eq!(decoded.comm_c, proof.comm_c);
+        assert_eq!(decoded.comm_q, proof.comm_q);
+        assert_eq!(decoded.comm_r_last, proof.comm_r_last);
+        assert!(decoded.window_proofs.is_empty());
+        assert!(decoded.wrapper_proofs.is_empty());
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(Proof::<PedersenHasher, Sha256Hasher>::from_versioned_bytes(&wrong_magic).is_err());
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4..6].copy_from_slice(&(PROOF_FORMAT_VERSION + 1).to_le_bytes());
+        assert!(
+            Proof::<PedersenHasher, Sha256Hasher>::from_versioned_bytes(&wrong_version).is_err()
+        );
+
+        // The hasher names are part of the header.
+        assert!(Proof::<Sha256Hasher, Sha256Hasher>::from_versioned_bytes(&bytes).is_err());
+
+        assert!(Proof::<PedersenHasher, Sha256Hasher>::from_versioned_bytes(&bytes[..3]).is_err());
+    }
+
+    #[test]
+    fn dump_load_encodings_roundtrip() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::new(
+            cache_dir.path(),
+            CacheKey::CommDTree.to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+
+        let layers = 3;
+        let nodes = 16;
+        let layer_size = nodes * NODE_SIZE;
+
+        let mut encodings = Vec::new();
+        let mut configs = Vec::new();
+        for layer in 0..layers {
+            let encoding = (0..nodes)
+                .flat_map(|_| <PedersenHasher as Hasher>::Domain::random(rng).into_bytes())
+                .collect::<Vec<u8>>();
+
+            let layer_config =
+                StoreConfig::from_config(&config, CacheKey::label_layer(layer), Some(layer_size));
+            let mut store: DiskStore<<PedersenHasher as Hasher>::Domain> =
+                DiskStore::new_with_config(layer_size, layer_config.clone()).unwrap();
+            store.copy_from_slice(&encoding, 0).unwrap();
+
+            encodings.push(encoding);
+            configs.push(layer_config);
+        }
+
+        let t_aux = TemporaryAux::<PedersenHasher, Sha256Hasher> {
+            labels: Labels::new(configs),
+            tree_d_config: config.clone(),
+            tree_r_last_config: config.clone(),
+            tree_c_config: config.clone(),
+            tree_q_config: config,
+            _g: PhantomData,
+        };
+
+        let mut dumped = Vec::new();
+        t_aux.dump_encodings(&mut dumped).unwrap();
+        assert_eq!(dumped.len(), layers * (8 + layer_size));
+
+        let loaded = load_encodings(&dumped[..]).unwrap();
+        assert_eq!(loaded, encodings);
+
+        assert!(load_encodings(&dumped[..dumped.len() - 1]).is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use generic_array::GenericArray;
+use merkletree::hash::Algorithm;
 use merkletree::merkle::FromIndexedParallelIterator;
 use merkletree::store::{DiskStore, StoreConfig};
 use paired::bls12_381::Fr;
@@ -15,14 +17,14 @@ use crate::error::Result;
 use crate::hasher::{Domain, Hasher};
 use crate::merkle::{MerkleProof, MerkleTree, Store};
 use crate::stacked::{
-    challenges::LayerChallenges,
+    challenges::{miss_probabilities, LayerChallenges},
     column::Column,
     graph::StackedBucketGraph,
     hash::hash3,
     params::{
-        get_node, CacheKey, Labels, LabelsCache, PersistentAux, Proof, PublicInputs, PublicParams,
-        ReplicaColumnProof, Tau, TemporaryAux, TemporaryAuxCache, TransformedLayers, Tree,
-        WindowProof, WrapperProof,
+        get_node, public_inputs_for, CacheKey, Labels, LabelsCache, PersistentAux, Proof,
+        PublicInputs, PublicParams, ReplicaColumnProof, Tau, TemporaryAux, TemporaryAuxCache,
+        TransformedLayers, Tree, WindowProof, WrapperProof,
     },
     EncodingProof, LabelingProof,
 };
@@ -40,18 +42,15 @@ fn get_drg_parents_columns<H: Hasher, G: Hasher>(
     x: usize,
     pub_params: &PublicParams<H>,
 ) -> Result<Vec<Column<H>>> {
-    let base_degree = graph.base_graph().degree();
+    let parents = graph.drg_parents(x);
 
-    let mut columns = Vec::with_capacity(base_degree);
-
-    let mut parents = vec![0; base_degree];
-    graph.base_parents(x, &mut parents);
+    let mut columns = Vec::with_capacity(parents.len());
 
     for parent in &parents {
         columns.push(t_aux.column(*parent, pub_params)?);
     }
 
-    debug_assert!(columns.len() == base_degree);
+    debug_assert!(columns.len() == graph.base_graph().degree());
 
     Ok(columns)
 }
@@ -62,15 +61,27 @@ fn get_exp_parents_columns<H: Hasher, G: Hasher>(
     x: usize,
     pub_params: &PublicParams<H>,
 ) -> Result<Vec<Column<H>>> {
-    let mut parents = vec![0; graph.expansion_degree()];
-    graph.expanded_parents(x, &mut parents);
-
-    parents
+    graph
+        .expansion_parents(x)
         .iter()
         .map(|parent| t_aux.column(*parent, pub_params))
         .collect()
 }
 
+/// Fraction of the nodes a dishonest prover is assumed to have replicated incorrectly, when
+/// estimating the soundness of a `StackedConfig`.
+pub const SOUNDNESS_SPACE_GAP: f64 = 0.2;
+
+/// Wall time spent building each of the trees during replication. Trees which were passed in
+/// instead of being built take no time.
+#[derive(Debug, Clone, Default)]
+pub struct TreeBuildTimings {
+    pub tree_d: Duration,
+    pub tree_q: Duration,
+    pub tree_c: Duration,
+    pub tree_r_last: Duration,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StackedConfig {
     pub window_challenges: LayerChallenges,
@@ -89,6 +100,35 @@ impl StackedConfig {
         // they are both the same
         self.window_challenges.layers()
     }
+
+    /// Estimate the soundness, in bits, of a single partition proof over a sector of
+    /// `sector_size` bytes.
+    ///
+    /// This is the negative log of the probability that none of the (distinct) challenges hits an
+    /// incorrectly replicated node, if a `SOUNDNESS_SPACE_GAP` fraction of the nodes is incorrect.
+    /// The window and wrapper challenges are checked independently, so the weaker of the two
+    /// determines the soundness.
+    pub fn soundness_bits(&self, sector_size: u64) -> f64 {
+        let bits = |challenges: &LayerChallenges| {
+            miss_probabilities(sector_size)
+                .take(challenges.challenges_count_all())
+                .map(|miss_probability| {
+                    if miss_probability <= 0.0 {
+                        std::f64::INFINITY
+                    } else {
+                        -miss_probability.log2()
+                    }
+                })
+                .sum::<f64>()
+        };
+
+        bits(&self.window_challenges).min(bits(&self.wrapper_challenges))
+    }
+
+    /// Whether the estimated soundness of this config reaches `target_bits`.
+    pub fn meets_soundness(&self, sector_size: u64, target_bits: f64) -> bool {
+        self.soundness_bits(sector_size) >= target_bits
+    }
 }
 
 impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
@@ -373,6 +413,11 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
         let window_graph = &pub_params.window_graph;
         let wrapper_graph = &pub_params.wrapper_graph;
 
+        // The roots are shared by all challenges of this partition, so extract them only once.
+        let comm_d = match pub_inputs.tau {
+            Some(ref tau) => &tau.comm_d,
+            None => return Ok(false),
+        };
         let comm_c = &proof.comm_c;
         let comm_q = &proof.comm_q;
         let comm_r_last = &proof.comm_r_last;
@@ -395,19 +440,6 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
             Some(k),
         );
 
-        for window_proof in &proof.window_proofs {
-            // make sure all proofs have the same comm_c
-            if window_proof.comm_c() != comm_c {
-                return Ok(false);
-            }
-        }
-        for wrapper_proof in &proof.wrapper_proofs {
-            // make sure all proofs have the same comm_r_last
-            if wrapper_proof.comm_r_last() != comm_r_last {
-                return Ok(false);
-            }
-        }
-
         let window_valid = proof
             .window_proofs
             .par_iter()
@@ -417,7 +449,14 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
 
                 // Validate for this challenge
                 let window_challenge = window_challenges[i];
-                proof.verify(pub_params, pub_inputs, window_challenge, comm_q, comm_c)
+                proof.verify_with_roots(
+                    pub_params,
+                    &pub_inputs.replica_id,
+                    window_challenge,
+                    comm_d,
+                    comm_q,
+                    comm_c,
+                )
             });
         if !window_valid {
             return Ok(false);
@@ -432,7 +471,13 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
 
                 // Validate for this challenge
                 let wrapper_challenge = wrapper_challenges[i];
-                proof.verify::<G>(pub_inputs, wrapper_challenge, wrapper_graph, comm_q)
+                proof.verify_with_roots(
+                    &pub_inputs.replica_id,
+                    wrapper_challenge,
+                    wrapper_graph,
+                    comm_q,
+                    comm_r_last,
+                )
             });
         Ok(wrapper_valid)
     }
@@ -513,6 +558,56 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
         Ok(decoded)
     }
 
+    /// Verify that `replica` decodes to data whose tree root is `expected_comm_d`.
+    ///
+    /// The replica is decoded one window at a time and the decoded nodes are folded into the
+    /// data tree incrementally, so at most a single window of decoded data is held in memory.
+    pub fn verify_extraction(
+        pp: &PublicParams<H>,
+        replica_id: &<H as Hasher>::Domain,
+        replica: &[u8],
+        expected_comm_d: &G::Domain,
+    ) -> Result<bool> {
+        ensure!(
+            replica.len() % pp.window_size_bytes() == 0,
+            "invalid replica size"
+        );
+        let nodes = replica.len() / NODE_SIZE;
+        ensure!(
+            nodes.is_power_of_two(),
+            "{} nodes do not form a complete tree",
+            nodes
+        );
+
+        // Roots of the completed subtrees, together with their height.
+        let mut stack: Vec<(usize, G::Domain)> = Vec::new();
+        let mut hasher = G::Function::default();
+
+        for (window_index, chunk) in replica.chunks(pp.window_size_bytes()).enumerate() {
+            let mut decoded_chunk = chunk.to_vec();
+            Self::extract_single_window(pp, replica_id, &mut decoded_chunk, window_index);
+
+            for node in decoded_chunk.chunks(NODE_SIZE) {
+                let mut current = G::Domain::try_from_bytes(node)?;
+                let mut height = 0;
+
+                while let Some((top_height, top)) = stack.last() {
+                    if *top_height != height {
+                        break;
+                    }
+                    hasher.reset();
+                    current = hasher.node(*top, current, height);
+                    height += 1;
+                    stack.pop();
+                }
+
+                stack.push((height, current));
+            }
+        }
+
+        Ok(&stack[0].1 == expected_comm_d)
+    }
+
     pub(crate) fn extract_single_window(
         pub_params: &PublicParams<H>,
         replica_id: &<H as Hasher>::Domain,
@@ -772,7 +867,67 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
         data_tree: Option<Tree<G>>,
         config: Option<StoreConfig>,
     ) -> Result<TransformedLayers<H, G>> {
+        let (transformed, _timings) = Self::transform_and_replicate_layers_timed(
+            pub_params, replica_id, data, data_tree, config,
+        )?;
+
+        Ok(transformed)
+    }
+
+    /// Replicate like `PoRep::replicate`, additionally returning how long building each of the
+    /// trees took.
+    pub fn replicate_with_timings(
+        pub_params: &PublicParams<H>,
+        replica_id: &<H as Hasher>::Domain,
+        data: &mut [u8],
+        data_tree: Option<Tree<G>>,
+        config: Option<StoreConfig>,
+    ) -> Result<(
+        Tau<<H as Hasher>::Domain, <G as Hasher>::Domain>,
+        (PersistentAux<H::Domain>, TemporaryAux<H, G>),
+        TreeBuildTimings,
+    )> {
+        let ((tau, p_aux, t_aux), timings) = Self::transform_and_replicate_layers_timed(
+            pub_params, replica_id, data, data_tree, config,
+        )?;
+
+        Ok((tau, (p_aux, t_aux), timings))
+    }
+
+    /// Replicate `data` for the given `replica_id`, returning the public inputs for challenge
+    /// `seed` instead of the bare `Tau`.
+    ///
+    /// No randomness is drawn, so identical public params, inputs and data always result in the
+    /// same replica and public inputs.
+    pub fn replicate_seeded(
+        pub_params: &PublicParams<H>,
+        replica_id: &<H as Hasher>::Domain,
+        seed: [u8; 32],
+        data: &mut [u8],
+        config: Option<StoreConfig>,
+    ) -> Result<(
+        PublicInputs<<H as Hasher>::Domain, <G as Hasher>::Domain>,
+        (PersistentAux<H::Domain>, TemporaryAux<H, G>),
+    )> {
+        let (tau, p_aux, t_aux) =
+            Self::transform_and_replicate_layers(pub_params, replica_id, data, None, config)?;
+
+        Ok((
+            public_inputs_for::<H, G>(*replica_id, tau, seed, 0),
+            (p_aux, t_aux),
+        ))
+    }
+
+    fn transform_and_replicate_layers_timed(
+        pub_params: &PublicParams<H>,
+        replica_id: &<H as Hasher>::Domain,
+        data: &mut [u8],
+        data_tree: Option<Tree<G>>,
+        config: Option<StoreConfig>,
+    ) -> Result<(TransformedLayers<H, G>, TreeBuildTimings)> {
         trace!("transform_and_replicate_layers");
+        let mut timings = TreeBuildTimings::default();
+
         let window_graph = &pub_params.window_graph;
         let wrapper_graph = &pub_params.wrapper_graph;
 
@@ -806,7 +961,11 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
             }
             None => {
                 trace!("building merkle tree for the original data");
-                Self::build_tree::<G>(&data, Some(tree_d_config.clone()))?
+                let start = Instant::now();
+                let tree_d = Self::build_tree::<G>(&data, Some(tree_d_config.clone()))?;
+                timings.tree_d = start.elapsed();
+
+                tree_d
             }
         };
 
@@ -823,9 +982,12 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
         let column_hashes = Self::build_column_hashes(pub_params, &labels)?;
 
         info!("building tree_q");
+        let start = Instant::now();
         let tree_q: Tree<H> = Self::build_tree::<H>(&data, Some(tree_q_config.clone()))?;
+        timings.tree_q = start.elapsed();
 
         info!("building tree_r_last");
+        let start = Instant::now();
         let tree_r_last: Tree<H> = MerkleTree::from_par_iter_with_config(
             (0..wrapper_nodes_count).into_par_iter().map(|node| {
                 // 1 Wrapping Layer
@@ -854,8 +1016,10 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
             }),
             tree_r_last_config.clone(),
         )?;
+        timings.tree_r_last = start.elapsed();
 
         info!("building tree_c");
+        let start = Instant::now();
         let tree_c: Tree<H> = {
             let column_hashes_flat = unsafe {
                 // Column_hashes is of type Vec<[u8; 32]>, so this is safe to do.
@@ -867,6 +1031,7 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
             };
             Self::build_tree::<H>(column_hashes_flat, Some(tree_c_config.clone()))?
         };
+        timings.tree_c = start.elapsed();
 
         // comm_r = H(comm_c || comm_q || comm_r_last)
         let comm_r: H::Domain =
@@ -880,7 +1045,7 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
         tree_c_config.size = Some(tree_c.len());
         tree_q_config.size = Some(tree_q.len());
 
-        Ok((
+        let transformed = (
             Tau {
                 comm_d: tree_d.root(),
                 comm_r,
@@ -898,7 +1063,9 @@ impl<'a, H: 'static + Hasher, G: 'static + Hasher> StackedDrg<'a, H, G> {
                 tree_q_config,
                 _g: PhantomData,
             },
-        ))
+        );
+
+        Ok((transformed, timings))
     }
 }
 
@@ -960,7 +1127,9 @@ mod tests {
     use crate::hasher::{Blake2sHasher, PedersenHasher, Sha256Hasher};
     use crate::porep::PoRep;
     use crate::proof::ProofScheme;
-    use crate::stacked::{PrivateInputs, SetupParams, EXP_DEGREE};
+    use crate::stacked::{
+        comm_c_from_columns, proof_derived_comm_r, PrivateInputs, SetupParams, EXP_DEGREE,
+    };
 
     const DEFAULT_STACKED_LAYERS: usize = 4;
 
@@ -973,6 +1142,310 @@ mod tests {
         assert_eq!(expected as usize, calculated_count);
     }
 
+    /// Replicates random data of 256 nodes in windows of 128 nodes with `layers` layers, returning
+    /// the public params, the opened aux and the cache directory holding it.
+    fn replicated(
+        layers: usize,
+    ) -> (
+        PublicParams<PedersenHasher>,
+        TemporaryAuxCache<PedersenHasher, Blake2sHasher>,
+        tempfile::TempDir,
+    ) {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let nodes = 8 * 32;
+
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        let mut replica: Vec<u8> = (0..nodes)
+            .flat_map(|_| <PedersenHasher as Hasher>::Domain::random(rng).into_bytes())
+            .collect();
+
+        let sp = SetupParams {
+            nodes,
+            degree: BASE_DEGREE,
+            expansion_degree: EXP_DEGREE,
+            seed: new_seed(),
+            config: StackedConfig::new(layers, 5, 8),
+            window_size_nodes: nodes / 2,
+        };
+        let pp = StackedDrg::<PedersenHasher, Blake2sHasher>::setup(&sp).unwrap();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::new(
+            cache_dir.path(),
+            CacheKey::CommDTree.to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+
+        let (_tau, (_p_aux, t_aux)) = StackedDrg::<PedersenHasher, Blake2sHasher>::replicate(
+            &pp,
+            &replica_id,
+            &mut replica,
+            None,
+            Some(config),
+        )
+        .unwrap();
+        let t_aux = TemporaryAuxCache::new(&t_aux).unwrap();
+
+        (pp, t_aux, cache_dir)
+    }
+
+    #[test]
+    fn column_row_indices() {
+        let layers = 5;
+        let (pp, t_aux, _cache_dir) = replicated(layers);
+
+        for node in &[0, 7, pp.window_size_nodes() - 1] {
+            let column = t_aux.column(*node as u32, &pp).unwrap();
+            let indices = column.row_indices();
+
+            // All windows, each with all but the last layer.
+            let expected: Vec<_> = (0..pp.num_windows())
+                .flat_map(|w| (1..layers).map(move |l| (w, l)))
+                .collect();
+            assert_eq!(indices, expected);
+
+            for (row, (window_index, layer)) in column.rows().iter().zip(indices) {
+                let label = t_aux
+                    .labels
+                    .labels_for_layer(layer)
+                    .read_at(window_index * pp.window_size_nodes() + node)
+                    .unwrap();
+                assert_eq!(row, &label);
+                assert_eq!(column.get_node_at_layer(window_index, layer), &label);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_columns() {
+        let (pp, t_aux, _cache_dir) = replicated(4);
+
+        // Unordered, with duplicates.
+        let indices: Vec<u32> = (0..pp.window_size_nodes() as u32)
+            .rev()
+            .chain(vec![3, 3, 0])
+            .collect();
+
+        let expected = indices
+            .iter()
+            .map(|index| t_aux.column(*index, &pp))
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(t_aux.columns(&indices, &pp).unwrap(), expected);
+        assert!(t_aux.columns(&[], &pp).unwrap().is_empty());
+    }
+
+    #[test]
+    fn replica_column_parents() {
+        let (pp, t_aux, _cache_dir) = replicated(5);
+
+        let graph = &pp.window_graph;
+        for challenge in &[1, 7, pp.window_size_nodes() - 1] {
+            let drg_parents = graph.drg_parents(*challenge);
+            let expansion_parents = graph.expansion_parents(*challenge);
+            assert_eq!(drg_parents.len(), BASE_DEGREE);
+            assert_eq!(expansion_parents.len(), EXP_DEGREE);
+
+            let proof = StackedDrg::<PedersenHasher, Blake2sHasher>::prove_replica_column(
+                &pp, *challenge, &t_aux,
+            )
+            .unwrap();
+
+            let proved_drg_parents: Vec<u32> = proof
+                .drg_parents
+                .iter()
+                .map(|column_proof| column_proof.column.index())
+                .collect();
+            let proved_expansion_parents: Vec<u32> = proof
+                .exp_parents
+                .iter()
+                .map(|column_proof| column_proof.column.index())
+                .collect();
+            assert_eq!(proved_drg_parents, drg_parents);
+            assert_eq!(proved_expansion_parents, expansion_parents);
+        }
+    }
+
+    #[test]
+    fn comm_c_from_column_hashes() {
+        let (pp, t_aux, _cache_dir) = replicated(DEFAULT_STACKED_LAYERS);
+
+        let column_hashes =
+            StackedDrg::<PedersenHasher, Blake2sHasher>::build_column_hashes(&pp, &t_aux.labels)
+                .unwrap()
+                .iter()
+                .map(|hash| <PedersenHasher as Hasher>::Domain::try_from_bytes(hash).unwrap())
+                .collect::<Vec<_>>();
+
+        let comm_c = comm_c_from_columns::<PedersenHasher>(&column_hashes).unwrap();
+        assert_eq!(comm_c, t_aux.tree_c.root());
+
+        assert!(comm_c_from_columns::<PedersenHasher>(&column_hashes[1..]).is_err());
+        assert!(comm_c_from_columns::<PedersenHasher>(&[]).is_err());
+    }
+
+    #[test]
+    fn replicate_seeded_is_deterministic() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let nodes = 8 * 32;
+
+        let replica_id = <PedersenHasher as Hasher>::Domain::random(rng);
+        let seed: [u8; 32] = rng.gen();
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| <PedersenHasher as Hasher>::Domain::random(rng).into_bytes())
+            .collect();
+
+        let sp = SetupParams {
+            nodes,
+            degree: BASE_DEGREE,
+            expansion_degree: EXP_DEGREE,
+            seed: new_seed(),
+            config: StackedConfig::new(DEFAULT_STACKED_LAYERS, 5, 8),
+            window_size_nodes: nodes / 2,
+        };
+
+        let replicate = || {
+            let pp = StackedDrg::<PedersenHasher, Blake2sHasher>::setup(&sp).unwrap();
+            let cache_dir = tempfile::tempdir().unwrap();
+            let config = StoreConfig::new(
+                cache_dir.path(),
+                CacheKey::CommDTree.to_string(),
+                DEFAULT_CACHED_ABOVE_BASE_LAYER,
+            );
+
+            let mut replica = data.clone();
+            let (pub_inputs, (p_aux, _t_aux)) =
+                StackedDrg::<PedersenHasher, Blake2sHasher>::replicate_seeded(
+                    &pp,
+                    &replica_id,
+                    seed,
+                    &mut replica,
+                    Some(config),
+                )
+                .unwrap();
+
+            let challenges = pub_inputs.all_challenges(
+                &pp.config.window_challenges,
+                pp.window_graph.size(),
+                Some(0),
+            );
+            (pub_inputs.tau.unwrap(), p_aux, replica, challenges)
+        };
+
+        let (tau_a, p_aux_a, replica_a, challenges_a) = replicate();
+        let (tau_b, p_aux_b, replica_b, challenges_b) = replicate();
+
+        assert_eq!(tau_a, tau_b);
+        assert_eq!(p_aux_a, p_aux_b);
+        assert_eq!(replica_a, replica_b);
+        assert_eq!(challenges_a, challenges_b);
+        assert_ne!(replica_a, data);
+    }
+
+    #[test]
+    fn test_soundness_bits() {
+        let sector_size = 1024 * 1024;
+
+        let mut previous = 0.0;
+        for count in &[1, 2, 10, 50, 200] {
+            let config = StackedConfig::new(DEFAULT_STACKED_LAYERS, *count, *count);
+            let bits = config.soundness_bits(sector_size);
+
+            assert!(
+                bits > previous,
+                "{} challenges: {} <= {}",
+                count,
+                bits,
+                previous
+            );
+            previous = bits;
+        }
+
+        // The weaker challenge set determines the soundness.
+        let unbalanced = StackedConfig::new(DEFAULT_STACKED_LAYERS, 200, 1);
+        assert_eq!(
+            unbalanced.soundness_bits(sector_size),
+            StackedConfig::new(DEFAULT_STACKED_LAYERS, 1, 1).soundness_bits(sector_size)
+        );
+
+        let config = StackedConfig::new(DEFAULT_STACKED_LAYERS, 200, 200);
+        let bits = config.soundness_bits(sector_size);
+        assert!(config.meets_soundness(sector_size, bits));
+        assert!(!config.meets_soundness(sector_size, bits + 1.0));
+    }
+
+    #[test]
+    fn verify_extraction_pedersen() {
+        test_verify_extraction::<PedersenHasher>();
+    }
+
+    #[test]
+    fn verify_extraction_blake2s() {
+        test_verify_extraction::<Blake2sHasher>();
+    }
+
+    fn test_verify_extraction<H: 'static + Hasher>() {
+        let rng = &mut XorShiftRng::from_seed(crate::TEST_SEED);
+        let replica_id: H::Domain = H::Domain::random(rng);
+        let nodes = 8 * 32;
+
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| {
+                let v: H::Domain = H::Domain::random(rng);
+                v.into_bytes()
+            })
+            .collect();
+        let config = StackedConfig::new(DEFAULT_STACKED_LAYERS, 5, 8);
+
+        let mut replica = data.clone();
+
+        let sp = SetupParams {
+            nodes,
+            degree: BASE_DEGREE,
+            expansion_degree: EXP_DEGREE,
+            seed: new_seed(),
+            config: config.clone(),
+            window_size_nodes: nodes / 2,
+        };
+
+        let pp = StackedDrg::<H, Blake2sHasher>::setup(&sp).expect("setup failed");
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = StoreConfig::new(
+            cache_dir.path(),
+            CacheKey::CommDTree.to_string(),
+            DEFAULT_CACHED_ABOVE_BASE_LAYER,
+        );
+
+        let (tau, _) = StackedDrg::<H, Blake2sHasher>::replicate(
+            &pp,
+            &replica_id,
+            replica.as_mut_slice(),
+            None,
+            Some(config),
+        )
+        .expect("replication failed");
+
+        assert!(StackedDrg::<H, Blake2sHasher>::verify_extraction(
+            &pp,
+            &replica_id,
+            &replica,
+            &tau.comm_d,
+        )
+        .expect("failed to verify extraction"));
+
+        // Corrupt a single node of the replica.
+        replica[3 * NODE_SIZE] ^= 1;
+
+        assert!(!StackedDrg::<H, Blake2sHasher>::verify_extraction(
+            &pp,
+            &replica_id,
+            &replica,
+            &tau.comm_d,
+        )
+        .expect("failed to verify extraction"));
+    }
+
     #[test]
     fn extract_all_pedersen() {
         test_extract_all::<PedersenHasher>();
@@ -1234,6 +1707,147 @@ mod tests {
         .expect("failed to verify partition proofs");
 
         assert!(proofs_are_valid);
+
+        let window_challenges = pub_inputs.all_challenges(
+            &pp.config.window_challenges,
+            pp.window_graph.size(),
+            Some(0),
+        );
+        for (challenge, window_proof) in window_challenges
+            .iter()
+            .zip(all_partition_proofs[0].window_proofs.iter())
+        {
+            let opened = pp
+                .challenge_openings(*challenge)
+                .into_iter()
+                .map(|(layer, node)| {
+                    priv_inputs
+                        .t_aux
+                        .domain_node_at_layer(layer, node as u32)
+                        .unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            let column_proof = &window_proof.replica_column_proof;
+            let proven = std::iter::once(&column_proof.c_x)
+                .chain(column_proof.drg_parents.iter())
+                .chain(column_proof.exp_parents.iter())
+                .flat_map(|proof| proof.column.rows().iter().cloned())
+                .collect::<Vec<_>>();
+
+            assert_eq!(opened, proven);
+        }
+
+        let comm_r = pub_inputs.tau.as_ref().unwrap().comm_r;
+        for proof in all_partition_proofs {
+            assert_eq!(proof_derived_comm_r(proof).unwrap(), comm_r);
+        }
+
+        let mut tampered = all_partition_proofs[0].clone();
+        let comm_r_last = H::Domain::random(rng);
+        for wrapper_proof in tampered.wrapper_proofs.iter_mut() {
+            wrapper_proof.comm_r_last_proof.root = comm_r_last;
+        }
+        assert_ne!(proof_derived_comm_r(&tampered).unwrap(), comm_r);
+
+        // Verifying against the partition roots matches verifying against each proof's own roots.
+        let tau = pub_inputs.tau.as_ref().unwrap();
+        let partition_proof = &all_partition_proofs[0];
+        let wrapper_challenges = pub_inputs.all_challenges(
+            &pp.config.wrapper_challenges,
+            pp.wrapper_graph.size(),
+            Some(0),
+        );
+        assert!(window_challenges.len() > 1);
+        assert!(wrapper_challenges.len() > 1);
+
+        for (challenge, window_proof) in window_challenges
+            .iter()
+            .zip(partition_proof.window_proofs.iter())
+        {
+            let original = window_proof.verify(
+                &pp,
+                &pub_inputs,
+                *challenge,
+                &partition_proof.comm_q,
+                window_proof.comm_c(),
+            );
+            let hoisted = window_proof.verify_with_roots(
+                &pp,
+                &pub_inputs.replica_id,
+                *challenge,
+                &tau.comm_d,
+                &partition_proof.comm_q,
+                &partition_proof.comm_c,
+            );
+            assert!(original);
+            assert_eq!(original, hoisted);
+        }
+
+        for (challenge, wrapper_proof) in wrapper_challenges
+            .iter()
+            .zip(partition_proof.wrapper_proofs.iter())
+        {
+            let original = wrapper_proof.verify::<Blake2sHasher>(
+                &pub_inputs,
+                *challenge,
+                &pp.wrapper_graph,
+                &partition_proof.comm_q,
+            );
+            let hoisted = wrapper_proof.verify_with_roots(
+                &pub_inputs.replica_id,
+                *challenge,
+                &pp.wrapper_graph,
+                &partition_proof.comm_q,
+                &partition_proof.comm_r_last,
+            );
+            assert!(original);
+            assert_eq!(original, hoisted);
+        }
+
+        // Splitting a proof into its challenges and reassembling it is lossless.
+        let challenge_proofs = partition_proof.per_challenge();
+        assert_eq!(
+            challenge_proofs.len(),
+            window_challenges.len() + wrapper_challenges.len()
+        );
+        let reassembled = Proof::from_challenge_proofs(challenge_proofs).unwrap();
+        assert_eq!(
+            serde_json::to_string(&reassembled).unwrap(),
+            serde_json::to_string(partition_proof).unwrap()
+        );
+
+        // The tree_r_last openings verify on their own, but not for other challenges or roots.
+        for (challenge, wrapper_proof) in wrapper_challenges
+            .iter()
+            .zip(partition_proof.wrapper_proofs.iter())
+        {
+            assert!(
+                wrapper_proof.verify_comm_r_last_proof(&partition_proof.comm_r_last, *challenge)
+            );
+            assert!(!wrapper_proof.verify_comm_r_last_proof(&comm_r_last, *challenge));
+            assert!(!wrapper_proof.verify_comm_r_last_proof(
+                &partition_proof.comm_r_last,
+                (*challenge + 1) % pp.wrapper_graph.size()
+            ));
+        }
+        assert!(!tampered.wrapper_proofs[0]
+            .verify_comm_r_last_proof(&partition_proof.comm_r_last, wrapper_challenges[0]));
+
+        // A proof opening a different comm_r_last fails against the partition roots.
+        assert!(!tampered.wrapper_proofs[0].verify_with_roots(
+            &pub_inputs.replica_id,
+            wrapper_challenges[0],
+            &pp.wrapper_graph,
+            &tampered.comm_q,
+            &tampered.comm_r_last,
+        ));
+        assert!(!StackedDrg::<H, Blake2sHasher>::verify_all_partitions(
+            &pp,
+            &pub_inputs,
+            &[tampered],
+        )
+        .unwrap());
     }
 
     table_tests! {
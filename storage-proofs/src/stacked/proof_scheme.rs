@@ -77,6 +77,21 @@ impl<'a, 'c, H: 'static + Hasher, G: 'static + Hasher> ProofScheme<'a> for Stack
             .collect()
     }
 
+    fn verify(
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        proof: &Self::Proof,
+    ) -> Result<bool> {
+        let expected_comm_r = if let Some(ref tau) = pub_inputs.tau {
+            &tau.comm_r
+        } else {
+            return Ok(false);
+        };
+
+        let k = pub_inputs.k.unwrap_or(0);
+        Self::verify_single_partition(pub_params, pub_inputs, proof, expected_comm_r, k)
+    }
+
     fn verify_all_partitions(
         pub_params: &Self::PublicParams,
         pub_inputs: &Self::PublicInputs,